@@ -0,0 +1,77 @@
+// This file is part of a6-tools.
+// Copyright (C) 2017 Jeffrey Sharp
+//
+// a6-tools is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License,
+// or (at your option) any later version.
+//
+// a6-tools is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See
+// the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with a6-tools.  If not, see <http://www.gnu.org/licenses/>.
+
+// Benchmarks of the 7-bit codec, comparing the CPU-specific fast path with the
+// portable 64-bit group codec.  Without the `simd` feature, both run the
+// portable codec.  Run with: cargo bench --features simd
+
+extern crate a6;
+#[macro_use]
+extern crate criterion;
+
+use a6::codec::{decode_7bit_with, encode_7bit_with, encoded_len_7bit, decoded_len_7bit};
+use criterion::{BenchmarkId, Criterion, Throughput};
+
+// Input lengths: one update block, and a large dump
+const LENS: [usize; 2] = [272, 64 * 1024];
+
+// Codec variants: name and whether to use the fast path
+const VARIANTS: [(&str, bool); 2] = [("groups", false), ("simd", true)];
+
+fn encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode_7bit");
+
+    for &len in &LENS {
+        let src = (0..len).map(|x| (x * 7) as u8).collect::<Vec<_>>();
+        group.throughput(Throughput::Bytes(len as u64));
+
+        for &(name, simd) in &VARIANTS {
+            group.bench_with_input(BenchmarkId::new(name, len), &src, |b, src| {
+                let mut dst = Vec::with_capacity(encoded_len_7bit(len));
+                b.iter(|| {
+                    dst.clear();
+                    encode_7bit_with(src, &mut dst, simd);
+                })
+            });
+        }
+    }
+
+    group.finish();
+}
+
+fn decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode_7bit");
+
+    for &len in &LENS {
+        let src = (0..len).map(|x| (x * 7) as u8 & 0x7F).collect::<Vec<_>>();
+        group.throughput(Throughput::Bytes(len as u64));
+
+        for &(name, simd) in &VARIANTS {
+            group.bench_with_input(BenchmarkId::new(name, len), &src, |b, src| {
+                let mut dst = Vec::with_capacity(decoded_len_7bit(len));
+                b.iter(|| {
+                    dst.clear();
+                    decode_7bit_with(src, &mut dst, simd);
+                })
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, encode, decode);
+criterion_main!(benches);
@@ -0,0 +1,46 @@
+// block_from_bytes - Parses arbitrary data as an update block.
+//
+// This file is part of a6-tools.
+// Copyright (C) 2017 Jeffrey Sharp
+//
+// a6-tools is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License,
+// or (at your option) any later version.
+//
+// a6-tools is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See
+// the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with a6-tools.  If not, see <http://www.gnu.org/licenses/>.
+
+#![no_main]
+
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate a6;
+
+use a6::a6::{Block, BlockDecodeError, extract_image};
+use a6::codec::encode_7bit;
+use a6::util::Handler;
+
+struct Continue;
+
+impl Handler<BlockDecodeError> for Continue {
+    fn on(&self, _: &BlockDecodeError) -> Result<(), ()> { Ok(()) }
+}
+
+fuzz_target!(|bytes: &[u8]| {
+    if let Ok(block) = Block::from_bytes(bytes, &Continue) {
+        let _ = block.header.check_len(&Continue);
+        let _ = block.header.check_block_index(&Continue);
+    }
+
+    // Decoding the same bytes as an OS update block must not panic
+    let mut msg = vec![0xF0, 0x00, 0x00, 0x0E, 0x1D, 0x30];
+    encode_7bit(bytes, &mut msg);
+    msg.push(0xF7);
+    let _ = extract_image(&mut &msg[..]);
+});
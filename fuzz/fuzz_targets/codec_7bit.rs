@@ -0,0 +1,47 @@
+// codec_7bit - Round-trips arbitrary data through the 7-bit codecs.
+//
+// This file is part of a6-tools.
+// Copyright (C) 2017 Jeffrey Sharp
+//
+// a6-tools is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License,
+// or (at your option) any later version.
+//
+// a6-tools is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See
+// the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with a6-tools.  If not, see <http://www.gnu.org/licenses/>.
+
+#![no_main]
+
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate a6;
+
+use a6::codec::*;
+
+fuzz_target!(|input: (Packing, &[u8])| {
+    let (packing, data) = input;
+
+    // Encode and decode must round-trip
+    let mut data7 = vec![];
+    packing.encode(data, &mut data7);
+    assert!(data7.iter().all(|&v| v < 0x80));
+
+    let mut data8 = vec![];
+    packing.decode(&data7, &mut data8);
+    assert_eq!(data8, data);
+
+    // Decoding garbage must not panic
+    let mut junk = vec![];
+    packing.decode(data, &mut junk);
+    assert_eq!(junk.len(), packing.decoded_len(data.len()));
+
+    // All implementations must agree
+    differential::check_7bit(data);
+    differential::check_decode_7bit(data);
+});
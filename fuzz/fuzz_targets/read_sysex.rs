@@ -0,0 +1,45 @@
+// read_sysex - Reads arbitrary data as a SysEx stream.
+//
+// This file is part of a6-tools.
+// Copyright (C) 2017 Jeffrey Sharp
+//
+// a6-tools is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License,
+// or (at your option) any later version.
+//
+// a6-tools is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See
+// the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with a6-tools.  If not, see <http://www.gnu.org/licenses/>.
+
+#![no_main]
+
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate a6;
+
+use a6::sysex::*;
+
+fuzz_target!(|input: (Option<u16>, bool, bool, &[u8])| {
+    let (cap, keep_incomplete, skip_duplicates, bytes) = input;
+
+    let mut options = ReadOptions::new(cap.map(|c| c as usize));
+    options.raw             = true;
+    options.keep_incomplete = keep_incomplete;
+    options.skip_duplicates = skip_duplicates;
+
+    // Reading must consume the whole stream without error
+    let complete = read_sysex_with(&mut &bytes[..], &options, &mut |event: SysExEvent| {
+        let range = event.range();
+        assert!(range.end <= bytes.len());
+        true
+    }).unwrap();
+    assert!(complete);
+
+    // Streaming and in-memory parsers must agree
+    differential::check_parser(bytes);
+});
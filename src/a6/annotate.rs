@@ -0,0 +1,328 @@
+// This file is part of a6-tools.
+// Copyright (C) 2017 Jeffrey Sharp
+//
+// a6-tools is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License,
+// or (at your option) any later version.
+//
+// a6-tools is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See
+// the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with a6-tools.  If not, see <http://www.gnu.org/licenses/>.
+
+
+use std::borrow::Cow;
+use std::io::{self, Write};
+
+use a6::{recognize_sysex_detailed, Image, Opcode, OpcodeRegistry, DATA_POS};
+use a6::error::MessageError;
+use a6::block::{Block, BLOCK_DATA_LEN, BLOCK_HEAD_LEN, BLOCK_7BIT_LEN};
+use codec::decode_7bit;
+use sysex::{scan_sysex, SysExEvent};
+use util::Handler;
+
+/// A labelled range of bytes in a file, for export to hex editors.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Annotation {
+    /// Offset of the first byte of the range.
+    pub offset: usize,
+
+    /// Length of the range in bytes.
+    pub len: usize,
+
+    /// Name of the field.
+    pub label: Cow<'static, str>,
+
+    /// Interpretation of the field's bytes.
+    pub value: String,
+}
+
+impl Annotation {
+    fn new<L>(offset: usize, len: usize, label: L, value: String) -> Self
+        where L: Into<Cow<'static, str>>
+    {
+        Self { offset, len, label: label.into(), value }
+    }
+}
+
+/// Annotates the messages in the given update file `bytes`: the framing, ID,
+/// and opcode of each A6 message, the payload of each update block with its
+/// decoded header, and any bytes outside of messages.
+///
+/// Fields within a message are annotated only if no real-time bytes are
+/// interleaved within it, since those would shift the fields.
+pub fn annotate_update(bytes: &[u8]) -> Vec<Annotation> {
+    annotate_update_with(bytes, &OpcodeRegistry::new())
+}
+
+/// Annotates the messages in the given update file `bytes` as
+/// `annotate_update` does, also recognizing the custom opcodes registered in
+/// `registry`.
+pub fn annotate_update_with(bytes: &[u8], registry: &OpcodeRegistry) -> Vec<Annotation> {
+    let mut notes = vec![];
+
+    for event in scan_sysex(bytes) {
+        match event {
+            SysExEvent::Message { pos, len, msg, .. } => {
+                let recognized = recognize(&msg, registry);
+
+                let value = match recognized {
+                    Some((_, name, _)) => name.to_string(),
+                    None               => "Not an A6 message".to_string(),
+                };
+                notes.push(Annotation::new(pos, len, "SysEx message", value));
+
+                // Skip fields if interleaved real-time bytes shift them
+                let (opcode, name, payload) = match recognized {
+                    Some(r) if msg.len() + 2 == len => r,
+                    _                               => continue,
+                };
+
+                notes.push(Annotation::new(pos + 1, 4, "Manufacturer ID", "00 00 0E 1D".to_string()));
+                notes.push(Annotation::new(pos + 5, 1, "Opcode", format!("{:02X} ({})", opcode, name)));
+
+                if payload.len() == 0 {
+                    continue
+                }
+
+                let value = match Opcode::from_u8(opcode) {
+                    Some(Opcode::OsBlock) | Some(Opcode::BootBlock) => describe_block(payload),
+                    _ => format!("{} byte(s)", payload.len()),
+                };
+                notes.push(Annotation::new(pos + 6, payload.len(), "Payload (7-bit)", value));
+            },
+            SysExEvent::Error { pos, len, err } => {
+                notes.push(Annotation::new(pos, len, "Not a message", err.to_string()));
+            },
+            SysExEvent::Incomplete { pos, len, .. } => {
+                notes.push(Annotation::new(pos, len, "Incomplete message", String::new()));
+            },
+            SysExEvent::RealTime { pos, byte } => {
+                notes.push(Annotation::new(pos, 1, "Real-time byte", format!("{:02X}", byte)));
+            },
+        }
+    }
+
+    notes
+}
+
+// Recognizes the given SysEx message `msg` (without start/end bytes) as an A6
+// message with a known opcode or a custom opcode registered in `registry`.
+// Returns the opcode value, the opcode name, and the payload.
+fn recognize<'a>(msg: &'a [u8], registry: &OpcodeRegistry)
+    -> Option<(u8, &'static str, &'a [u8])>
+{
+    match recognize_sysex_detailed(msg) {
+        Ok((opcode, payload)) => Some((opcode as u8, opcode.name(), payload)),
+        Err(MessageError::UnknownOpcode { opcode }) => {
+            registry.get(opcode).map(|op| (opcode, op.name, &msg[DATA_POS..]))
+        },
+        Err(_) => None,
+    }
+}
+
+// Describes the decoded header of an update block payload.
+fn describe_block(payload: &[u8]) -> String {
+    if payload.len() != BLOCK_7BIT_LEN {
+        return format!("Invalid block: {} byte(s)", payload.len())
+    }
+
+    let mut bytes = Vec::with_capacity(BLOCK_HEAD_LEN + BLOCK_DATA_LEN);
+    decode_7bit(payload, &mut bytes);
+
+    match Block::from_bytes(&bytes, &Ignore) {
+        Ok(block) => {
+            let h = block.header;
+            format!(
+                "Block {} of {}, version {}, image length {}, checksum {:08X}",
+                h.block_index, h.block_count, h.version, h.length, h.checksum
+            )
+        },
+        Err(_) => "Invalid block".to_string(),
+    }
+}
+
+// A handler that continues past any condition.
+struct Ignore;
+
+impl<E> Handler<E> for Ignore {
+    fn on(&self, _: &E) -> Result<(), ()> { Ok(()) }
+}
+
+/// Annotates the given firmware `image`: the range of each block and each
+/// version string.
+pub fn annotate_image(image: &Image) -> Vec<Annotation> {
+    let len       = image.bytes().len();
+    let mut notes = (0..image.block_count() as usize)
+        .map(|i| {
+            let start = i * BLOCK_DATA_LEN;
+            let end   = len.min(start + BLOCK_DATA_LEN);
+            Annotation::new(start, end - start, format!("Block {}", i), String::new())
+        })
+        .collect::<Vec<_>>();
+
+    for (range, v) in image.version_spans() {
+        let value = format!("{}.{}.{}", v / 10000, v / 100 % 100, v % 100);
+        notes.push(Annotation::new(range.start, range.len(), "Version string", value));
+    }
+
+    notes.sort_by_key(|n| n.offset);
+    notes
+}
+
+/// Writes the given `notes` to `output` as CSV, with a header row of
+/// `offset,length,label,value`.  Offsets are hexadecimal.
+pub fn write_annotations_csv<W: Write>(notes: &[Annotation], output: &mut W) -> io::Result<()> {
+    writeln!(output, "offset,length,label,value")?;
+    for n in notes {
+        writeln!(output, "0x{:X},{},{},{}", n.offset, n.len, csv(&n.label), csv(&n.value))?;
+    }
+    Ok(())
+}
+
+/// Writes the given `notes` to `output` as a JSON array of objects with
+/// `offset`, `length`, `label`, and `value` members.
+pub fn write_annotations_json<W: Write>(notes: &[Annotation], output: &mut W) -> io::Result<()> {
+    write!(output, "[")?;
+    for (i, n) in notes.iter().enumerate() {
+        write!(
+            output, "{}\n  {{\"offset\": {}, \"length\": {}, \"label\": {}, \"value\": {}}}",
+            if i == 0 { "" } else { "," }, n.offset, n.len, json(&n.label), json(&n.value)
+        )?;
+    }
+    writeln!(output, "\n]")
+}
+
+// Quotes a CSV field if it contains special characters.
+fn csv(s: &str) -> Cow<str> {
+    match s.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        true  => format!("\"{}\"", s.replace('"', "\"\"")).into(),
+        false => s.into(),
+    }
+}
+
+// Quotes a JSON string.
+fn json(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"'  => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if c < ' ' => out.push_str(&format!("\\u{:04X}", c as u32)),
+            c    => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use a6::build_message;
+    use codec::encode_7bit;
+
+    #[test]
+    fn update() {
+        let mut block = vec![];
+        block.extend_from_slice(&20105u32.to_be_bytes());   // version
+        block.extend_from_slice(&0x1234u32.to_be_bytes());  // checksum
+        block.extend_from_slice(&300u32.to_be_bytes());     // length
+        block.extend_from_slice(&2u16.to_be_bytes());       // block count
+        block.extend_from_slice(&1u16.to_be_bytes());       // block index
+        block.resize(BLOCK_HEAD_LEN + BLOCK_DATA_LEN, 0);
+
+        let mut payload = vec![];
+        encode_7bit(&block, &mut payload);
+
+        let mut bytes = b"xy".to_vec();
+        bytes.extend(build_message(Opcode::OsBlock, &payload));
+
+        let notes = annotate_update(&bytes);
+
+        assert_eq!(notes.len(), 5);
+        assert_eq!(notes[0].offset, 0);
+        assert_eq!(notes[0].label,  "Not a message");
+        assert_eq!(notes[1], Annotation::new(2, 6 + BLOCK_7BIT_LEN + 1, "SysEx message", "OsBlock".to_string()));
+        assert_eq!(notes[2], Annotation::new(3, 4, "Manufacturer ID", "00 00 0E 1D".to_string()));
+        assert_eq!(notes[3], Annotation::new(7, 1, "Opcode", "30 (OsBlock)".to_string()));
+        assert_eq!(notes[4], Annotation::new(
+            8, BLOCK_7BIT_LEN, "Payload (7-bit)",
+            "Block 1 of 2, version 20105, image length 300, checksum 00001234".to_string()
+        ));
+    }
+
+    #[test]
+    fn update_custom_opcode() {
+        use a6::CustomOpcode;
+
+        let mut registry = OpcodeRegistry::new();
+        registry.register(CustomOpcode {
+            value: 0x10, name: "Probe", payload_len: None, check: None,
+        });
+
+        let mut bytes = registry.build_message(0x10, &[0x01, 0x02]);
+        bytes.extend(registry.build_message(0x10, &[]));
+
+        let notes = annotate_update_with(&bytes, &registry);
+
+        assert_eq!(notes, vec![
+            Annotation::new(0, 9, "SysEx message",   "Probe".to_string()),
+            Annotation::new(1, 4, "Manufacturer ID", "00 00 0E 1D".to_string()),
+            Annotation::new(5, 1, "Opcode",          "10 (Probe)".to_string()),
+            Annotation::new(6, 2, "Payload (7-bit)", "2 byte(s)".to_string()),
+            Annotation::new(9, 7, "SysEx message",   "Probe".to_string()),
+            Annotation::new(10, 4, "Manufacturer ID", "00 00 0E 1D".to_string()),
+            Annotation::new(14, 1, "Opcode",          "10 (Probe)".to_string()),
+        ]);
+        assert_eq!(annotate_update(&bytes)[0].value, "Not an A6 message");
+    }
+
+    #[test]
+    fn image() {
+        let mut bytes = vec![0; 300];
+        bytes[260..265].copy_from_slice(b"2.1.5");
+        let image = Image::new(bytes).unwrap();
+
+        let notes = annotate_image(&image);
+
+        assert_eq!(notes, vec![
+            Annotation::new(  0, 256, "Block 0",        String::new()),
+            Annotation::new(256,  44, "Block 1",        String::new()),
+            Annotation::new(260,   5, "Version string", "2.1.5".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn csv_output() {
+        let notes = vec![Annotation::new(0x10, 2, "Label", "a, \"b\"".to_string())];
+        let mut out = vec![];
+
+        write_annotations_csv(&notes, &mut out).unwrap();
+
+        assert_eq!(out, b"offset,length,label,value\n0x10,2,Label,\"a, \"\"b\"\"\"\n".to_vec());
+    }
+
+    #[test]
+    fn json_output() {
+        let notes = vec![
+            Annotation::new(0, 1, "A", "x\"y".to_string()),
+            Annotation::new(1, 2, "B", "\u{1}".to_string()),
+        ];
+        let mut out = vec![];
+
+        write_annotations_json(&notes, &mut out).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "[\n  {\"offset\": 0, \"length\": 1, \"label\": \"A\", \"value\": \"x\\\"y\"},\
+             \n  {\"offset\": 1, \"length\": 2, \"label\": \"B\", \"value\": \"\\u0001\"}\n]\n"
+        );
+    }
+}
@@ -0,0 +1,347 @@
+// This file is part of a6-tools.
+// Copyright (C) 2017 Jeffrey Sharp
+//
+// a6-tools is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License,
+// or (at your option) any later version.
+//
+// a6-tools is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See
+// the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with a6-tools.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Range;
+
+use a6::{recognize_sysex, Opcode};
+use a6::program::{ProgramDump, BANK_PROGRAMS};
+use sysex::{scan_sysex, SysExEvent};
+
+/// Summary statistics of a collection of program dumps.
+#[derive(Clone, PartialEq, Eq, Default, Debug)]
+pub struct BankStats {
+    /// Count of program dumps examined.
+    pub programs: usize,
+
+    /// Count of distinct slots used, by bank number.
+    pub slots_used: BTreeMap<u8, usize>,
+
+    /// Slots that occur in more than one dump, with their counts.
+    pub repeated_slots: Vec<((u8, u8), usize)>,
+
+    /// Groups of distinct slots whose programs have identical data.
+    pub identical: Vec<Vec<(u8, u8)>>,
+}
+
+impl BankStats {
+    /// Computes statistics of the given program `dumps`.
+    pub fn new<'a, I>(dumps: I) -> Self
+    where
+        I: IntoIterator<Item = &'a ProgramDump>,
+    {
+        let mut stats   = Self::default();
+        let mut slots   = BTreeMap::<(u8, u8), usize>::new();
+        let mut by_data = BTreeMap::<&[u8], Vec<(u8, u8)>>::new();
+
+        for dump in dumps {
+            stats.programs += 1;
+
+            let count = slots.entry(dump.slot()).or_insert(0);
+            *count += 1;
+            if *count > 1 { continue }
+
+            *stats.slots_used.entry(dump.bank).or_insert(0) += 1;
+            by_data.entry(&dump.data).or_insert_with(Vec::new).push(dump.slot());
+        }
+
+        stats.repeated_slots = slots.into_iter().filter(|&(_, n)| n > 1).collect();
+        stats.identical      = by_data.into_iter().map(|(_, s)| s).filter(|s| s.len() > 1).collect();
+        stats.identical.sort();
+        stats
+    }
+}
+
+impl fmt::Display for BankStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Programs: {}", self.programs)?;
+
+        for (bank, used) in &self.slots_used {
+            writeln!(f, "Bank {:2}: {:3} of {} slots used", bank, used, BANK_PROGRAMS)?;
+        }
+
+        for &((bank, number), count) in &self.repeated_slots {
+            writeln!(f, "Slot {}:{:03} occurs {} times", bank, number, count)?;
+        }
+
+        for group in &self.identical {
+            f.write_str("Identical:")?;
+            for (i, &(bank, number)) in group.iter().enumerate() {
+                let sep = if i == 0 { " " } else { ", " };
+                write!(f, "{}{}:{:03}", sep, bank, number)?;
+            }
+            f.write_str("\n")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Program and mix dumps found by `import_programs`.
+#[derive(Clone, PartialEq, Eq, Default, Debug)]
+pub struct ProgramImport {
+    /// Program dumps found, in input order.
+    pub programs: Vec<ProgramDump>,
+
+    /// Payloads of mix dumps found, as received, in input order.  Mix data is
+    /// not decoded; each payload begins with its bank and number.
+    pub mixes: Vec<Vec<u8>>,
+
+    /// Ranges of input positions that did not contain a program or mix dump,
+    /// in order.  Adjacent ranges are merged.
+    pub skipped: Vec<Range<usize>>,
+}
+
+/// Scans the given in-memory `bytes` for program and mix dumps, tolerating any
+/// other content, such as other messages, malformed messages, and wrapper
+/// bytes added by other tools.  Returns the dumps found and the ranges of
+/// input skipped.
+pub fn import_programs(bytes: &[u8]) -> ProgramImport {
+    let mut import = ProgramImport::default();
+
+    for event in scan_sysex(bytes) {
+        let range = event.range();
+
+        if let SysExEvent::Message { ref msg, .. } = event {
+            if let Some(dump) = ProgramDump::from_message(msg) {
+                import.programs.push(dump);
+                continue
+            }
+            if let Some((Opcode::Mix, payload)) = recognize_sysex(msg) {
+                if payload.len() >= 2 {
+                    import.mixes.push(payload.to_vec());
+                    continue
+                }
+            }
+        } else if let SysExEvent::RealTime { .. } = event {
+            // Part of the enclosing message
+            continue
+        }
+
+        match import.skipped.last_mut() {
+            Some(last) if last.end == range.start => last.end = range.end,
+            _                                     => import.skipped.push(range),
+        }
+    }
+
+    import
+}
+
+/// Data found by `split_all_data` in the response to an all-data request.
+#[derive(Clone, PartialEq, Eq, Default, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AllData {
+    /// Payload of the first global data dump, as received, if any.
+    pub global: Option<Vec<u8>>,
+
+    /// Input ranges of any global data dumps after the first, which are not
+    /// used.  A response to a single all-data request contains only one.
+    pub extra_globals: Vec<Range<usize>>,
+
+    /// Program dumps, by bank number, in input order.
+    pub programs: BTreeMap<u8, Vec<ProgramDump>>,
+
+    /// Payloads of mix dumps, as received, by bank number, in input order.
+    /// Mix data is not decoded; each payload begins with its bank and number.
+    pub mixes: BTreeMap<u8, Vec<Vec<u8>>>,
+
+    /// Count of messages that are not global, program, or mix dumps.
+    pub ignored: usize,
+}
+
+/// Demultiplexes the given in-memory `bytes`, the response to an all-data
+/// request (see `request_all`), into global data, program banks, and mix
+/// banks.  Calls `progress` after each event of the input, whether a message
+/// or not, with the count of bytes consumed and the total count of bytes.
+pub fn split_all_data<F>(bytes: &[u8], mut progress: F) -> AllData
+where
+    F: FnMut(usize, usize),
+{
+    let mut data = AllData::default();
+
+    for event in scan_sysex(bytes) {
+        let end = event.range().end;
+
+        let msg = match event {
+            SysExEvent::Message { ref msg, .. } => msg,
+            _                                   => {
+                progress(end, bytes.len());
+                continue
+            },
+        };
+
+        match recognize_sysex(msg) {
+            Some((Opcode::GlobalData, payload)) => match data.global {
+                Some(_) => data.extra_globals.push(event.range()),
+                None    => data.global = Some(payload.to_vec()),
+            },
+            Some((Opcode::Pgm, _)) => match ProgramDump::from_message(msg) {
+                Some(dump) => data.programs.entry(dump.bank).or_insert_with(Vec::new).push(dump),
+                None       => data.ignored += 1,
+            },
+            Some((Opcode::Mix, payload)) if payload.len() >= 2 => {
+                data.mixes.entry(payload[0]).or_insert_with(Vec::new).push(payload.to_vec());
+            },
+            _ => data.ignored += 1,
+        }
+
+        progress(end, bytes.len());
+    }
+
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use a6::program::PROGRAM_DATA_LEN;
+
+    fn dump(bank: u8, number: u8, fill: u8) -> ProgramDump {
+        ProgramDump { bank, number, data: vec![fill; PROGRAM_DATA_LEN].into_boxed_slice() }
+    }
+
+    #[test]
+    fn stats() {
+        let dumps = vec![
+            dump(0, 0, 1),
+            dump(0, 1, 2),
+            dump(0, 1, 2),  // repeated slot
+            dump(1, 5, 1),  // identical to 0:000
+            dump(1, 6, 3),
+        ];
+
+        let stats = BankStats::new(&dumps);
+
+        assert_eq!(stats.programs, 5);
+        assert_eq!(stats.slots_used.into_iter().collect::<Vec<_>>(), vec![(0, 2), (1, 2)]);
+        assert_eq!(stats.repeated_slots, vec![((0, 1), 2)]);
+        assert_eq!(stats.identical, vec![vec![(0, 0), (1, 5)]]);
+    }
+
+    #[test]
+    fn import() {
+        use sysex::encode_7bit;
+
+        let data = (0..PROGRAM_DATA_LEN).map(|x| x as u8).collect::<Vec<_>>();
+
+        let mut bytes = b"junk".to_vec();
+        for &(bank, number) in &[(2, 7), (0, 3)] {
+            bytes.extend_from_slice(&[0xF0, 0x00, 0x00, 0x0E, 0x1D, 0x00, bank, number]);
+            encode_7bit(&data, &mut bytes);
+            bytes.push(0xF7);
+            bytes.extend_from_slice(b"\xF0\x43\x10\xF7");
+        }
+        bytes.extend_from_slice(b"\xF0\x00\x00");
+
+        let import = import_programs(&bytes);
+        let len    = 9 + 2341;
+
+        assert_eq!(
+            import.programs.iter().map(|p| p.slot()).collect::<Vec<_>>(),
+            vec![(2, 7), (0, 3)]
+        );
+        assert_eq!(&import.programs[0].data[..], &data[..]);
+        assert_eq!(import.skipped, vec![
+            0..4,
+            4 + len..8 + len,
+            8 + len * 2..15 + len * 2,
+        ]);
+    }
+
+    #[test]
+    fn import_mixes() {
+        use a6::build_message;
+
+        let mut bytes = b"junk".to_vec();
+        bytes.extend(build_message(Opcode::Mix, &[1, 4, 0x33]));
+        bytes.extend(build_message(Opcode::Mix, &[1]));  // too short for a slot
+        bytes.extend(build_message(Opcode::Mix, &[2, 5, 0x44]));
+
+        let import = import_programs(&bytes);
+
+        assert_eq!(import.programs, vec![]);
+        assert_eq!(import.mixes, vec![vec![1, 4, 0x33], vec![2, 5, 0x44]]);
+        assert_eq!(import.skipped, vec![0..4, 14..22]);
+    }
+
+    #[test]
+    fn split_all() {
+        use a6::build_message;
+        use sysex::encode_7bit;
+
+        let mut program = vec![3, 9];
+        encode_7bit(&[0; PROGRAM_DATA_LEN], &mut program);
+
+        let mut bytes = vec![];
+        bytes.extend(build_message(Opcode::GlobalData, &[0x11, 0x22]));
+        bytes.extend(build_message(Opcode::Pgm,        &program));
+        bytes.extend(build_message(Opcode::Mix,        &[1, 4, 0x33]));
+        bytes.extend(build_message(Opcode::Mix,        &[1, 5, 0x44]));
+        bytes.extend(build_message(Opcode::Mode,       &[0x00]));
+
+        let mut reports = vec![];
+        let data = split_all_data(&bytes, |pos, total| reports.push((pos, total)));
+
+        assert_eq!(data.global, Some(vec![0x11, 0x22]));
+        assert_eq!(
+            data.programs.iter().map(|(&b, p)| (b, p[0].slot())).collect::<Vec<_>>(),
+            vec![(3, (3, 9))]
+        );
+        assert_eq!(
+            data.mixes.into_iter().collect::<Vec<_>>(),
+            vec![(1, vec![vec![1, 4, 0x33], vec![1, 5, 0x44]])]
+        );
+        assert_eq!(data.ignored, 1);
+        assert_eq!(reports.len(), 5);
+        assert_eq!(reports.last(), Some(&(bytes.len(), bytes.len())));
+    }
+
+    #[test]
+    fn split_all_extra_global() {
+        use a6::build_message;
+
+        let mut bytes = b"junk".to_vec();
+        bytes.extend(build_message(Opcode::GlobalData, &[0x11]));
+        bytes.extend(build_message(Opcode::GlobalData, &[0x22]));
+        bytes.extend_from_slice(b"\xF0\x00");
+
+        let mut reports = vec![];
+        let data = split_all_data(&bytes, |pos, total| reports.push((pos, total)));
+
+        assert_eq!(data.global, Some(vec![0x11]));
+        assert_eq!(data.extra_globals, vec![12..20]);
+        assert_eq!(data.ignored, 0);
+        assert_eq!(
+            reports.iter().map(|&(pos, _)| pos).collect::<Vec<_>>(),
+            vec![4, 12, 20, 22]
+        );
+    }
+
+    #[test]
+    fn display() {
+        let dumps = vec![dump(0, 0, 1), dump(1, 5, 1)];
+
+        let text = BankStats::new(&dumps).to_string();
+
+        assert_eq!(text, "\
+            Programs: 2\n\
+            Bank  0:   1 of 128 slots used\n\
+            Bank  1:   1 of 128 slots used\n\
+            Identical: 0:000, 1:005\n\
+        ");
+    }
+}
@@ -14,13 +14,20 @@
 // You should have received a copy of the GNU General Public License
 // along with a6-tools.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::fmt;
 use std::ops::Range;
 
+use a6::Opcode;
 use a6::error::BlockDecodeError;
 use a6::error::BlockDecodeError::*;
-use io::*;
 use util::Handler;
 
+#[cfg(not(feature = "std"))]
+use heap::*;
+
+#[cfg(feature = "arbitrary")]
+use arbitrary::{Arbitrary, Unstructured};
+
 pub const BLOCK_HEAD_LEN:   usize =  16;  // Raw block header length (bytes)
 pub const BLOCK_DATA_LEN:   usize = 256;  // Raw block data length (bytes)
 pub const BLOCK_7BIT_LEN:   usize = 311;  // 7-bit-encoded block length (bytes)
@@ -32,7 +39,8 @@ pub const IMAGE_MAX_BLOCKS: u16 = (IMAGE_MAX_BYTES as usize / BLOCK_DATA_LEN) as
 const BLOCK_DIV_SHIFT:  usize = 8;
 
 /// Metadata describing a bootloader/OS update block.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct BlockHeader {
     /// Version of the firmware in the image.
     pub version: u32,
@@ -50,6 +58,46 @@ pub struct BlockHeader {
     pub block_index: u16,
 }
 
+/// Kind of update block, as given by the opcode of the message carrying it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum BlockKind {
+    /// OS update block (`Opcode::OsBlock`).
+    Os,
+
+    /// Bootloader update block (`Opcode::BootBlock`).
+    Boot,
+}
+
+impl BlockKind {
+    /// Gets the kind of update block carried by messages with the given
+    /// `opcode`, or `None` if such messages do not carry update blocks.
+    pub fn from_opcode(opcode: Opcode) -> Option<Self> {
+        match opcode {
+            Opcode::OsBlock   => Some(BlockKind::Os),
+            Opcode::BootBlock => Some(BlockKind::Boot),
+            _                 => None,
+        }
+    }
+
+    /// Gets the opcode of messages carrying this kind of update block.
+    pub fn opcode(self) -> Opcode {
+        match self {
+            BlockKind::Os   => Opcode::OsBlock,
+            BlockKind::Boot => Opcode::BootBlock,
+        }
+    }
+}
+
+impl fmt::Display for BlockKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            BlockKind::Os   => "OS",
+            BlockKind::Boot => "bootloader",
+        })
+    }
+}
+
 /// A portion of an OS/bootloader update image.
 #[derive(Clone, Copy, Debug)]
 pub struct Block<'a> {
@@ -93,29 +141,52 @@ impl<'a> Block<'a> {
 
         // Read block header, leaving `bytes` to contain just the data
         let header = BlockHeader {
-            version:     bytes.read_u32().unwrap(),
-            checksum:    bytes.read_u32().unwrap(),
-            length:      bytes.read_u32().unwrap(),
-            block_count: bytes.read_u16().unwrap(),
-            block_index: bytes.read_u16().unwrap(),
+            version:     read_u32(&mut bytes),
+            checksum:    read_u32(&mut bytes),
+            length:      read_u32(&mut bytes),
+            block_count: read_u16(&mut bytes),
+            block_index: read_u16(&mut bytes),
         };
 
         // Create block
         Ok(Self { header, data: bytes })
     }
+
+    /// Encodes the block as bytes, the inverse of `from_bytes`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(BLOCK_HEAD_LEN + self.data.len());
+        bytes.extend_from_slice(&self.header.to_bytes());
+        bytes.extend_from_slice(self.data);
+        bytes
+    }
 }
 
 impl BlockHeader {
+    /// Encodes the header as bytes, in the big-endian layout read by
+    /// `Block::from_bytes`.
+    pub fn to_bytes(&self) -> [u8; BLOCK_HEAD_LEN] {
+        let mut bytes = [0; BLOCK_HEAD_LEN];
+        bytes[ 0.. 4].copy_from_slice(&self.version    .to_be_bytes());
+        bytes[ 4.. 8].copy_from_slice(&self.checksum   .to_be_bytes());
+        bytes[ 8..12].copy_from_slice(&self.length     .to_be_bytes());
+        bytes[12..14].copy_from_slice(&self.block_count.to_be_bytes());
+        bytes[14..16].copy_from_slice(&self.block_index.to_be_bytes());
+        bytes
+    }
+
     /// Verifies that the header specifies a valid image length and block count.
-    pub fn check_len<H>(&self, handler: &H) -> Result<(), ()>
+    ///
+    /// Returns `Err(true)` if either is invalid and `handler` returns `Ok(())`
+    /// (continue), or `Err(false)` if `handler` returns `Err(())` (stop).
+    pub fn check_len<H>(&self, handler: &H) -> Result<(), bool>
         where H: Handler<BlockDecodeError>
     {
         // Validate claimed image length
         if self.length > IMAGE_MAX_BYTES {
-            handler.on(&InvalidImageLength {
-                actual: self.length,
-            });
-            return Err(());
+            handler
+                .on(&InvalidImageLength { actual: self.length })
+                .or(Err(false))?;
+            return Err(true);
         }
 
         // Cannot fall through here, because `self.length` is potentially out
@@ -124,11 +195,10 @@ impl BlockHeader {
         // Validate claimed block count
         let bc = block_count_for(self.length);
         if self.block_count != bc {
-            handler.on(&InvalidBlockCount {
-                actual:   self.block_count,
-                expected: bc,
-            });
-            return Err(());
+            handler
+                .on(&InvalidBlockCount { actual: self.block_count, expected: bc })
+                .or(Err(false))?;
+            return Err(true);
         }
 
         Ok(())
@@ -136,7 +206,10 @@ impl BlockHeader {
 
     /// Verifies that the header's fields (except `block_index`) match those of
     /// the given `other` header.
-    pub fn check_match<H>(&self, other: &BlockHeader, handler: &H) -> Result<(), ()>
+    ///
+    /// Returns `Err(true)` if any field differs and `handler` returns `Ok(())`
+    /// (continue), or `Err(false)` if `handler` returns `Err(())` (stop).
+    pub fn check_match<H>(&self, other: &BlockHeader, handler: &H) -> Result<(), bool>
         where H: Handler<BlockDecodeError>
     {
         let mut result = Ok(());
@@ -146,8 +219,8 @@ impl BlockHeader {
                 actual:   self .version,
                 expected: other.version,
                 index:    self .block_index,
-            })?;
-            result = Err(());
+            }).or(Err(false))?;
+            result = Err(true);
         }
 
         if self.checksum != other.checksum {
@@ -155,8 +228,8 @@ impl BlockHeader {
                 actual:   self .checksum,
                 expected: other.checksum,
                 index:    self .block_index,
-            })?;
-            result = Err(());
+            }).or(Err(false))?;
+            result = Err(true);
         }
 
         if self.length != other.length {
@@ -164,8 +237,8 @@ impl BlockHeader {
                 actual:   self .length,
                 expected: other.length,
                 index:    self .block_index,
-            })?;
-            result = Err(());
+            }).or(Err(false))?;
+            result = Err(true);
         }
 
         if self.block_count != other.block_count {
@@ -173,30 +246,87 @@ impl BlockHeader {
                 actual:   self .block_count,
                 expected: other.block_count,
                 index:    self .block_index,
-            })?;
-            result = Err(());
+            }).or(Err(false))?;
+            result = Err(true);
         }
 
         result
     }
 
     /// Verifies that the header specifies a valid block index.
-    pub fn check_block_index<H>(&self, handler: &H) -> Result<(), ()>
+    ///
+    /// Returns `Ok(true)` if the index is valid.
+    ///
+    /// Returns `Ok(false)` if the index is invalid and `handler` returns
+    /// `Ok(())` (continue).
+    ///
+    /// Returns `Err(())` if the index is invalid and `handler` returns
+    /// `Err(())` (stop).
+    pub fn check_block_index<H>(&self, handler: &H) -> Result<bool, ()>
         where H: Handler<BlockDecodeError>
     {
         if self.block_index >= self.block_count {
             handler.on(&InvalidBlockIndex {
                 actual: self.block_index,
                 max:    self.block_count.saturating_sub(1),
-            });
+            })?;
+            return Ok(false);
         }
 
-        Ok(())
+        Ok(true)
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a> Arbitrary<'a> for BlockHeader {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(BlockHeader {
+            version:     u.arbitrary()?,
+            checksum:    u.arbitrary()?,
+            length:      u.arbitrary()?,
+            block_count: u.arbitrary()?,
+            block_index: u.arbitrary()?,
+        })
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a> Arbitrary<'a> for Block<'a> {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        // Always a full data payload, as from_bytes produces
+        Ok(Block {
+            header: u.arbitrary()?,
+            data:   u.bytes(BLOCK_DATA_LEN)?,
+        })
     }
 }
 
+// Reads a big-endian u32 from the start of `bytes`, advancing past it.  Does
+// not depend on std::io, so that block parsing is available without std.
+fn read_u32(bytes: &mut &[u8]) -> u32 {
+    let (head, rest) = bytes.split_at(4);
+    *bytes = rest;
+    u32::from_be_bytes([head[0], head[1], head[2], head[3]])
+}
+
+// Reads a big-endian u16 from the start of `bytes`, advancing past it.
+fn read_u16(bytes: &mut &[u8]) -> u16 {
+    let (head, rest) = bytes.split_at(2);
+    *bytes = rest;
+    u16::from_be_bytes([head[0], head[1]])
+}
+
+/// Computes the checksum of the given image `bytes`.
+pub fn checksum(bytes: &[u8]) -> u32 {
+    let mut sum = 0u32;
+    for &b in bytes {
+        sum = sum.wrapping_add(b as u32);
+    }
+    sum
+}
+
 #[inline]
-fn block_count_for(len: u32) -> u16 {
+pub fn block_count_for(len: u32) -> u16 {
     // Ceiling of `len` divided by `BLOCK_DATA_LEN`
     match len {
         0 => 0,
@@ -239,6 +369,14 @@ mod tests {
         }
     }
 
+    #[test]
+    fn block_kind_opcode() {
+        for &kind in &[BlockKind::Os, BlockKind::Boot] {
+            assert_eq!(BlockKind::from_opcode(kind.opcode()), Some(kind));
+        }
+        assert_eq!(BlockKind::from_opcode(Opcode::Pgm), None);
+    }
+
     #[test]
     fn block_from_bytes_ok() {
         let bytes
@@ -321,5 +459,50 @@ mod tests {
 
         assert_eq!(result.unwrap_err(), false);
     }
+
+    #[cfg(feature = "std")]
+    mod props {
+        use super::*;
+        use proptest::prelude::*;
+
+        struct Continue;
+
+        impl Handler<BlockDecodeError> for Continue {
+            fn on(&self, _: &BlockDecodeError) -> Result<(), ()> { Ok(()) }
+        }
+
+        proptest! {
+            #[test]
+            fn prop_block_from_bytes_any(bytes in prop::collection::vec(any::<u8>(), 0..600)) {
+                match Block::from_bytes(&bytes[..], &Continue) {
+                    Ok(block) => {
+                        let h = block.header;
+                        prop_assert!(bytes.len() >= BLOCK_HEAD_LEN + BLOCK_DATA_LEN);
+                        prop_assert_eq!(block.data, &bytes[BLOCK_HEAD_LEN..][..BLOCK_DATA_LEN]);
+                        prop_assert_eq!(&h.version .to_be_bytes()[..], &bytes[ 0.. 4]);
+                        prop_assert_eq!(&h.checksum.to_be_bytes()[..], &bytes[ 4.. 8]);
+                        prop_assert_eq!(&h.length  .to_be_bytes()[..], &bytes[ 8..12]);
+
+                        // Header checks must not panic on any header
+                        let _ = h.check_len(&Continue);
+                        let _ = h.check_block_index(&Continue);
+                        let _ = h.check_match(&h, &Continue);
+                    },
+                    Err(more) => {
+                        prop_assert!(more);
+                        prop_assert!(bytes.len() < BLOCK_HEAD_LEN + BLOCK_DATA_LEN);
+                    },
+                }
+            }
+
+            #[test]
+            fn prop_block_to_bytes_round_trip(
+                bytes in prop::collection::vec(any::<u8>(), BLOCK_HEAD_LEN + BLOCK_DATA_LEN)
+            ) {
+                let block = Block::from_bytes(&bytes[..], &Panicker).unwrap();
+                prop_assert_eq!(block.to_bytes(), bytes);
+            }
+        }
+    }
 }
 
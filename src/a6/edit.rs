@@ -0,0 +1,117 @@
+// This file is part of a6-tools.
+// Copyright (C) 2017 Jeffrey Sharp
+//
+// a6-tools is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License,
+// or (at your option) any later version.
+//
+// a6-tools is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See
+// the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with a6-tools.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::time::Duration;
+
+/// A schedule of intermediate values for changing a numeric parameter
+/// gradually, rather than jumping straight to the new value.
+///
+/// A `Ramp` is an iterator of `(time, value)` pairs, where `time` is relative
+/// to the start of the ramp.  Each pair is one Edit message to send.  Steps
+/// that would repeat the previous value are skipped, so a ramp over a narrow
+/// range yields fewer messages than the rate allows.  The final pair is always
+/// the target value at the full duration, unless the value does not change.
+#[derive(Clone, Debug)]
+pub struct Ramp {
+    from:     i32,          // Initial value
+    to:       i32,          // Target value
+    duration: Duration,     // Total time
+    steps:    u32,          // Count of steps
+    step:     u32,          // Index of next step (1-based)
+    last:     i32,          // Value of previous step
+}
+
+impl Ramp {
+    /// Creates a `Ramp` from value `from` to value `to` over the given
+    /// `duration`, with at most `rate` steps per second.
+    ///
+    /// Panics if `rate` is not positive.
+    pub fn new(from: i32, to: i32, duration: Duration, rate: f64) -> Self {
+        if !(rate > 0.0) {
+            panic!("Ramp rate must be positive.");
+        }
+
+        let secs  = duration.as_secs() as f64 + duration.subsec_nanos() as f64 * 1e-9;
+        let steps = (secs * rate).ceil().max(1.0) as u32;
+
+        Self { from, to, duration, steps, step: 1, last: from }
+    }
+}
+
+impl Iterator for Ramp {
+    type Item = (Duration, i32);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.step <= self.steps {
+            let k = self.step;
+            self.step += 1;
+
+            let delta = (self.to - self.from) as f64 * k as f64 / self.steps as f64;
+            let value = self.from + delta.round() as i32;
+            if value == self.last {
+                continue
+            }
+            self.last = value;
+
+            let time = self.duration * k / self.steps;
+            return Some((time, value))
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test]
+    fn ramp_up() {
+        let steps = Ramp::new(0, 100, ms(100), 40.0).collect::<Vec<_>>();
+
+        assert_eq!(steps, vec![
+            (ms( 25),  25),
+            (ms( 50),  50),
+            (ms( 75),  75),
+            (ms(100), 100),
+        ]);
+    }
+
+    #[test]
+    fn ramp_down_skips_repeats() {
+        let steps = Ramp::new(2, 0, ms(100), 100.0).collect::<Vec<_>>();
+
+        assert_eq!(steps, vec![
+            (ms( 30), 1),   // 1.4 rounds to 1
+            (ms( 80), 0),   // 0.4 rounds to 0
+        ]);
+    }
+
+    #[test]
+    fn ramp_zero_duration() {
+        let steps = Ramp::new(10, 20, ms(0), 50.0).collect::<Vec<_>>();
+
+        assert_eq!(steps, vec![(ms(0), 20)]);
+    }
+
+    #[test]
+    fn ramp_no_change() {
+        assert_eq!(Ramp::new(7, 7, ms(100), 50.0).count(), 0);
+    }
+}
@@ -16,7 +16,8 @@
 
 use std::fmt;
 
-use a6::block::{BLOCK_HEAD_LEN, BLOCK_DATA_LEN, IMAGE_MAX_BYTES, IMAGE_MAX_BLOCKS};
+use a6::Opcode;
+use a6::block::{BlockKind, BLOCK_HEAD_LEN, BLOCK_DATA_LEN, IMAGE_MAX_BYTES, IMAGE_MAX_BLOCKS};
 
 use self::BlockDecodeError::*;
 
@@ -34,10 +35,37 @@ pub enum BlockDecodeError {
     ChecksumMismatch        { actual: u32, expected: u32             },
     DuplicateBlock          {                             index: u16 },
     MissingBlock            {                             index: u16 },
+    UnexpectedMessage       { len: usize                             },
+    UnusedFinalBytes        { count: usize                           },
+    InvalidPadding          { actual: u8, expected: u8, offset: u32  },
+    MixedBlockKinds         { actual: BlockKind, expected: BlockKind },
 }
 
-impl fmt::Display for BlockDecodeError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+impl BlockDecodeError {
+    /// Gets the stable diagnostic code of the error, such as `A6-BLK-001`.
+    /// Codes appear in `Display` output and are never reused or renumbered.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            InvalidBlockLength      { .. } => "A6-BLK-001",
+            InvalidImageLength      { .. } => "A6-BLK-002",
+            InvalidBlockIndex       { .. } => "A6-BLK-003",
+            InvalidBlockCount       { .. } => "A6-BLK-004",
+            InconsistentVersion     { .. } => "A6-BLK-005",
+            InconsistentChecksum    { .. } => "A6-BLK-006",
+            InconsistentImageLength { .. } => "A6-BLK-007",
+            InconsistentBlockCount  { .. } => "A6-BLK-008",
+            ChecksumMismatch        { .. } => "A6-BLK-009",
+            DuplicateBlock          { .. } => "A6-BLK-010",
+            MissingBlock            { .. } => "A6-BLK-011",
+            UnexpectedMessage       { .. } => "A6-BLK-012",
+            UnusedFinalBytes        { .. } => "A6-BLK-013",
+            InvalidPadding          { .. } => "A6-BLK-014",
+            MixedBlockKinds         { .. } => "A6-BLK-015",
+        }
+    }
+
+    // Writes the message of the error, without its code.
+    fn fmt_message(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             InvalidBlockLength { actual } => write!(
                 f, "Invalid block length: {} byte(s). \
@@ -92,7 +120,281 @@ impl fmt::Display for BlockDecodeError {
                     First missing block is at index {}.",
                 index
             ),
+            UnexpectedMessage { len } => write!(
+                f, "Unexpected message: {} byte(s). \
+                    The message is not an OS or bootloader update block.",
+                len
+            ),
+            UnusedFinalBytes { count } => write!(
+                f, "Final block: {} trailing byte(s) are beyond the image length and are ignored. \
+                    They do not contribute to the checksum.",
+                count
+            ),
+            InvalidPadding { actual, expected, offset } => write!(
+                f, "Final block: invalid padding byte {:02X} at offset {} past the image end. \
+                    Padding bytes must be {:02X}.",
+                actual, offset, expected
+            ),
+            MixedBlockKinds { actual, expected } => write!(
+                f, "Unexpected {} update block in {} update. \
+                    An image cannot combine OS and bootloader blocks.",
+                actual, expected
+            ),
+        }
+    }
+}
+
+impl fmt::Display for BlockDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: ", self.code())?;
+        self.fmt_message(f)
+    }
+}
+
+
+/// Warning conditions reportable during block encoding.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BlockEncodeError {
+    VersionMismatch { version: u32, found: u32 },
+}
+
+impl BlockEncodeError {
+    /// Gets the stable diagnostic code of the error.  See `BlockDecodeError::code`.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            BlockEncodeError::VersionMismatch { .. } => "A6-ENC-001",
+        }
+    }
+}
+
+impl fmt::Display for BlockEncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: ", self.code())?;
+        match *self {
+            BlockEncodeError::VersionMismatch { version, found } => write!(
+                f, "Version {}.{}.{} ({}) does not match version {}.{}.{} ({}) \
+                    found in the image.",
+                version / 10000, version / 100 % 100, version % 100, version,
+                found   / 10000, found   / 100 % 100, found   % 100, found,
+            ),
+        }
+    }
+}
+
+/// Error conditions reportable when encoding or decoding patch names.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NameError {
+    TooLong         { len: usize, max: usize   },
+    UnsupportedChar { ch: char,   index: usize },
+    UnsupportedByte { byte: u8,   index: usize },
+}
+
+impl NameError {
+    /// Gets the stable diagnostic code of the error.  See `BlockDecodeError::code`.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            NameError::TooLong         { .. } => "A6-NAM-001",
+            NameError::UnsupportedChar { .. } => "A6-NAM-002",
+            NameError::UnsupportedByte { .. } => "A6-NAM-003",
+        }
+    }
+}
+
+impl fmt::Display for NameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: ", self.code())?;
+        match *self {
+            NameError::TooLong { len, max } => write!(
+                f, "Name too long: {} character(s). \
+                    Names can have at most {} characters.",
+                len, max
+            ),
+            NameError::UnsupportedChar { ch, index } => write!(
+                f, "Unsupported character {:?} at index {}. \
+                    The A6 cannot display this character.",
+                ch, index
+            ),
+            NameError::UnsupportedByte { byte, index } => write!(
+                f, "Unsupported byte {:02X} at index {}. \
+                    The byte does not encode a name character.",
+                byte, index
+            ),
+        }
+    }
+}
+
+/// Reasons a SysEx message is rejected as an A6 message.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MessageError {
+    WrongManufacturer,
+    Truncated        { len: usize                                  },
+    UnknownOpcode    { opcode: u8                                  },
+    BadLength        { opcode: Opcode, len: usize, expected: usize },
+    BadPayload       { opcode: Opcode                              },
+    BadCustomPayload { opcode: u8                                  },
+}
+
+impl MessageError {
+    /// Gets the stable diagnostic code of the error.  See `BlockDecodeError::code`.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            MessageError::WrongManufacturer       => "A6-MSG-001",
+            MessageError::UnknownOpcode    { .. } => "A6-MSG-002",
+            MessageError::BadLength        { .. } => "A6-MSG-003",
+            MessageError::BadPayload       { .. } => "A6-MSG-004",
+            MessageError::Truncated        { .. } => "A6-MSG-005",
+            MessageError::BadCustomPayload { .. } => "A6-MSG-006",
+        }
+    }
+}
+
+impl fmt::Display for MessageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: ", self.code())?;
+        match *self {
+            MessageError::WrongManufacturer => write!(
+                f, "Not an A6 message. \
+                    A6 messages begin with ID 00 00 0E 1D."
+            ),
+            MessageError::Truncated { len } => write!(
+                f, "Truncated A6 message: {} byte(s). \
+                    A6 messages have at least an ID and an opcode.",
+                len
+            ),
+            MessageError::UnknownOpcode { opcode } => write!(
+                f, "Unknown opcode {:02X}.",
+                opcode
+            ),
+            MessageError::BadLength { opcode, len, expected } => write!(
+                f, "Invalid {:?} data length: {} byte(s). \
+                    {:?} messages must have exactly {} data bytes.",
+                opcode, len, opcode, expected
+            ),
+            MessageError::BadPayload { opcode } => write!(
+                f, "Invalid {:?} data. \
+                    The data has the right length but invalid content.",
+                opcode
+            ),
+            MessageError::BadCustomPayload { opcode } => write!(
+                f, "Invalid data for custom opcode {:02X}.",
+                opcode
+            ),
+        }
+    }
+}
+
+/// Problems reportable when checking outgoing messages.  Each identifies the
+/// affected message by its 0-based `index`, except `BadUpdate`, which applies
+/// to the update as a whole.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LintError {
+    BadFraming      { index: usize                                               },
+    InvalidDataByte { index: usize, offset: usize, byte: u8                      },
+    UnknownMessage  { index: usize                                               },
+    BadLength       { index: usize, opcode: Opcode, len: usize, expected: usize },
+    BadBlock        { index: usize, err: BlockDecodeError                        },
+    BadUpdate       {               err: BlockDecodeError                        },
+}
+
+impl LintError {
+    /// Gets the stable diagnostic code of the error.  See `BlockDecodeError::code`.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            LintError::BadFraming      { ..          } => "A6-LNT-001",
+            LintError::InvalidDataByte { ..          } => "A6-LNT-002",
+            LintError::UnknownMessage  { ..          } => "A6-LNT-003",
+            LintError::BadLength       { ..          } => "A6-LNT-004",
+            LintError::BadBlock        { ref err, .. } => err.code(),
+            LintError::BadUpdate       { ref err     } => err.code(),
+        }
+    }
+}
+
+impl fmt::Display for LintError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: ", self.code())?;
+        match *self {
+            LintError::BadFraming { index } => write!(
+                f, "Message {}: invalid framing. \
+                    Messages must begin with F0 and end with F7.",
+                index
+            ),
+            LintError::InvalidDataByte { index, offset, byte } => write!(
+                f, "Message {}: invalid data byte {:02X} at offset {}. \
+                    Data bytes must not have the high bit set.",
+                index, byte, offset
+            ),
+            LintError::UnknownMessage { index } => write!(
+                f, "Message {}: not an A6 message.",
+                index
+            ),
+            LintError::BadLength { index, opcode, len, expected } => write!(
+                f, "Message {}: invalid {:?} data length: {} byte(s). \
+                    {:?} messages must have exactly {} data bytes.",
+                index, opcode, len, opcode, expected
+            ),
+            LintError::BadBlock { index, ref err } => {
+                write!(f, "Message {}: ", index)?;
+                err.fmt_message(f)
+            },
+            LintError::BadUpdate { ref err } => {
+                err.fmt_message(f)
+            },
         }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt::Write;
+
+    // A fixed-capacity text buffer, to show that formatting does not allocate
+    struct FixedBuf {
+        buf: [u8; 256],
+        len: usize,
+    }
+
+    impl FixedBuf {
+        fn new() -> Self {
+            Self { buf: [0; 256], len: 0 }
+        }
+
+        fn as_str(&self) -> &str {
+            ::std::str::from_utf8(&self.buf[..self.len]).unwrap()
+        }
+    }
+
+    impl Write for FixedBuf {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let end = self.len + s.len();
+            if end > self.buf.len() { return Err(fmt::Error) }
+            self.buf[self.len..end].copy_from_slice(s.as_bytes());
+            self.len = end;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn display_without_allocation() {
+        let mut buf = FixedBuf::new();
+        write!(buf, "{}", DuplicateBlock { index: 7 }).unwrap();
+        assert_eq!(buf.as_str(), "A6-BLK-010: Block 7: duplicate block.");
+
+        let mut buf = FixedBuf::new();
+        write!(buf, "{}", NameError::TooLong { len: 17, max: 16 }).unwrap();
+        assert_eq!(
+            buf.as_str(),
+            "A6-NAM-001: Name too long: 17 character(s). \
+             Names can have at most 16 characters."
+        );
+
+        let mut buf = FixedBuf::new();
+        write!(buf, "{}", LintError::BadBlock { index: 3, err: MissingBlock { index: 2 } }).unwrap();
+        assert_eq!(
+            buf.as_str(),
+            "A6-BLK-011: Message 3: Incomplete image: one or more block(s) is missing. \
+             First missing block is at index 2."
+        );
+    }
+}
@@ -0,0 +1,170 @@
+// This file is part of a6-tools.
+// Copyright (C) 2017 Jeffrey Sharp
+//
+// a6-tools is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License,
+// or (at your option) any later version.
+//
+// a6-tools is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See
+// the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with a6-tools.  If not, see <http://www.gnu.org/licenses/>.
+
+
+// MIDI Identity Request and Identity Reply (Universal Non-Real-Time SysEx,
+// General Information sub-ID 06).
+
+#[cfg(feature = "std")]
+use std::borrow::Cow;
+
+use a6::{ID, MANUFACTURER_LEN};
+
+#[cfg(not(feature = "std"))]
+use heap::*;
+
+pub const ALL_CALL: u8 = 0x7F;  // Device ID to which every device responds
+
+const NON_REAL_TIME:    u8 = 0x7E;  // Universal Non-Real-Time SysEx ID
+const GENERAL_INFO:     u8 = 0x06;  // Sub-ID #1: General Information
+const IDENTITY_REQUEST: u8 = 0x01;  // Sub-ID #2: Identity Request
+const IDENTITY_REPLY:   u8 = 0x02;  // Sub-ID #2: Identity Reply
+
+/// Builds a MIDI Identity Request message (with start/end bytes) for the
+/// device with the given `device` ID, or for all devices if `ALL_CALL`.
+///
+/// Panics if `device` is not a 7-bit value.
+pub fn request_identity(device: u8) -> Vec<u8> {
+    if device > 0x7F {
+        panic!("Device ID {} is not a 7-bit value.", device);
+    }
+    vec![0xF0, NON_REAL_TIME, device, GENERAL_INFO, IDENTITY_REQUEST, 0xF7]
+}
+
+/// A MIDI Identity Reply: the identity of a device and its software version.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct IdentityReply<'a> {
+    /// Device ID of the responding device.
+    pub device: u8,
+
+    /// Manufacturer ID: one byte, or three bytes beginning with 00.  Borrowed
+    /// from the parsed message.
+    pub manufacturer: Cow<'a, [u8]>,
+
+    /// Device family code, LSB first.
+    pub family: [u8; 2],
+
+    /// Device family member code, LSB first.
+    pub member: [u8; 2],
+
+    /// Software version, comparable to `BlockHeader::version` and the result
+    /// of `read_update_version`.  Decoded from the four 7-bit software
+    /// revision bytes, LSB first.
+    pub version: u32,
+}
+
+impl<'a> IdentityReply<'a> {
+    /// Parses the given SysEx message `msg` (without start/end bytes) as a MIDI
+    /// Identity Reply.  Returns `None` if the message is not a well-formed
+    /// Identity Reply.
+    pub fn parse(msg: &'a [u8]) -> Option<Self> {
+        if msg.len() < 5 || msg[0] != NON_REAL_TIME
+            || msg[2] != GENERAL_INFO || msg[3] != IDENTITY_REPLY {
+            return None
+        }
+
+        let device = msg[1];
+        let rest   = &msg[4..];
+
+        let mfr_len = match rest[0] {
+            0x00 => MANUFACTURER_LEN,
+            _    => 1,
+        };
+        if rest.len() != mfr_len + 8 {
+            return None
+        }
+
+        let (manufacturer, rest) = rest.split_at(mfr_len);
+        Some(Self {
+            device,
+            manufacturer: Cow::Borrowed(manufacturer),
+            family:  [rest[0], rest[1]],
+            member:  [rest[2], rest[3]],
+            version: rest[4..8].iter().rev().fold(0, |v, &b| v << 7 | b as u32),
+        })
+    }
+
+    /// Checks whether the reply is from an A6: that is, whether the
+    /// manufacturer ID and the LSB of the family code match the identifier
+    /// bytes used in A6 messages.
+    pub fn is_a6(&self) -> bool {
+        let (mfr, model) = ID.split_at(MANUFACTURER_LEN);
+        &self.manufacturer[..] == mfr && self.family[0] == model[0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request() {
+        assert_eq!(request_identity(ALL_CALL), [0xF0, 0x7E, 0x7F, 0x06, 0x01, 0xF7]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn request_bad_device() {
+        request_identity(0x80);
+    }
+
+    #[test]
+    fn parse_a6() {
+        let msg = [
+            0x7E, 0x10, 0x06, 0x02, 0x00, 0x00, 0x0E,
+            0x1D, 0x00, 0x00, 0x00, 0x09, 0x1D, 0x01, 0x00,
+        ];
+
+        let reply = IdentityReply::parse(&msg).unwrap();
+
+        assert_eq!(reply, IdentityReply {
+            device:       0x10,
+            manufacturer: Cow::Borrowed(&[0x00, 0x00, 0x0E]),
+            family:       [0x1D, 0x00],
+            member:       [0x00, 0x00],
+            version:      20105,
+        });
+        assert!(reply.is_a6());
+    }
+
+    #[test]
+    fn parse_other_device() {
+        let msg = [0x7E, 0x00, 0x06, 0x02, 0x43, 0x00, 0x41, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00];
+
+        let reply = IdentityReply::parse(&msg).unwrap();
+
+        assert_eq!(&reply.manufacturer[..], &[0x43]);
+        assert_eq!(reply.version, 1);
+        assert!(!reply.is_a6());
+    }
+
+    #[test]
+    fn parse_version() {
+        let msg = [0x7E, 0x00, 0x06, 0x02, 0x43, 0x00, 0x41, 0x01, 0x00, 0x7F, 0x7F, 0x7F, 0x7F];
+
+        let reply = IdentityReply::parse(&msg).unwrap();
+
+        assert_eq!(reply.version, 0x0FFF_FFFF);
+    }
+
+    #[test]
+    fn parse_rejected() {
+        assert_eq!(IdentityReply::parse(&[0x7E, 0x10, 0x06, 0x01]), None);
+        assert_eq!(IdentityReply::parse(&[0x7E, 0x10, 0x06, 0x02, 0x00, 0x00, 0x0E]), None);
+        assert_eq!(IdentityReply::parse(&[0x00, 0x00, 0x0E, 0x1D, 0x00]), None);
+    }
+}
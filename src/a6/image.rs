@@ -0,0 +1,480 @@
+// This file is part of a6-tools.
+// Copyright (C) 2017 Jeffrey Sharp
+//
+// a6-tools is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License,
+// or (at your option) any later version.
+//
+// a6-tools is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See
+// the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with a6-tools.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::ops::Range;
+use std::slice::Chunks;
+
+use a6::build_message;
+use a6::block::*;
+use a6::error::{BlockDecodeError, BlockEncodeError};
+use a6::error::BlockDecodeError::*;
+use a6::error::BlockEncodeError::*;
+use codec::encode_7bit;
+use util::Handler;
+
+/// A raw A6 OS/bootloader image.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Image {
+    bytes: Box<[u8]>,
+}
+
+impl Image {
+    /// Creates an `Image` containing the given `bytes`.
+    ///
+    /// Returns `InvalidImageLength` if `bytes` exceeds the maximum image size.
+    pub fn new<B: Into<Box<[u8]>>>(bytes: B) -> Result<Self, BlockDecodeError> {
+        let bytes = bytes.into();
+
+        if bytes.len() > IMAGE_MAX_BYTES as usize {
+            return Err(InvalidImageLength { actual: bytes.len() as u32 });
+        }
+
+        Ok(Self { bytes })
+    }
+
+    /// Gets the bytes of the image.
+    #[inline]
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Gets the length of the image in bytes.
+    #[inline]
+    pub fn len(&self) -> u32 {
+        self.bytes.len() as u32
+    }
+
+    /// Gets the count of blocks required to hold the image.
+    #[inline]
+    pub fn block_count(&self) -> u16 {
+        block_count_for(self.len())
+    }
+
+    /// Computes the checksum of the image.
+    #[inline]
+    pub fn checksum(&self) -> u32 {
+        checksum(&self.bytes)
+    }
+
+    /// Computes the block header shared by the blocks of the image, given the
+    /// firmware `version`.  The `block_index` of the returned header is 0.
+    pub fn header(&self, version: u32) -> BlockHeader {
+        BlockHeader {
+            version,
+            checksum:    self.checksum(),
+            length:      self.len(),
+            block_count: self.block_count(),
+            block_index: 0,
+        }
+    }
+
+    /// Finds version strings of the form `major.minor.patch` in the image, and
+    /// returns their values as header-style version numbers.  For example, the
+    /// string `2.1.5` yields `20105`.
+    pub fn version_strings(&self) -> Vec<u32> {
+        self.version_spans().into_iter().map(|(_, v)| v).collect()
+    }
+
+    /// Finds version strings in the image as `version_strings` does, and
+    /// returns the byte range of each string along with its value.
+    pub fn version_spans(&self) -> Vec<(Range<usize>, u32)> {
+        let mut versions = vec![];
+        let mut bytes    = &self.bytes[..];
+
+        while bytes.len() != 0 {
+            let pos = self.bytes.len() - bytes.len();
+            match parse_version(bytes) {
+                Some((v, n)) => { versions.push((pos..pos + n, v)); bytes = &bytes[n..] },
+                None         => { bytes = skip_token(bytes) },
+            }
+        }
+
+        versions
+    }
+
+    /// Verifies that the given firmware `version` agrees with the version
+    /// strings found in the image, if any.  Reports a `VersionMismatch` to
+    /// the given `handler` if no version string in the image matches.
+    pub fn check_version<H>(&self, version: u32, handler: &H) -> Result<(), ()>
+        where H: Handler<BlockEncodeError>
+    {
+        let found = self.version_strings();
+
+        match found.first() {
+            Some(&first) if !found.contains(&version) => {
+                handler.on(&VersionMismatch { version, found: first })
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns an iterator over the blocks of the image, given the firmware
+    /// `version`.  Blocks borrow their data from the image; the data of the
+    /// final block is shorter than `BLOCK_DATA_LEN` if the image length is not
+    /// a multiple of it.
+    pub fn blocks_with(&self, version: u32) -> Blocks {
+        Blocks {
+            header: self.header(version),
+            chunks: self.bytes.chunks(BLOCK_DATA_LEN),
+        }
+    }
+
+    /// Encodes the image as a complete update, given the firmware `version`
+    /// and the `kind` of update.  Returns the concatenated SysEx messages
+    /// (with start/end bytes), ready to write to a `.syx` file.
+    pub fn to_sysex(&self, version: u32, kind: BlockKind) -> Vec<u8> {
+        let messages = BlockEncoder::new(self, version).messages(kind);
+        let mut sysex = Vec::with_capacity(messages.len() * (BLOCK_7BIT_LEN + 7));
+        for msg in messages {
+            sysex.extend_from_slice(&msg);
+        }
+        sysex
+    }
+}
+
+// Parses a version string at the start of `bytes`.  Returns the version number
+// and the length of the string, or `None` if `bytes` does not start with a
+// version string.
+fn parse_version(bytes: &[u8]) -> Option<(u32, usize)> {
+    let mut parts = [0u32; 3];
+    let mut i     = 0;
+
+    for (n, part) in parts.iter_mut().enumerate() {
+        // Require '.' between parts
+        if n != 0 {
+            match bytes.get(i) {
+                Some(&b'.') => i += 1,
+                _           => return None,
+            }
+        }
+
+        // Require 1-2 digits
+        let digits = bytes[i..].iter().take_while(|b| b.is_ascii_digit()).count();
+        if digits == 0 || digits > 2 {
+            return None
+        }
+
+        for &b in &bytes[i..i + digits] {
+            *part = *part * 10 + (b - b'0') as u32;
+        }
+        i += digits;
+    }
+
+    // Reject if followed by more of a token
+    match bytes.get(i) {
+        Some(&b) if b == b'.' || b.is_ascii_digit() => None,
+        _ => Some((parts[0] * 10000 + parts[1] * 100 + parts[2], i)),
+    }
+}
+
+// Skips the token (run of digits and dots, or single other byte) at the start
+// of `bytes`.
+fn skip_token(bytes: &[u8]) -> &[u8] {
+    let n = bytes.iter().take_while(|&&b| b == b'.' || b.is_ascii_digit()).count();
+    &bytes[n.max(1)..]
+}
+
+/// An iterator over the blocks of an `Image`.
+#[derive(Clone, Debug)]
+pub struct Blocks<'a> {
+    /// Header of next block.
+    header: BlockHeader,
+
+    /// Remaining image data.
+    chunks: Chunks<'a, u8>,
+}
+
+impl<'a> Iterator for Blocks<'a> {
+    type Item = Block<'a>;
+
+    fn next(&mut self) -> Option<Block<'a>> {
+        let data  = self.chunks.next()?;
+        let block = Block { header: self.header, data };
+        self.header.block_index += 1;
+        Some(block)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.chunks.size_hint()
+    }
+}
+
+impl<'a> ExactSizeIterator for Blocks<'a> { }
+
+/// Encodes an `Image` as update blocks, the inverse of `BlockDecoder`.
+///
+/// A `BlockEncoder` is an iterator of raw blocks, each a header followed by
+/// `BLOCK_DATA_LEN` bytes of data.  The data of the final block is padded
+/// beyond the end of the image, with zeros by default.
+#[derive(Clone, Debug)]
+pub struct BlockEncoder<'a> {
+    /// Blocks remaining to encode.
+    blocks: Blocks<'a>,
+
+    /// Value of final-block padding bytes.
+    pad: u8,
+}
+
+impl<'a> BlockEncoder<'a> {
+    /// Creates a `BlockEncoder` for the given `image` and firmware `version`.
+    pub fn new(image: &'a Image, version: u32) -> Self {
+        Self { blocks: image.blocks_with(version), pad: 0 }
+    }
+
+    /// Sets the value of the padding bytes that follow the image data in the
+    /// final block.
+    pub fn set_padding(&mut self, pad: u8) {
+        self.pad = pad;
+    }
+
+    /// Returns an iterator over the remaining blocks as `Block` values,
+    /// without padding.
+    pub fn blocks(&self) -> Blocks<'a> {
+        self.blocks.clone()
+    }
+
+    /// Returns an iterator over the remaining blocks as SysEx messages (with
+    /// start/end bytes) of the given `kind`.
+    pub fn messages(self, kind: BlockKind) -> Messages<'a> {
+        Messages { encoder: self, kind }
+    }
+}
+
+impl<'a> Iterator for BlockEncoder<'a> {
+    type Item = [u8; BLOCK_HEAD_LEN + BLOCK_DATA_LEN];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let block = self.blocks.next()?;
+        let data  = block.data.len();
+
+        let mut bytes = [self.pad; BLOCK_HEAD_LEN + BLOCK_DATA_LEN];
+        bytes[..BLOCK_HEAD_LEN].copy_from_slice(&block.header.to_bytes());
+        bytes[BLOCK_HEAD_LEN..][..data].copy_from_slice(block.data);
+        Some(bytes)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.blocks.size_hint()
+    }
+}
+
+impl<'a> ExactSizeIterator for BlockEncoder<'a> { }
+
+/// An iterator over the blocks of a `BlockEncoder` as SysEx messages.
+#[derive(Clone, Debug)]
+pub struct Messages<'a> {
+    /// Source of raw blocks.
+    encoder: BlockEncoder<'a>,
+
+    /// Kind of update block.
+    kind: BlockKind,
+}
+
+impl<'a> Iterator for Messages<'a> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        let block = self.encoder.next()?;
+        let mut payload = Vec::with_capacity(BLOCK_7BIT_LEN);
+        encode_7bit(&block, &mut payload);
+        Some(build_message(self.kind.opcode(), &payload))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.encoder.size_hint()
+    }
+}
+
+impl<'a> ExactSizeIterator for Messages<'a> { }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl Handler<BlockEncodeError> for Vec<(BlockEncodeError, Result<(), ()>)> {
+        fn on(&self, event: &BlockEncodeError) -> Result<(), ()> {
+            match self.iter().find(|&&(e, _)| e == *event) {
+                Some(&(_, result)) => result,
+                None               => panic!("Unexpected event: {:?}", event),
+            }
+        }
+    }
+
+    struct Panicker;
+
+    impl Handler<BlockDecodeError> for Panicker {
+        fn on(&self, event: &BlockDecodeError) -> Result<(), ()> {
+            panic!("Unexpected event: {:?}", event)
+        }
+    }
+
+    // Continues past the unused-final-bytes notice, and panics on anything else
+    struct Ignore;
+
+    impl Handler<BlockDecodeError> for Ignore {
+        fn on(&self, event: &BlockDecodeError) -> Result<(), ()> {
+            match *event {
+                UnusedFinalBytes { .. } => Ok(()),
+                _ => panic!("Unexpected event: {:?}", event),
+            }
+        }
+    }
+
+    fn new_image(len: usize) -> Image {
+        let bytes = (0..len).map(|x| x as u8).collect::<Vec<_>>();
+        Image::new(bytes).unwrap()
+    }
+
+    #[test]
+    fn new_too_large() {
+        let bytes = vec![0; IMAGE_MAX_BYTES as usize + 1];
+
+        let result = Image::new(bytes);
+
+        assert_eq!(result, Err(InvalidImageLength { actual: IMAGE_MAX_BYTES + 1 }));
+    }
+
+    #[test]
+    fn header() {
+        let image = new_image(1000);
+
+        let header = image.header(0x01020304);
+
+        assert_eq!(header.version,     0x01020304);
+        assert_eq!(header.checksum,    checksum(image.bytes()));
+        assert_eq!(header.length,      1000);
+        assert_eq!(header.block_count, 4);
+        assert_eq!(header.block_index, 0);
+    }
+
+    #[test]
+    fn blocks_with() {
+        let image = new_image(1000);
+
+        let blocks = image.blocks_with(0x01020304).collect::<Vec<_>>();
+
+        assert_eq!(blocks.len(), 4);
+        for (i, block) in blocks.iter().enumerate() {
+            assert_eq!(block.header.version,     0x01020304);
+            assert_eq!(block.header.length,      1000);
+            assert_eq!(block.header.block_count, 4);
+            assert_eq!(block.header.block_index, i as u16);
+        }
+        assert_eq!(blocks[0].data, &image.bytes()[  0.. 256]);
+        assert_eq!(blocks[3].data, &image.bytes()[768..1000]);
+    }
+
+    #[test]
+    fn version_strings() {
+        let image = Image::new(&b"v2.1.5 x1.02.03y 1.2 1.2.3.4 99.99.99"[..]).unwrap();
+
+        assert_eq!(image.version_strings(), vec![20105, 10203, 999999]);
+    }
+
+    #[test]
+    fn version_spans() {
+        let image = Image::new(&b"v2.1.5 x1.02.03y"[..]).unwrap();
+
+        assert_eq!(image.version_spans(), vec![(1..6, 20105), (8..15, 10203)]);
+    }
+
+    #[test]
+    fn check_version_match() {
+        let image   = Image::new(&b"OS 2.1.5"[..]).unwrap();
+        let handler = vec![];
+
+        assert_eq!(image.check_version(20105, &handler), Ok(()));
+    }
+
+    #[test]
+    fn check_version_no_strings() {
+        let image   = new_image(1000);
+        let handler = vec![];
+
+        assert_eq!(image.check_version(20105, &handler), Ok(()));
+    }
+
+    #[test]
+    fn check_version_mismatch() {
+        let image   = Image::new(&b"OS 2.1.5"[..]).unwrap();
+        let handler = vec![
+            ( VersionMismatch { version: 20104, found: 20105 }, Err(()) )
+        ];
+
+        assert_eq!(image.check_version(20104, &handler), Err(()));
+    }
+
+    #[test]
+    fn block_encoder() {
+        let image = new_image(1000);
+
+        let mut encoder = BlockEncoder::new(&image, 20105);
+        encoder.set_padding(0xFF);
+        let blocks = encoder.collect::<Vec<_>>();
+
+        assert_eq!(blocks.len(), 4);
+        for (i, bytes) in blocks.iter().enumerate() {
+            let block = Block::from_bytes(&bytes[..], &Panicker).unwrap();
+            assert_eq!(block.header, BlockHeader { block_index: i as u16, ..image.header(20105) });
+        }
+        assert_eq!(&blocks[3][BLOCK_HEAD_LEN..][..232], &image.bytes()[768..]);
+        assert!(blocks[3][BLOCK_HEAD_LEN + 232..].iter().all(|&b| b == 0xFF));
+    }
+
+    #[test]
+    fn block_encoder_round_trip() {
+        use a6::update::BlockDecoder;
+
+        let image = new_image(1000);
+
+        let mut decoder = BlockDecoder::new(IMAGE_MAX_BYTES, Ignore);
+        for bytes in BlockEncoder::new(&image, 20105) {
+            decoder.decode_block(&bytes).unwrap();
+        }
+
+        assert_eq!(decoder.image(), Ok(image.bytes()));
+    }
+
+    #[test]
+    fn to_sysex() {
+        use a6::Opcode;
+        use a6::update::extract_image;
+
+        let image = new_image(1000);
+
+        let sysex = image.to_sysex(20105, BlockKind::Boot);
+        let (decoded, report, stats) = extract_image(&mut &sysex[..]).unwrap();
+
+        assert_eq!(sysex.len(), 4 * (BLOCK_7BIT_LEN + 7));
+        assert_eq!(sysex[5], Opcode::BootBlock as u8);
+        assert_eq!(decoded, image);
+        assert!(report.is_valid());
+        assert_eq!(report.header, Some(image.header(20105)));
+        assert_eq!(stats.blocks, 4);
+    }
+
+    #[test]
+    fn blocks_with_empty() {
+        let image = new_image(0);
+
+        let blocks = image.blocks_with(0);
+
+        assert_eq!(blocks.len(), 0);
+    }
+}
@@ -0,0 +1,289 @@
+// This file is part of a6-tools.
+// Copyright (C) 2017 Jeffrey Sharp
+//
+// a6-tools is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License,
+// or (at your option) any later version.
+//
+// a6-tools is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See
+// the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with a6-tools.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::cell::{Cell, RefCell};
+
+use a6::recognize_sysex;
+use a6::block::{BlockKind, IMAGE_MAX_BYTES};
+use a6::error::{BlockDecodeError, LintError};
+use a6::update::BlockDecoder;
+use sysex::{SYSEX_START, SYSEX_END};
+use util::Handler;
+
+use self::LintError::*;
+
+/// Checks the given outgoing SysEx messages `msgs` (with start/end bytes) for
+/// problems that make them unsafe to transmit: invalid framing, invalid data
+/// bytes, messages not addressed to the A6, implausible lengths for known
+/// opcodes, and update blocks that do not form a valid image, such as blocks
+/// with inconsistent headers, duplicate or missing blocks, OS blocks mixed
+/// with bootloader blocks, and block data that does not match the checksum.
+///
+/// Returns the problems found, in message order, followed by any problems with
+/// the update as a whole.  An empty result indicates that the messages passed
+/// all checks.
+pub fn lint_messages<'a, I>(msgs: I) -> Vec<LintError>
+where
+    I: IntoIterator<Item = &'a [u8]>,
+{
+    let errors      = RefCell::new(vec![]);
+    let current     = Cell::new(None);  // Index of update block being decoded
+    let handler     = At { index: &current, errors: &errors };
+    let mut decoder = BlockDecoder::new(IMAGE_MAX_BYTES, handler);
+
+    for (index, msg) in msgs.into_iter().enumerate() {
+        // Check framing
+        let data = match msg.len() {
+            n if n >= 2 && msg[0] == SYSEX_START && msg[n - 1] == SYSEX_END => &msg[1..n - 1],
+            _ => {
+                errors.borrow_mut().push(BadFraming { index });
+                continue
+            },
+        };
+
+        // Check data bytes
+        if let Some(i) = data.iter().position(|&b| b & 0x80 != 0) {
+            errors.borrow_mut().push(InvalidDataByte { index, offset: i + 1, byte: data[i] });
+            continue
+        }
+
+        // Check opcode and length
+        let (opcode, payload) = match recognize_sysex(data) {
+            Some(r) => r,
+            None    => {
+                errors.borrow_mut().push(UnknownMessage { index });
+                continue
+            },
+        };
+
+        if let Some(expected) = opcode.expected_payload_len() {
+            if payload.len() != expected {
+                errors.borrow_mut().push(BadLength { index, opcode, len: payload.len(), expected });
+                continue
+            }
+        }
+
+        // Check update blocks
+        if BlockKind::from_opcode(opcode).is_some() {
+            current.set(Some(index));
+            decoder.decode_message(data).expect("At does not abort");
+        }
+    }
+
+    // Check update image
+    if decoder.header().is_some() {
+        current.set(None);
+        decoder.image().expect("At does not abort");
+    }
+
+    errors.into_inner()
+}
+
+// A handler that records block decoding errors for the message at `index`, or
+// for the update as a whole if `index` is `None`.
+struct At<'e> {
+    index:  &'e Cell<Option<usize>>,
+    errors: &'e RefCell<Vec<LintError>>,
+}
+
+impl<'e> Handler<BlockDecodeError> for At<'e> {
+    fn on(&self, err: &BlockDecodeError) -> Result<(), ()> {
+        let err = *err;
+        let lint = match (self.index.get(), err) {
+            // Expected for any image that does not fill its final block
+            (_, BlockDecodeError::UnusedFinalBytes { .. }) => return Ok(()),
+
+            (Some(index), _) => BadBlock  { index, err },
+            (None,        _) => BadUpdate {        err },
+        };
+        self.errors.borrow_mut().push(lint);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use a6::Opcode;
+    use a6::block::{block_range, checksum, BLOCK_7BIT_LEN};
+    use a6::image::{BlockEncoder, Image};
+
+    // Bytes of a 3-block image; final block partially used
+    fn image_bytes() -> Vec<u8> {
+        (0..600).map(|x| (x * 7) as u8).collect()
+    }
+
+    // Encodes the image as update block messages of the given `version` and `kind`
+    fn block_messages(version: u32, kind: BlockKind) -> Vec<Vec<u8>> {
+        let image = Image::new(image_bytes()).unwrap();
+        BlockEncoder::new(&image, version).messages(kind).collect()
+    }
+
+    #[test]
+    fn clean() {
+        let msgs = block_messages(20000, BlockKind::Os);
+
+        let errors = lint_messages(msgs.iter().map(|m| &m[..]));
+
+        assert_eq!(errors, vec![]);
+    }
+
+    #[test]
+    fn bad_framing() {
+        let msgs: &[&[u8]] = &[b"\xF0\x00", b"\x00\xF7", b"\xF7"];
+
+        let errors = lint_messages(msgs.iter().cloned());
+
+        assert_eq!(errors, vec![
+            BadFraming { index: 0 },
+            BadFraming { index: 1 },
+            BadFraming { index: 2 },
+        ]);
+    }
+
+    #[test]
+    fn invalid_data_byte() {
+        let msgs: &[&[u8]] = &[b"\xF0\x00\x00\x0E\x1D\x90\xF7"];
+
+        let errors = lint_messages(msgs.iter().cloned());
+
+        assert_eq!(errors, vec![InvalidDataByte { index: 0, offset: 5, byte: 0x90 }]);
+    }
+
+    #[test]
+    fn unknown_message() {
+        let msgs: &[&[u8]] = &[b"\xF0\x43\x10\x00\xF7", b"\xF0\x00\x00\x0E\x1D\x01\x00\x05\xF7"];
+
+        let errors = lint_messages(msgs.iter().cloned());
+
+        assert_eq!(errors, vec![UnknownMessage { index: 0 }]);
+    }
+
+    #[test]
+    fn bad_length() {
+        let msgs: &[&[u8]] = &[b"\xF0\x00\x00\x0E\x1D\x30\x00\x00\xF7"];
+
+        let errors = lint_messages(msgs.iter().cloned());
+
+        assert_eq!(errors, vec![
+            BadLength { index: 0, opcode: Opcode::OsBlock, len: 2, expected: BLOCK_7BIT_LEN },
+        ]);
+    }
+
+    #[test]
+    fn inconsistent_headers() {
+        let os    = block_messages(20000, BlockKind::Os);
+        let other = block_messages(20001, BlockKind::Os);
+        let msgs  = vec![&os[0], &os[1], &other[2], &os[2]];
+
+        let errors = lint_messages(msgs.iter().map(|m| &m[..]));
+
+        assert_eq!(errors, vec![
+            BadBlock {
+                index: 2,
+                err: BlockDecodeError::InconsistentVersion {
+                    actual: 20001, expected: 20000, index: 2
+                },
+            },
+        ]);
+    }
+
+    #[test]
+    fn duplicate_block() {
+        let os   = block_messages(20000, BlockKind::Os);
+        let msgs = vec![&os[0], &os[1], &os[1], &os[2]];
+
+        let errors = lint_messages(msgs.iter().map(|m| &m[..]));
+
+        assert_eq!(errors, vec![
+            BadBlock { index: 2, err: BlockDecodeError::DuplicateBlock { index: 1 } },
+        ]);
+    }
+
+    #[test]
+    fn missing_block() {
+        let os   = block_messages(20000, BlockKind::Os);
+        let msgs = vec![&os[0], &os[2]];
+
+        let errors = lint_messages(msgs.iter().map(|m| &m[..]));
+
+        let mut partial = image_bytes();
+        for b in &mut partial[block_range(1)] { *b = 0 }
+
+        assert_eq!(errors, vec![
+            BadUpdate { err: BlockDecodeError::MissingBlock { index: 1 } },
+            BadUpdate {
+                err: BlockDecodeError::ChecksumMismatch {
+                    actual: checksum(&partial), expected: checksum(&image_bytes())
+                },
+            },
+        ]);
+    }
+
+    #[test]
+    fn mixed_block_kinds() {
+        let os   = block_messages(20000, BlockKind::Os);
+        let boot = block_messages(20000, BlockKind::Boot);
+        let msgs = vec![&os[0], &boot[1], &os[1], &os[2]];
+
+        let errors = lint_messages(msgs.iter().map(|m| &m[..]));
+
+        assert_eq!(errors, vec![
+            BadBlock {
+                index: 1,
+                err: BlockDecodeError::MixedBlockKinds {
+                    actual: BlockKind::Boot, expected: BlockKind::Os
+                },
+            },
+        ]);
+    }
+
+    #[test]
+    fn corrupt_block_data() {
+        let mut msgs = block_messages(20000, BlockKind::Os);
+        msgs[1][100] ^= 0x01;
+
+        let errors = lint_messages(msgs.iter().map(|m| &m[..]));
+
+        match errors[..] {
+            [BadUpdate { err: BlockDecodeError::ChecksumMismatch { expected, .. } }] => {
+                assert_eq!(expected, checksum(&image_bytes()));
+            },
+            ref e => panic!("Unexpected errors: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn display() {
+        let err = BadBlock {
+            index: 2,
+            err:   BlockDecodeError::DuplicateBlock { index: 1 },
+        };
+
+        assert_eq!(err.code(), "A6-BLK-010");
+        assert_eq!(err.to_string(), "A6-BLK-010: Message 2: Block 1: duplicate block.");
+        assert_eq!(
+            BadUpdate { err: BlockDecodeError::MissingBlock { index: 3 } }.to_string(),
+            "A6-BLK-011: Incomplete image: one or more block(s) is missing. \
+             First missing block is at index 3."
+        );
+        assert_eq!(
+            BadFraming { index: 0 }.to_string(),
+            "A6-LNT-001: Message 0: invalid framing. \
+             Messages must begin with F0 and end with F7."
+        );
+    }
+}
@@ -0,0 +1,413 @@
+// This file is part of a6-tools.
+// Copyright (C) 2017 Jeffrey Sharp
+//
+// a6-tools is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License,
+// or (at your option) any later version.
+//
+// a6-tools is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See
+// the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with a6-tools.  If not, see <http://www.gnu.org/licenses/>.
+
+
+#[cfg(feature = "std")]
+use std::borrow::Cow;
+
+use a6::{build_message, parse_sysex, EditBufferDump, Opcode, ProgramDump};
+use a6::block::{Block, BlockHeader, BLOCK_HEAD_LEN, BLOCK_DATA_LEN, BLOCK_7BIT_LEN};
+use a6::error::{BlockDecodeError, MessageError};
+use codec::{decode_7bit, decoded_len_7bit, encode_7bit};
+use util::Handler;
+
+#[cfg(not(feature = "std"))]
+use heap::*;
+
+/// An A6 SysEx message with its payload decoded according to its opcode.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum A6Message<'a> {
+    /// A program dump.
+    Program(ProgramDump),
+
+    /// A program edit buffer dump.
+    EditBuffer(EditBufferDump),
+
+    /// An OS update block, with its data decoded.
+    OsBlock   { header: BlockHeader, data: Box<[u8]> },
+
+    /// A bootloader update block, with its data decoded.
+    BootBlock { header: BlockHeader, data: Box<[u8]> },
+
+    /// A message whose payload format is not decoded by this crate, with its
+    /// raw 7-bit payload, borrowed from the parsed message.
+    Other     { opcode: Opcode, payload: Cow<'a, [u8]> },
+}
+
+impl<'a> A6Message<'a> {
+    /// Parses the given SysEx message `msg` (without start/end bytes) as an A6
+    /// message, decoding its payload.
+    ///
+    /// Returns an error if the message is not an A6 message, if its length is
+    /// wrong for its opcode, or if its payload is invalid.  A decoded payload
+    /// is invalid if it is not exactly as `encode_7bit` would encode it.
+    pub fn parse(msg: &'a [u8]) -> Result<Self, MessageError> {
+        let (opcode, payload) = parse_sysex(msg)?;
+
+        match opcode {
+            Opcode::Pgm => {
+                ProgramDump::from_message(msg)
+                    .filter(|_| is_canonical_7bit(&payload[2..]))
+                    .map(A6Message::Program)
+                    .ok_or(MessageError::BadPayload { opcode })
+            },
+            Opcode::PgmEditBuf => {
+                EditBufferDump::from_message(msg)
+                    .filter(|_| is_canonical_7bit(&payload[1..]))
+                    .map(A6Message::EditBuffer)
+                    .ok_or(MessageError::BadPayload { opcode })
+            },
+            Opcode::OsBlock | Opcode::BootBlock => {
+                if !is_canonical_7bit(payload) {
+                    return Err(MessageError::BadPayload { opcode })
+                }
+
+                let mut bytes = Vec::with_capacity(BLOCK_HEAD_LEN + BLOCK_DATA_LEN);
+                decode_7bit(payload, &mut bytes);
+
+                let block  = Block::from_bytes(&bytes, &Reject)
+                    .map_err(|_| MessageError::BadPayload { opcode })?;
+                let header = block.header;
+                let data   = block.data.to_vec().into_boxed_slice();
+
+                Ok(match opcode {
+                    Opcode::OsBlock => A6Message::OsBlock   { header, data },
+                    _               => A6Message::BootBlock { header, data },
+                })
+            },
+            _ => Ok(A6Message::Other { opcode, payload: Cow::Borrowed(payload) }),
+        }
+    }
+
+    /// Gets the opcode of the message.
+    pub fn opcode(&self) -> Opcode {
+        match *self {
+            A6Message::Program(..)          => Opcode::Pgm,
+            A6Message::EditBuffer(..)       => Opcode::PgmEditBuf,
+            A6Message::OsBlock   { .. }     => Opcode::OsBlock,
+            A6Message::BootBlock { .. }     => Opcode::BootBlock,
+            A6Message::Other { opcode, .. } => opcode,
+        }
+    }
+
+    /// Builds the message (with start/end bytes), re-encoding its decoded
+    /// payload.  For a message returned by `parse`, the result is identical to
+    /// the parsed message.
+    pub fn to_message(&self) -> Vec<u8> {
+        match *self {
+            A6Message::Program(ref dump)    => dump.to_message(),
+            A6Message::EditBuffer(ref dump) => dump.to_message(),
+            A6Message::OsBlock   { header, ref data }
+          | A6Message::BootBlock { header, ref data } => {
+                let bytes = Block { header, data }.to_bytes();
+                let mut payload = Vec::with_capacity(BLOCK_7BIT_LEN);
+                encode_7bit(&bytes, &mut payload);
+                build_message(self.opcode(), &payload)
+            },
+            A6Message::Other { opcode, ref payload } => build_message(opcode, payload),
+        }
+    }
+
+    /// Builds a copy of the message (with start/end bytes) that targets slot
+    /// `number` in `bank` instead of the slot it was saved from.  Returns
+    /// `None` if the message is not a program or mix dump.
+    ///
+    /// Program dumps are re-encoded from their decoded data.  Mix dumps are
+    /// not decoded by this crate, so only their leading bank and number bytes
+    /// are rewritten, as addressed by mix requests; the rest of the payload is
+    /// copied unchanged.
+    ///
+    /// Panics if `bank` or `number` is out of range for the dump.
+    pub fn retarget(&self, bank: u8, number: u8) -> Option<Vec<u8>> {
+        match *self {
+            A6Message::Program(ref dump) => {
+                Some(dump.with_slot(bank, number).to_message())
+            },
+            A6Message::Other { opcode: Opcode::Mix, ref payload } if payload.len() >= 2 => {
+                if bank > 0x7F || number > 0x7F {
+                    panic!("Mix slot {}:{} is not addressable.", bank, number);
+                }
+                let mut payload = payload.to_vec();
+                payload[0] = bank;
+                payload[1] = number;
+                Some(build_message(Opcode::Mix, &payload))
+            },
+            _ => None,
+        }
+    }
+}
+
+// Checks whether the given 7-bit values `src` are exactly as `encode_7bit`
+// would encode their decoded bytes: each value is 7-bit, and no bits beyond
+// the decoded bytes are set.  Decoding ignores such bits, so re-encoding would
+// not reproduce them.
+fn is_canonical_7bit(src: &[u8]) -> bool {
+    let unused = src.len() * 7 - decoded_len_7bit(src.len()) * 8;
+    let padding = match src.last() {
+        Some(_) if unused >= 7 => return false,
+        Some(&v) if unused > 0 => v >> (7 - unused),
+        _                      => 0,
+    };
+    padding == 0 && src.iter().all(|&v| v & 0x80 == 0)
+}
+
+// A handler that stops on any block decoding error.
+struct Reject;
+
+impl Handler<BlockDecodeError> for Reject {
+    fn on(&self, _: &BlockDecodeError) -> Result<(), ()> {
+        Err(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use a6::{EDIT_BUFFER_COUNT, PROGRAM_DATA_LEN, PROGRAM_MODE_BUFFER};
+    use codec::encode_7bit;
+
+    // Builds a message and strips its start/end bytes
+    fn message(opcode: Opcode, payload: &[u8]) -> Vec<u8> {
+        let mut msg = build_message(opcode, payload);
+        msg.pop();
+        msg.remove(0);
+        msg
+    }
+
+    #[test]
+    fn parse_program() {
+        let data = (0..PROGRAM_DATA_LEN).map(|x| x as u8).collect::<Vec<_>>();
+        let mut payload = vec![2, 7];
+        encode_7bit(&data, &mut payload);
+        let msg = message(Opcode::Pgm, &payload);
+
+        match A6Message::parse(&msg).unwrap() {
+            A6Message::Program(dump) => {
+                assert_eq!(dump.slot(), (2, 7));
+                assert_eq!(&dump.data[..], &data[..]);
+            },
+            other => panic!("Unexpected message: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_edit_buffer() {
+        let data = (0..PROGRAM_DATA_LEN).map(|x| x as u8).collect::<Vec<_>>();
+        let mut payload = vec![4];
+        encode_7bit(&data, &mut payload);
+        let msg = message(Opcode::PgmEditBuf, &payload);
+
+        let parsed = A6Message::parse(&msg).unwrap();
+
+        assert_eq!(parsed.opcode(), Opcode::PgmEditBuf);
+        match parsed {
+            A6Message::EditBuffer(dump) => {
+                assert_eq!(dump.buffer, 4);
+                assert_eq!(&dump.data[..], &data[..]);
+            },
+            other => panic!("Unexpected message: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_edit_buffer_bad_buffer() {
+        let mut payload = vec![EDIT_BUFFER_COUNT];
+        encode_7bit(&[0; PROGRAM_DATA_LEN], &mut payload);
+        let msg = message(Opcode::PgmEditBuf, &payload);
+
+        assert_eq!(
+            A6Message::parse(&msg),
+            Err(MessageError::BadPayload { opcode: Opcode::PgmEditBuf })
+        );
+    }
+
+    #[test]
+    fn parse_program_bad_slot() {
+        let mut payload = vec![99, 0];
+        encode_7bit(&[0; PROGRAM_DATA_LEN], &mut payload);
+        let msg = message(Opcode::Pgm, &payload);
+
+        assert_eq!(
+            A6Message::parse(&msg),
+            Err(MessageError::BadPayload { opcode: Opcode::Pgm })
+        );
+    }
+
+    #[test]
+    fn parse_block() {
+        let bytes = (0..BLOCK_HEAD_LEN + BLOCK_DATA_LEN).map(|x| x as u8).collect::<Vec<_>>();
+        let mut payload = vec![];
+        encode_7bit(&bytes, &mut payload);
+        let msg = message(Opcode::BootBlock, &payload);
+
+        let parsed = A6Message::parse(&msg).unwrap();
+
+        assert_eq!(parsed.opcode(), Opcode::BootBlock);
+        match parsed {
+            A6Message::BootBlock { header, data } => {
+                assert_eq!(header.version,     0x00010203);
+                assert_eq!(header.block_index, 0x0E0F);
+                assert_eq!(&data[..], &bytes[BLOCK_HEAD_LEN..]);
+            },
+            other => panic!("Unexpected message: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_non_canonical() {
+        let mut program = vec![2, 7];
+        encode_7bit(&[0; PROGRAM_DATA_LEN], &mut program);
+        *program.last_mut().unwrap() |= 0x10;  // unused bit set
+
+        let bytes = (0..BLOCK_HEAD_LEN + BLOCK_DATA_LEN).map(|x| x as u8).collect::<Vec<_>>();
+        let mut block = vec![];
+        encode_7bit(&bytes, &mut block);
+        *block.last_mut().unwrap() |= 0x40;   // unused bit set
+
+        for &(opcode, ref payload) in &[(Opcode::Pgm, program), (Opcode::OsBlock, block)] {
+            assert_eq!(
+                A6Message::parse(&message(opcode, payload)),
+                Err(MessageError::BadPayload { opcode })
+            );
+        }
+    }
+
+    #[test]
+    fn canonical_7bit() {
+        assert!( is_canonical_7bit(&[]));
+        assert!( is_canonical_7bit(&[0x7F, 0x01]));
+        assert!(!is_canonical_7bit(&[0x7F, 0x03]));  // unused bit set
+        assert!(!is_canonical_7bit(&[0x00]));        // unused value
+        assert!(!is_canonical_7bit(&[0x80, 0x00]));  // not 7-bit
+    }
+
+    #[test]
+    fn parse_other() {
+        let msg = message(Opcode::Edit, &[0x01, 0x02]);
+
+        assert_eq!(
+            A6Message::parse(&msg),
+            Ok(A6Message::Other {
+                opcode: Opcode::Edit, payload: Cow::Borrowed(&[0x01, 0x02])
+            })
+        );
+    }
+
+    #[test]
+    fn to_message_round_trip() {
+        let data  = (0..PROGRAM_DATA_LEN).map(|x| (x * 3) as u8).collect::<Vec<_>>();
+        let bytes = (0..BLOCK_HEAD_LEN + BLOCK_DATA_LEN).map(|x| (x * 11) as u8).collect::<Vec<_>>();
+
+        let mut program = vec![5, 6];
+        encode_7bit(&data, &mut program);
+        let mut edit = vec![PROGRAM_MODE_BUFFER];
+        encode_7bit(&data, &mut edit);
+        let mut block = vec![];
+        encode_7bit(&bytes, &mut block);
+
+        let msgs = vec![
+            build_message(Opcode::Pgm,        &program),
+            build_message(Opcode::PgmEditBuf, &edit),
+            build_message(Opcode::OsBlock,    &block),
+            build_message(Opcode::BootBlock,  &block),
+            build_message(Opcode::Mode,       &[0x01]),
+        ];
+
+        for msg in &msgs {
+            let parsed = A6Message::parse(&msg[1..msg.len() - 1]).unwrap();
+            assert_eq!(&parsed.to_message(), msg);
+        }
+    }
+
+    #[test]
+    fn retarget_program() {
+        let data = (0..PROGRAM_DATA_LEN).map(|x| (x * 5) as u8).collect::<Vec<_>>();
+        let mut payload = vec![2, 7];
+        encode_7bit(&data, &mut payload);
+        let msg = message(Opcode::Pgm, &payload);
+
+        let out = A6Message::parse(&msg).unwrap().retarget(15, 127).unwrap();
+
+        match A6Message::parse(&out[1..out.len() - 1]).unwrap() {
+            A6Message::Program(dump) => {
+                assert_eq!(dump.slot(), (15, 127));
+                assert_eq!(&dump.data[..], &data[..]);
+            },
+            other => panic!("Unexpected message: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn retarget_mix() {
+        let msg = message(Opcode::Mix, &[1, 2, 0x33, 0x44]);
+
+        let out = A6Message::parse(&msg).unwrap().retarget(3, 4).unwrap();
+
+        assert_eq!(out, build_message(Opcode::Mix, &[3, 4, 0x33, 0x44]));
+    }
+
+    #[test]
+    fn retarget_other() {
+        let msg = message(Opcode::Edit, &[0x01, 0x02]);
+
+        assert_eq!(A6Message::parse(&msg).unwrap().retarget(0, 0), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn retarget_mix_out_of_range() {
+        let msg = message(Opcode::Mix, &[1, 2]);
+        A6Message::parse(&msg).unwrap().retarget(0x80, 0);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde_round_trip() {
+        use a6::IdentityReply;
+
+        let mut payload = vec![1, 2];
+        encode_7bit(&[9; PROGRAM_DATA_LEN], &mut payload);
+        let msg    = message(Opcode::Pgm, &payload);
+        let parsed = A6Message::parse(&msg).unwrap();
+
+        let other = message(Opcode::Mode, &[0x01]);
+        let other = A6Message::parse(&other).unwrap();
+
+        let reply = [
+            0x7E, 0x10, 0x06, 0x02, 0x00, 0x00, 0x0E,
+            0x1D, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00,
+        ];
+        let reply = IdentityReply::parse(&reply).unwrap();
+
+        for msg in &[parsed, other] {
+            let json = ::serde_json::to_string(msg).unwrap();
+            let back = ::serde_json::from_str::<A6Message>(&json).unwrap();
+            assert_eq!(&back, msg);
+        }
+
+        let json = ::serde_json::to_string(&reply).unwrap();
+        let back = ::serde_json::from_str::<IdentityReply>(&json).unwrap();
+        assert_eq!(back, reply);
+
+        assert_eq!(::serde_json::to_string(&Opcode::MixReq).unwrap(), "\"MixReq\"");
+    }
+
+    #[test]
+    fn parse_rejected() {
+        assert_eq!(A6Message::parse(&[0x43, 0x10]), Err(MessageError::WrongManufacturer));
+    }
+}
@@ -14,23 +14,63 @@
 // You should have received a copy of the GNU General Public License
 // along with a6-tools.  If not, see <http://www.gnu.org/licenses/>.
 
-mod block;
-mod error;
-mod update;
+#[cfg(feature = "std")] mod annotate;
+#[cfg(feature = "std")] mod bank;
+                        mod block;
+#[cfg(feature = "std")] mod edit;
+                        mod error;
+                        mod identity;
+#[cfg(feature = "std")] mod image;
+#[cfg(feature = "std")] mod lint;
+                        mod message;
+#[cfg(feature = "std")] mod name;
+                        mod program;
+                        mod registry;
+                        mod request;
+#[cfg(feature = "std")] mod search;
+#[cfg(feature = "std")] mod update;
 
-pub use self::error::*;
-pub use self::update::*;
+#[cfg(feature = "std")] pub use self::annotate::*;
+#[cfg(feature = "std")] pub use self::bank::*;
+                        pub use self::block::{Block, BlockHeader, BlockKind};
+#[cfg(feature = "std")] pub use self::edit::*;
+                        pub use self::error::*;
+                        pub use self::identity::*;
+#[cfg(feature = "std")] pub use self::image::*;
+#[cfg(feature = "std")] pub use self::lint::*;
+                        pub use self::message::*;
+#[cfg(feature = "std")] pub use self::name::*;
+                        pub use self::program::*;
+                        pub use self::registry::*;
+                        pub use self::request::*;
+#[cfg(feature = "std")] pub use self::search::*;
+#[cfg(feature = "std")] pub use self::update::*;
+
+use std::convert::TryFrom;
+use std::iter;
+use std::slice;
+
+use self::block::BLOCK_7BIT_LEN;
+
+#[cfg(not(feature = "std"))]
+use heap::*;
+
+#[cfg(feature = "arbitrary")]
+use arbitrary::{Arbitrary, Unstructured};
 
 // Position constants
 const OPCODE_POS: usize = 4; // Position of opcode
 const DATA_POS:   usize = 5; // Start position of data
 
-// Manufacturer/device identifer bytes
+// Manufacturer/device identifer bytes: a 3-byte manufacturer ID (Alesis),
+// then a model ID (A6)
 static ID: [u8; 4] = [0x00, 0x00, 0x0E, 0x1D];
+const MANUFACTURER_LEN: usize = 3;
 
 /// A6 System Exclusive message types
 #[repr(u8)]
 #[derive(Clone, Copy, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Opcode {
     Pgm           = 0x00,
     PgmReq        = 0x01,
@@ -51,26 +91,298 @@ pub enum Opcode {
     BootBlock     = 0x3F,
 }
 
+impl Opcode {
+    /// All opcodes, in numeric order.
+    pub const ALL: [Opcode; 17] = [
+        Opcode::Pgm,
+        Opcode::PgmReq,
+        Opcode::PgmEditBuf,
+        Opcode::PgmEditBufReq,
+        Opcode::Mix,
+        Opcode::MixReq,
+        Opcode::MixEditBuf,
+        Opcode::MixEditBufReq,
+        Opcode::GlobalData,
+        Opcode::GlobalDataReq,
+        Opcode::PgmBankReq,
+        Opcode::MixBankReq,
+        Opcode::AllReq,
+        Opcode::Mode,
+        Opcode::Edit,
+        Opcode::OsBlock,
+        Opcode::BootBlock,
+    ];
+
+    /// Returns an iterator over all opcodes, in numeric order.
+    pub fn iter() -> iter::Cloned<slice::Iter<'static, Opcode>> {
+        Self::ALL.iter().cloned()
+    }
+
+    /// Gets the opcode with the given numeric value, or `None` if there is no
+    /// such opcode.
+    pub fn from_u8(value: u8) -> Option<Self> {
+        Some(match value {
+            0x00 => Opcode::Pgm,
+            0x01 => Opcode::PgmReq,
+            0x02 => Opcode::PgmEditBuf,
+            0x03 => Opcode::PgmEditBufReq,
+            0x04 => Opcode::Mix,
+            0x05 => Opcode::MixReq,
+            0x06 => Opcode::MixEditBuf,
+            0x07 => Opcode::MixEditBufReq,
+            0x08 => Opcode::GlobalData,
+            0x09 => Opcode::GlobalDataReq,
+            0x0A => Opcode::PgmBankReq,
+            0x0B => Opcode::MixBankReq,
+            0x0C => Opcode::AllReq,
+            0x0D => Opcode::Mode,
+            0x0E => Opcode::Edit,
+            0x30 => Opcode::OsBlock,
+            0x3F => Opcode::BootBlock,
+            _    => return None,
+        })
+    }
+
+    /// Gets the name of the opcode.
+    pub fn name(self) -> &'static str {
+        // Exhaustive, so that adding an opcode fails to compile until it is
+        // named here.  Tests check `ALL` and `from_u8` against each other.
+        match self {
+            Opcode::Pgm           => "Pgm",
+            Opcode::PgmReq        => "PgmReq",
+            Opcode::PgmEditBuf    => "PgmEditBuf",
+            Opcode::PgmEditBufReq => "PgmEditBufReq",
+            Opcode::Mix           => "Mix",
+            Opcode::MixReq        => "MixReq",
+            Opcode::MixEditBuf    => "MixEditBuf",
+            Opcode::MixEditBufReq => "MixEditBufReq",
+            Opcode::GlobalData    => "GlobalData",
+            Opcode::GlobalDataReq => "GlobalDataReq",
+            Opcode::PgmBankReq    => "PgmBankReq",
+            Opcode::MixBankReq    => "MixBankReq",
+            Opcode::AllReq        => "AllReq",
+            Opcode::Mode          => "Mode",
+            Opcode::Edit          => "Edit",
+            Opcode::OsBlock       => "OsBlock",
+            Opcode::BootBlock     => "BootBlock",
+        }
+    }
+
+    /// Gets the length of the data that follows the opcode in a message, or
+    /// `None` if the length varies or is not known.
+    pub fn expected_payload_len(self) -> Option<usize> {
+        match self {
+            Opcode::Pgm           => Some(2 + PROGRAM_7BIT_LEN),  // Bank, number, program
+            Opcode::PgmReq        => Some(2),                     // Bank, number
+            Opcode::PgmEditBuf    => Some(1 + PROGRAM_7BIT_LEN),  // Buffer, program
+            Opcode::PgmEditBufReq => None,
+            Opcode::Mix           => None,
+            Opcode::MixReq        => Some(2),                     // Bank, number
+            Opcode::MixEditBuf    => None,
+            Opcode::MixEditBufReq => None,
+            Opcode::GlobalData    => None,
+            Opcode::GlobalDataReq => None,
+            Opcode::PgmBankReq    => Some(1),                     // Bank
+            Opcode::MixBankReq    => Some(1),                     // Bank
+            Opcode::AllReq        => Some(0),
+            Opcode::Mode          => None,
+            Opcode::Edit          => None,
+            Opcode::OsBlock       => Some(BLOCK_7BIT_LEN),
+            Opcode::BootBlock     => Some(BLOCK_7BIT_LEN),
+        }
+    }
+
+    /// Gets the opcode with the given name, or `None` if there is no such
+    /// opcode.  Names are as returned by `name`, compared ignoring case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().cloned().find(|op| op.name().eq_ignore_ascii_case(name))
+    }
+}
+
+impl From<Opcode> for u8 {
+    #[inline]
+    fn from(op: Opcode) -> u8 {
+        op as u8
+    }
+}
+
+impl TryFrom<u8> for Opcode {
+    type Error = MessageError;
+
+    fn try_from(value: u8) -> Result<Self, MessageError> {
+        Opcode::from_u8(value).ok_or(MessageError::UnknownOpcode { opcode: value })
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a> Arbitrary<'a> for Opcode {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        u.choose(&Opcode::ALL).map(|&op| op)
+    }
+}
+
+/// Recognizes the given SysEx message `msg` (without start/end bytes) as an A6
+/// message.  Returns the message opcode and data, or `None` if the message is
+/// not an A6 message.
 pub fn recognize_sysex(msg: &[u8]) -> Option<(Opcode, &[u8])> {
-    use std::mem::transmute;
+    recognize_sysex_detailed(msg).ok()
+}
 
-    if !msg.starts_with(&ID) || msg.len() <= OPCODE_POS {
-        return None
+/// Recognizes the given SysEx message `msg` (without start/end bytes) as an A6
+/// message, as `recognize_sysex` does.  If the message is not an A6 message,
+/// returns an error distinguishing a message for another manufacturer or
+/// device, a truncated message, and an unknown opcode.
+pub fn recognize_sysex_detailed(msg: &[u8]) -> Result<(Opcode, &[u8]), MessageError> {
+    if msg.len() <= OPCODE_POS {
+        return Err(match ID.starts_with(msg) {
+            true  => MessageError::Truncated { len: msg.len() },
+            false => MessageError::WrongManufacturer,
+        })
     }
 
-    let opcode = msg[OPCODE_POS];
-    if opcode > 0x0E && opcode != 0x30 && opcode != 0x3F {
-        return None
+    if !msg.starts_with(&ID) {
+        return Err(MessageError::WrongManufacturer)
     }
 
-    let opcode = unsafe { transmute(opcode) };
-    Some((opcode, &msg[DATA_POS..]))
+    let opcode = Opcode::try_from(msg[OPCODE_POS])?;
+    Ok((opcode, &msg[DATA_POS..]))
+}
+
+/// Builds a complete SysEx message (with start/end bytes) addressed to the A6,
+/// having the given `opcode` and `payload`.  The payload must already consist
+/// of 7-bit values; see `codec` for encoding raw data.
+///
+/// Panics if any byte of `payload` is not a 7-bit value.
+pub fn build_message(opcode: Opcode, payload: &[u8]) -> Vec<u8> {
+    frame_message(opcode as u8, payload)
+}
+
+// Builds a complete SysEx message (with start/end bytes) addressed to the A6,
+// having the given raw `opcode` value and `payload`.  Panics if any byte of
+// `payload` is not a 7-bit value.
+fn frame_message(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    if let Some(i) = payload.iter().position(|&b| b > 0x7F) {
+        panic!("Payload byte {:02X} at offset {} is not a 7-bit value.", payload[i], i);
+    }
+    let mut msg = Vec::with_capacity(DATA_POS + payload.len() + 2);
+    msg.push(0xF0);
+    msg.extend_from_slice(&ID);
+    msg.push(opcode);
+    msg.extend_from_slice(payload);
+    msg.push(0xF7);
+    msg
+}
+
+/// Recognizes the given SysEx message `msg` (without start/end bytes) as an A6
+/// message and checks its length against `Opcode::expected_payload_len`.
+///
+/// Returns the message opcode and data, or an error describing why the message
+/// was rejected.
+pub fn parse_sysex(msg: &[u8]) -> Result<(Opcode, &[u8]), MessageError> {
+    let (opcode, payload) = recognize_sysex_detailed(msg)?;
+
+    match opcode.expected_payload_len() {
+        Some(expected) if payload.len() != expected => Err(MessageError::BadLength {
+            opcode, len: payload.len(), expected
+        }),
+        _ => Ok((opcode, payload)),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn opcode_all() {
+        // Every opcode is in ALL exactly once, in numeric order
+        let values = Opcode::iter().map(u8::from);
+        let known  = (0..=255).filter(|&v| Opcode::from_u8(v).is_some());
+        assert!(values.eq(known));
+
+        for op in Opcode::iter() {
+            assert_eq!(Opcode::from_u8(op as u8), Some(op));
+            assert_eq!(Opcode::from_name(op.name()), Some(op));
+        }
+    }
+
+    #[test]
+    fn opcode_conversions() {
+        assert_eq!(Opcode::from_u8(0x30),          Some(Opcode::OsBlock));
+        assert_eq!(Opcode::from_u8(0x0F),          None);
+        assert_eq!(Opcode::from_name("pgmeditbuf"), Some(Opcode::PgmEditBuf));
+        assert_eq!(Opcode::from_name("nope"),       None);
+        assert_eq!(Opcode::BootBlock.name(),       "BootBlock");
+        assert_eq!(u8::from(Opcode::Edit),         0x0E);
+    }
+
+    #[test]
+    fn opcode_try_from() {
+        assert_eq!(Opcode::try_from(0x3F), Ok(Opcode::BootBlock));
+        assert_eq!(Opcode::try_from(0x40), Err(MessageError::UnknownOpcode { opcode: 0x40 }));
+    }
+
+    #[test]
+    fn parse_sysex_ok() {
+        let msg = &[0x00, 0x00, 0x0E, 0x1D, 0x0E, 0x01, 0x02];
+
+        assert_eq!(parse_sysex(msg), Ok((Opcode::Edit, &[0x01, 0x02][..])));
+    }
+
+    #[test]
+    fn parse_sysex_rejected() {
+        assert_eq!(
+            parse_sysex(&[0x00, 0x00, 0x0E, 0x1D]),
+            Err(MessageError::Truncated { len: 4 })
+        );
+        assert_eq!(
+            parse_sysex(&[0x00, 0x00, 0x0E, 0x1D, 0x20]),
+            Err(MessageError::UnknownOpcode { opcode: 0x20 })
+        );
+        assert_eq!(
+            parse_sysex(&[0x00, 0x00, 0x0E, 0x1D, 0x30, 0x5A, 0xA5]),
+            Err(MessageError::BadLength { opcode: Opcode::OsBlock, len: 2, expected: BLOCK_7BIT_LEN })
+        );
+    }
+
+    #[test]
+    fn build_message_ok() {
+        let msg = build_message(Opcode::PgmReq, &[0x01, 0x2A]);
+
+        assert_eq!(msg, [0xF0, 0x00, 0x00, 0x0E, 0x1D, 0x01, 0x01, 0x2A, 0xF7]);
+        assert_eq!(recognize_sysex(&msg[1..msg.len() - 1]), Some((Opcode::PgmReq, &[0x01, 0x2A][..])));
+    }
+
+    #[test]
+    #[should_panic(expected = "not a 7-bit value")]
+    fn build_message_8bit_payload() {
+        build_message(Opcode::Mode, &[0x01, 0xF7]);
+    }
+
+    #[test]
+    fn recognize_sysex_detailed_rejected() {
+        assert_eq!(
+            recognize_sysex_detailed(&[0x43, 0x10, 0x00, 0x00, 0x00, 0x00]),
+            Err(MessageError::WrongManufacturer)
+        );
+        assert_eq!(
+            recognize_sysex_detailed(&[0x43]),
+            Err(MessageError::WrongManufacturer)
+        );
+        assert_eq!(
+            recognize_sysex_detailed(&[]),
+            Err(MessageError::Truncated { len: 0 })
+        );
+        assert_eq!(
+            recognize_sysex_detailed(&[0x00, 0x00, 0x0E]),
+            Err(MessageError::Truncated { len: 3 })
+        );
+        assert_eq!(
+            recognize_sysex_detailed(&[0x00, 0x00, 0x0E, 0x1D, 0x7F]),
+            Err(MessageError::UnknownOpcode { opcode: 0x7F })
+        );
+    }
+
     #[test]
     fn recognize_sysex_ok() {
         let msg = &[0x00, 0x00, 0x0E, 0x1D, 0x30, 0x5A, 0xA5];
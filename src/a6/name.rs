@@ -0,0 +1,274 @@
+// This file is part of a6-tools.
+// Copyright (C) 2017 Jeffrey Sharp
+//
+// a6-tools is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License,
+// or (at your option) any later version.
+//
+// a6-tools is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See
+// the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with a6-tools.  If not, see <http://www.gnu.org/licenses/>.
+
+use a6::error::NameError;
+use a6::error::NameError::*;
+
+/// Length of a program or mix name (bytes).
+pub const NAME_LEN: usize = 16;
+
+/// Byte that pads names shorter than `NAME_LEN`.
+pub const NAME_PAD: u8 = b' ';
+
+/// Trait for character sets that map name characters to and from bytes.
+pub trait Charset {
+    /// Gets the byte that encodes the given character `ch`, or `None` if the
+    /// character set does not contain `ch`.
+    fn encode(&self, ch: char) -> Option<u8>;
+
+    /// Gets the character that the given `byte` encodes, or `None` if `byte`
+    /// does not encode a character in the character set.
+    fn decode(&self, byte: u8) -> Option<char>;
+}
+
+/// The character set of the A6 front-panel display: printable ASCII, except
+/// `\` and `~`, which the display shows as other glyphs.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct A6Charset;
+
+impl A6Charset {
+    #[inline]
+    fn contains(byte: u8) -> bool {
+        match byte {
+            b'\\' | b'~' => false,
+            0x20...0x7D  => true,
+            _            => false,
+        }
+    }
+}
+
+impl Charset for A6Charset {
+    fn encode(&self, ch: char) -> Option<u8> {
+        let n = ch as u32;
+        if n < 0x80 && Self::contains(n as u8) { Some(n as u8) } else { None }
+    }
+
+    fn decode(&self, byte: u8) -> Option<char> {
+        if Self::contains(byte) { Some(byte as char) } else { None }
+    }
+}
+
+/// Policies for handling characters or bytes outside a character set.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Substitution {
+    /// Fail with an `UnsupportedChar` or `UnsupportedByte` error.
+    Reject,
+
+    /// Substitute the given character, which must be in the character set.
+    Replace(char),
+
+    /// Omit the character or byte.
+    Omit,
+}
+
+/// Encoder and decoder of program and mix names.
+#[derive(Clone, Copy, Debug)]
+pub struct NameCodec<C: Charset = A6Charset> {
+    charset: C,
+    policy:  Substitution,
+}
+
+impl NameCodec<A6Charset> {
+    /// Creates a `NameCodec` for the A6 character set that substitutes `?`
+    /// for unsupported characters.
+    pub fn new() -> Self {
+        Self::with_charset(A6Charset, Substitution::Replace('?'))
+    }
+}
+
+impl<C: Charset> NameCodec<C> {
+    /// Creates a `NameCodec` for the given `charset`, handling unsupported
+    /// characters according to the given `policy`.
+    ///
+    /// Panics if `policy` substitutes a character that is not in `charset`.
+    pub fn with_charset(charset: C, policy: Substitution) -> Self {
+        if let Substitution::Replace(ch) = policy {
+            if charset.encode(ch).is_none() {
+                panic!("Substitute character {:?} is not in the character set.", ch);
+            }
+        }
+        Self { charset, policy }
+    }
+
+    /// Gets the policy for unsupported characters.
+    #[inline]
+    pub fn policy(&self) -> Substitution {
+        self.policy
+    }
+
+    /// Encodes the given `name` into a `NAME_LEN`-byte field, padded with
+    /// spaces.  Unsupported characters are handled according to the codec's
+    /// policy.  Returns `TooLong` if the encoded name does not fit.
+    pub fn encode(&self, name: &str) -> Result<[u8; NAME_LEN], NameError> {
+        let mut bytes = [NAME_PAD; NAME_LEN];
+        let mut len   = 0;
+
+        for (index, ch) in name.chars().enumerate() {
+            let byte = match self.charset.encode(ch) {
+                Some(b) => b,
+                None    => match self.policy {
+                    Substitution::Reject      => return Err(UnsupportedChar { ch, index }),
+                    Substitution::Replace(ch) => self.charset.encode(ch).unwrap(),
+                    Substitution::Omit        => continue,
+                },
+            };
+
+            if len < NAME_LEN {
+                bytes[len] = byte;
+            }
+            len += 1;
+        }
+
+        if len > NAME_LEN {
+            return Err(TooLong { len, max: NAME_LEN })
+        }
+
+        Ok(bytes)
+    }
+
+    /// Decodes the given name field `bytes`, removing trailing padding.
+    /// Unsupported bytes are handled according to the codec's policy.
+    pub fn decode(&self, bytes: &[u8]) -> Result<String, NameError> {
+        let mut name = String::with_capacity(bytes.len());
+
+        for (index, &byte) in bytes.iter().enumerate() {
+            match self.charset.decode(byte) {
+                Some(ch) => name.push(ch),
+                None     => match self.policy {
+                    Substitution::Reject      => return Err(UnsupportedByte { byte, index }),
+                    Substitution::Replace(ch) => name.push(ch),
+                    Substitution::Omit        => (),
+                },
+            }
+        }
+
+        let len = name.trim_end_matches(NAME_PAD as char).len();
+        name.truncate(len);
+        Ok(name)
+    }
+
+    /// Replaces the name in the given name `field` with the given `name`.
+    /// Leaves `field` unchanged if `name` cannot be encoded.
+    ///
+    /// Panics if `field` is not `NAME_LEN` bytes long.
+    pub fn rename(&self, field: &mut [u8], name: &str) -> Result<(), NameError> {
+        let bytes = self.encode(name)?;
+        field.copy_from_slice(&bytes);
+        Ok(())
+    }
+}
+
+impl Default for NameCodec<A6Charset> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let codec = NameCodec::new();
+
+        for name in &["", "Jupiter Pad 2", "0123456789ABCDEF", "a-b_c [d] {e}"] {
+            let bytes = codec.encode(name).unwrap();
+            assert_eq!(codec.decode(&bytes).unwrap(), *name);
+        }
+    }
+
+    #[test]
+    fn encode_pads() {
+        let bytes = NameCodec::new().encode("Pad").unwrap();
+
+        assert_eq!(&bytes, b"Pad             ");
+    }
+
+    #[test]
+    fn encode_too_long() {
+        let result = NameCodec::new().encode("Seventeen chars!!");
+
+        assert_eq!(result, Err(TooLong { len: 17, max: 16 }));
+    }
+
+    #[test]
+    fn encode_replace() {
+        let bytes = NameCodec::new().encode("Café~Bass").unwrap();
+
+        assert_eq!(&bytes, b"Caf??Bass       ");
+    }
+
+    #[test]
+    fn encode_omit() {
+        let codec = NameCodec::with_charset(A6Charset, Substitution::Omit);
+
+        let bytes = codec.encode("Café~Bass").unwrap();
+
+        assert_eq!(&bytes, b"CafBass         ");
+    }
+
+    #[test]
+    fn encode_reject() {
+        let codec = NameCodec::with_charset(A6Charset, Substitution::Reject);
+
+        let result = codec.encode("Back\\slash");
+
+        assert_eq!(result, Err(UnsupportedChar { ch: '\\', index: 4 }));
+    }
+
+    #[test]
+    fn decode_replace() {
+        let name = NameCodec::new().decode(b"Lead\x00\x7E  ").unwrap();
+
+        assert_eq!(name, "Lead??");
+    }
+
+    #[test]
+    fn decode_reject() {
+        let codec = NameCodec::with_charset(A6Charset, Substitution::Reject);
+
+        let result = codec.decode(b"Lead\x80");
+
+        assert_eq!(result, Err(UnsupportedByte { byte: 0x80, index: 4 }));
+    }
+
+    #[test]
+    fn rename_ok() {
+        let mut field = *b"Old Name        ";
+
+        NameCodec::new().rename(&mut field, "New").unwrap();
+
+        assert_eq!(&field, b"New             ");
+    }
+
+    #[test]
+    fn rename_invalid() {
+        let mut field = *b"Old Name        ";
+        let codec     = NameCodec::with_charset(A6Charset, Substitution::Reject);
+
+        let result = codec.rename(&mut field, "Ω");
+
+        assert_eq!(result, Err(UnsupportedChar { ch: 'Ω', index: 0 }));
+        assert_eq!(&field, b"Old Name        ");
+    }
+
+    #[test]
+    #[should_panic]
+    fn with_charset_bad_substitute() {
+        NameCodec::with_charset(A6Charset, Substitution::Replace('~'));
+    }
+}
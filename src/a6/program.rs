@@ -0,0 +1,288 @@
+// This file is part of a6-tools.
+// Copyright (C) 2017 Jeffrey Sharp
+//
+// a6-tools is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License,
+// or (at your option) any later version.
+//
+// a6-tools is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See
+// the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with a6-tools.  If not, see <http://www.gnu.org/licenses/>.
+
+use a6::{build_message, recognize_sysex, Opcode};
+use codec::{decode_7bit, encode_7bit};
+
+#[cfg(not(feature = "std"))]
+use heap::*;
+
+pub const PROGRAM_DATA_LEN: usize = 2048;  // Raw program data length (bytes)
+pub const PROGRAM_7BIT_LEN: usize = 2341;  // 7-bit-encoded program data length (bytes)
+
+pub const BANK_COUNT:       u8 =  16;      // Banks addressable by program dumps
+pub const BANK_PROGRAMS:    u8 = 128;      // Programs per bank
+
+pub const EDIT_BUFFER_COUNT:   u8 = 17;    // Program edit buffers: 16 mix channels, then program mode
+pub const PROGRAM_MODE_BUFFER: u8 = 16;    // Edit buffer used in program mode
+
+/// A program dump: the raw data of one stored program and its slot.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ProgramDump {
+    /// 0-based bank number.
+    pub bank: u8,
+
+    /// 0-based program number within the bank.
+    pub number: u8,
+
+    /// Raw (decoded) program data.
+    pub data: Box<[u8]>,
+}
+
+impl ProgramDump {
+    /// Recognizes the given SysEx message `msg` (without start/end bytes) as a
+    /// program dump.  Returns `None` if the message is not a well-formed
+    /// program dump.
+    pub fn from_message(msg: &[u8]) -> Option<Self> {
+        let payload = match recognize_sysex(msg) {
+            Some((Opcode::Pgm, payload)) => payload,
+            _                            => return None,
+        };
+
+        if payload.len() != 2 + PROGRAM_7BIT_LEN {
+            return None
+        }
+
+        let (bank, number) = (payload[0], payload[1]);
+        if bank >= BANK_COUNT || number >= BANK_PROGRAMS {
+            return None
+        }
+
+        Some(Self { bank, number, data: decode_program(&payload[2..]) })
+    }
+
+    /// Gets the slot of the program as a (bank, number) pair.
+    #[inline]
+    pub fn slot(&self) -> (u8, u8) {
+        (self.bank, self.number)
+    }
+
+    /// Returns a copy of the dump that targets program `number` in `bank`, so
+    /// that sending it stores the program in that slot instead.
+    ///
+    /// Panics if `bank` or `number` is out of range.
+    pub fn with_slot(&self, bank: u8, number: u8) -> Self {
+        if bank >= BANK_COUNT || number >= BANK_PROGRAMS {
+            panic!("Program slot {}:{} is out of range.", bank, number);
+        }
+        Self { bank, number, data: self.data.clone() }
+    }
+
+    /// Builds the program dump message (with start/end bytes) for the dump.
+    pub fn to_message(&self) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(2 + PROGRAM_7BIT_LEN);
+        payload.push(self.bank);
+        payload.push(self.number);
+        encode_7bit(&self.data, &mut payload);
+        build_message(Opcode::Pgm, &payload)
+    }
+
+    /// Returns an edit buffer dump of the program, so that sending it loads
+    /// the program into edit buffer `buffer` without storing it.
+    ///
+    /// Panics if `buffer` is out of range.
+    pub fn to_edit_buffer(&self, buffer: u8) -> EditBufferDump {
+        check_buffer(buffer);
+        EditBufferDump { buffer, data: self.data.clone() }
+    }
+}
+
+/// A program edit buffer dump: the raw data of the program being edited in
+/// one of the edit buffers.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct EditBufferDump {
+    /// Edit buffer number: 0-15 for the channels of the current mix, or
+    /// `PROGRAM_MODE_BUFFER` for program mode.
+    pub buffer: u8,
+
+    /// Raw (decoded) program data.
+    pub data: Box<[u8]>,
+}
+
+impl EditBufferDump {
+    /// Recognizes the given SysEx message `msg` (without start/end bytes) as a
+    /// program edit buffer dump.  Returns `None` if the message is not a
+    /// well-formed program edit buffer dump.
+    pub fn from_message(msg: &[u8]) -> Option<Self> {
+        let payload = match recognize_sysex(msg) {
+            Some((Opcode::PgmEditBuf, payload)) => payload,
+            _                                   => return None,
+        };
+
+        if payload.len() != 1 + PROGRAM_7BIT_LEN {
+            return None
+        }
+
+        let buffer = payload[0];
+        if buffer >= EDIT_BUFFER_COUNT {
+            return None
+        }
+
+        Some(Self { buffer, data: decode_program(&payload[1..]) })
+    }
+
+    /// Returns a program dump of the edited program, so that sending it stores
+    /// the program as program `number` in `bank`.
+    ///
+    /// Panics if `bank` or `number` is out of range.
+    pub fn to_program(&self, bank: u8, number: u8) -> ProgramDump {
+        ProgramDump { bank: 0, number: 0, data: self.data.clone() }.with_slot(bank, number)
+    }
+
+    /// Builds the program edit buffer dump message (with start/end bytes) for
+    /// the dump.
+    pub fn to_message(&self) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(1 + PROGRAM_7BIT_LEN);
+        payload.push(self.buffer);
+        encode_7bit(&self.data, &mut payload);
+        build_message(Opcode::PgmEditBuf, &payload)
+    }
+}
+
+// Decodes 7-bit-encoded program data.
+fn decode_program(src: &[u8]) -> Box<[u8]> {
+    let mut data = Vec::with_capacity(PROGRAM_DATA_LEN);
+    decode_7bit(src, &mut data);
+    data.into_boxed_slice()
+}
+
+// Panics unless `buffer` is a program edit buffer.
+fn check_buffer(buffer: u8) {
+    if buffer >= EDIT_BUFFER_COUNT {
+        panic!("Edit buffer {} is out of range; there are {} buffers.", buffer, EDIT_BUFFER_COUNT);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Encodes a program dump message (without start/end bytes)
+    fn program_message(bank: u8, number: u8, data: &[u8]) -> Vec<u8> {
+        let mut msg = vec![0x00, 0x00, 0x0E, 0x1D, 0x00, bank, number];
+        encode_7bit(data, &mut msg);
+        msg
+    }
+
+    #[test]
+    fn from_message_ok() {
+        let data = (0..PROGRAM_DATA_LEN).map(|x| x as u8).collect::<Vec<_>>();
+        let msg  = program_message(1, 42, &data);
+
+        let dump = ProgramDump::from_message(&msg).unwrap();
+
+        assert_eq!(msg.len(), 7 + PROGRAM_7BIT_LEN);
+        assert_eq!(dump.slot(), (1, 42));
+        assert_eq!(&dump.data[..], &data[..]);
+    }
+
+    #[test]
+    fn from_message_bad_length() {
+        let mut msg = program_message(0, 0, &[0; PROGRAM_DATA_LEN]);
+        msg.pop();
+
+        assert_eq!(ProgramDump::from_message(&msg), None);
+    }
+
+    #[test]
+    fn from_message_bad_slot() {
+        let msg = program_message(16, 0, &[0; PROGRAM_DATA_LEN]);
+
+        assert_eq!(ProgramDump::from_message(&msg), None);
+    }
+
+    #[test]
+    fn from_message_other_opcode() {
+        let msg = [0x00, 0x00, 0x0E, 0x1D, 0x30, 0x00];
+
+        assert_eq!(ProgramDump::from_message(&msg), None);
+    }
+
+    #[test]
+    fn to_message_round_trip() {
+        let data = (0..PROGRAM_DATA_LEN).map(|x| (x * 3) as u8).collect::<Vec<_>>();
+        let msg  = program_message(2, 9, &data);
+        let dump = ProgramDump::from_message(&msg).unwrap();
+
+        let out = dump.to_message();
+
+        assert_eq!(&out[1..out.len() - 1], &msg[..]);
+    }
+
+    #[test]
+    fn with_slot() {
+        let msg  = program_message(2, 9, &[7; PROGRAM_DATA_LEN]);
+        let dump = ProgramDump::from_message(&msg).unwrap().with_slot(15, 0);
+        let out  = dump.to_message();
+
+        let moved = ProgramDump::from_message(&out[1..out.len() - 1]).unwrap();
+
+        assert_eq!(moved.slot(), (15, 0));
+        assert_eq!(moved.data, dump.data);
+    }
+
+    #[test]
+    #[should_panic]
+    fn with_slot_out_of_range() {
+        let msg = program_message(0, 0, &[0; PROGRAM_DATA_LEN]);
+        ProgramDump::from_message(&msg).unwrap().with_slot(16, 0);
+    }
+
+    #[test]
+    fn edit_buffer_to_program() {
+        let data = (0..PROGRAM_DATA_LEN).map(|x| (x * 7) as u8).collect::<Vec<_>>();
+        let mut msg = vec![0x00, 0x00, 0x0E, 0x1D, 0x02, PROGRAM_MODE_BUFFER];
+        encode_7bit(&data, &mut msg);
+
+        let edit = EditBufferDump::from_message(&msg).unwrap();
+        let out  = edit.to_program(3, 100).to_message();
+        let dump = ProgramDump::from_message(&out[1..out.len() - 1]).unwrap();
+
+        assert_eq!(edit.buffer, PROGRAM_MODE_BUFFER);
+        assert_eq!(dump.slot(), (3, 100));
+        assert_eq!(&dump.data[..], &data[..]);
+    }
+
+    #[test]
+    fn program_to_edit_buffer() {
+        let msg  = program_message(2, 9, &[5; PROGRAM_DATA_LEN]);
+        let dump = ProgramDump::from_message(&msg).unwrap();
+
+        let out  = dump.to_edit_buffer(4).to_message();
+        let edit = EditBufferDump::from_message(&out[1..out.len() - 1]).unwrap();
+
+        assert_eq!(out[5], 0x02);
+        assert_eq!(edit.buffer, 4);
+        assert_eq!(edit.data, dump.data);
+    }
+
+    #[test]
+    fn edit_buffer_bad_buffer() {
+        let mut msg = vec![0x00, 0x00, 0x0E, 0x1D, 0x02, EDIT_BUFFER_COUNT];
+        encode_7bit(&[0; PROGRAM_DATA_LEN], &mut msg);
+
+        assert_eq!(EditBufferDump::from_message(&msg), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn to_edit_buffer_out_of_range() {
+        let msg = program_message(0, 0, &[0; PROGRAM_DATA_LEN]);
+        ProgramDump::from_message(&msg).unwrap().to_edit_buffer(EDIT_BUFFER_COUNT);
+    }
+}
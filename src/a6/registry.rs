@@ -0,0 +1,212 @@
+// This file is part of a6-tools.
+// Copyright (C) 2017 Jeffrey Sharp
+//
+// a6-tools is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License,
+// or (at your option) any later version.
+//
+// a6-tools is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See
+// the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with a6-tools.  If not, see <http://www.gnu.org/licenses/>.
+
+
+use a6::{frame_message, recognize_sysex_detailed, A6Message, Opcode, DATA_POS};
+use a6::error::MessageError;
+
+#[cfg(not(feature = "std"))]
+use heap::*;
+
+/// An opcode that this crate does not know, described by a downstream crate
+/// for registration with an `OpcodeRegistry`.
+#[derive(Clone, Copy, Debug)]
+pub struct CustomOpcode {
+    /// Numeric value of the opcode.
+    pub value: u8,
+
+    /// Name of the opcode, for display.
+    pub name: &'static str,
+
+    /// Length of the data that follows the opcode, or `None` if it varies.
+    pub payload_len: Option<usize>,
+
+    /// Function that returns `true` if a payload is valid, or `None` to accept
+    /// any payload of the right length.
+    pub check: Option<fn(&[u8]) -> bool>,
+}
+
+/// A message recognized by `OpcodeRegistry::parse`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Recognized<'a> {
+    /// A message with an opcode known to this crate.
+    Known(A6Message<'a>),
+
+    /// A message with a registered custom opcode, with its raw 7-bit payload.
+    Custom { value: u8, name: &'static str, payload: &'a [u8] },
+}
+
+/// A set of custom opcodes that extends message recognition beyond the
+/// opcodes known to this crate.
+///
+/// Custom opcodes are honored only by the methods of `OpcodeRegistry` and by
+/// `annotate_update_with`.  Other functions recognize only known opcodes.
+#[derive(Clone, Default, Debug)]
+pub struct OpcodeRegistry {
+    custom: Vec<CustomOpcode>,
+}
+
+impl OpcodeRegistry {
+    /// Creates an empty `OpcodeRegistry`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the given custom `opcode`.
+    ///
+    /// Panics if the opcode value is known to this crate or is already
+    /// registered, or if it is not a 7-bit value.
+    pub fn register(&mut self, opcode: CustomOpcode) -> &mut Self {
+        if opcode.value > 0x7F {
+            panic!("Opcode {:02X} is not a 7-bit value.", opcode.value);
+        }
+        if let Some(op) = Opcode::from_u8(opcode.value) {
+            panic!("Opcode {:02X} is already known as {}.", opcode.value, op.name());
+        }
+        if let Some(op) = self.get(opcode.value) {
+            panic!("Opcode {:02X} is already registered as {}.", opcode.value, op.name);
+        }
+        self.custom.push(opcode);
+        self
+    }
+
+    /// Gets the registered custom opcode with the given `value`, if any.
+    pub fn get(&self, value: u8) -> Option<&CustomOpcode> {
+        self.custom.iter().find(|op| op.value == value)
+    }
+
+    /// Parses the given SysEx message `msg` (without start/end bytes) as
+    /// `A6Message::parse` does, but also recognizes registered custom opcodes,
+    /// checking their payloads as registered.
+    pub fn parse<'a>(&self, msg: &'a [u8]) -> Result<Recognized<'a>, MessageError> {
+        let (value, payload) = match recognize_sysex_detailed(msg) {
+            Err(MessageError::UnknownOpcode { opcode }) => (opcode, &msg[DATA_POS..]),
+            Err(e)                                      => return Err(e),
+            Ok(_)                                       => {
+                return A6Message::parse(msg).map(Recognized::Known)
+            },
+        };
+
+        let op = self.get(value).ok_or(MessageError::UnknownOpcode { opcode: value })?;
+
+        let len_ok   = op.payload_len.map_or(true, |n| payload.len() == n);
+        let check_ok = op.check.map_or(true, |f| f(payload));
+        if !len_ok || !check_ok {
+            return Err(MessageError::BadCustomPayload { opcode: value })
+        }
+
+        Ok(Recognized::Custom { value, name: op.name, payload })
+    }
+
+    /// Builds a complete SysEx message (with start/end bytes) addressed to the
+    /// A6, having the registered custom opcode `value` and the given
+    /// `payload`, as `build_message` does for known opcodes.
+    ///
+    /// Panics if `value` is not registered, or if any byte of `payload` is not
+    /// a 7-bit value.
+    pub fn build_message(&self, value: u8, payload: &[u8]) -> Vec<u8> {
+        if self.get(value).is_none() {
+            panic!("Opcode {:02X} is not registered.", value);
+        }
+        frame_message(value, payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> OpcodeRegistry {
+        let mut registry = OpcodeRegistry::new();
+        registry.register(CustomOpcode {
+            value:       0x10,
+            name:        "Probe",
+            payload_len: Some(1),
+            check:       Some(|p| p[0] < 0x10),
+        });
+        registry
+    }
+
+    // Strips start/end bytes
+    fn inner(msg: &[u8]) -> &[u8] {
+        &msg[1..msg.len() - 1]
+    }
+
+    #[test]
+    fn parse_custom() {
+        let registry = registry();
+        let msg      = registry.build_message(0x10, &[0x05]);
+
+        assert_eq!(msg, [0xF0, 0x00, 0x00, 0x0E, 0x1D, 0x10, 0x05, 0xF7]);
+        assert_eq!(
+            registry.parse(inner(&msg)),
+            Ok(Recognized::Custom { value: 0x10, name: "Probe", payload: &[0x05] })
+        );
+    }
+
+    #[test]
+    fn parse_custom_invalid() {
+        let registry = registry();
+
+        for payload in &[&[0x55][..], &[0x01, 0x02][..]] {
+            let msg = registry.build_message(0x10, payload);
+            assert_eq!(
+                registry.parse(inner(&msg)),
+                Err(MessageError::BadCustomPayload { opcode: 0x10 })
+            );
+        }
+    }
+
+    #[test]
+    fn parse_known_and_unknown() {
+        let registry = registry();
+
+        let msg = ::a6::build_message(Opcode::Edit, &[0x01]);
+        assert_eq!(
+            registry.parse(inner(&msg)),
+            Ok(Recognized::Known(A6Message::Other {
+                opcode: Opcode::Edit, payload: (&[0x01][..]).into()
+            }))
+        );
+
+        assert_eq!(
+            registry.parse(&[0x00, 0x00, 0x0E, 0x1D, 0x11]),
+            Err(MessageError::UnknownOpcode { opcode: 0x11 })
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn register_known() {
+        OpcodeRegistry::new().register(CustomOpcode {
+            value: 0x0E, name: "Clash", payload_len: None, check: None,
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "not a 7-bit value")]
+    fn build_message_8bit_payload() {
+        registry().build_message(0x10, &[0x80]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn register_twice() {
+        registry().register(CustomOpcode {
+            value: 0x10, name: "Again", payload_len: None, check: None,
+        });
+    }
+}
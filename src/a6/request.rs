@@ -0,0 +1,117 @@
+// This file is part of a6-tools.
+// Copyright (C) 2017 Jeffrey Sharp
+//
+// a6-tools is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License,
+// or (at your option) any later version.
+//
+// a6-tools is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See
+// the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with a6-tools.  If not, see <http://www.gnu.org/licenses/>.
+
+
+use a6::{build_message, Opcode};
+use a6::program::{BANK_COUNT, BANK_PROGRAMS};
+
+#[cfg(not(feature = "std"))]
+use heap::*;
+
+/// Builds a request for the program dump of program `number` in `bank`.
+///
+/// Panics if `bank` or `number` is out of range.
+pub fn request_program(bank: u8, number: u8) -> Vec<u8> {
+    check_program_slot(bank, number);
+    build_message(Opcode::PgmReq, &[bank, number])
+}
+
+/// Builds a request for the program dumps of all programs in `bank`.
+///
+/// Panics if `bank` is out of range.
+pub fn request_program_bank(bank: u8) -> Vec<u8> {
+    check_program_slot(bank, 0);
+    build_message(Opcode::PgmBankReq, &[bank])
+}
+
+/// Builds a request for the mix dump of mix `number` in `bank`.
+///
+/// Panics if `bank` or `number` is not a 7-bit value.
+pub fn request_mix(bank: u8, number: u8) -> Vec<u8> {
+    check_7bit(bank, "bank");
+    check_7bit(number, "number");
+    build_message(Opcode::MixReq, &[bank, number])
+}
+
+/// Builds a request for the mix dumps of all mixes in `bank`.
+///
+/// Panics if `bank` is not a 7-bit value.
+pub fn request_mix_bank(bank: u8) -> Vec<u8> {
+    check_7bit(bank, "bank");
+    build_message(Opcode::MixBankReq, &[bank])
+}
+
+/// Builds a request for all programs, mixes, and global data.
+pub fn request_all() -> Vec<u8> {
+    build_message(Opcode::AllReq, &[])
+}
+
+// Panics unless `bank` and `number` address a program.
+fn check_program_slot(bank: u8, number: u8) {
+    if bank >= BANK_COUNT {
+        panic!("Program bank {} is out of range; there are {} banks.", bank, BANK_COUNT);
+    }
+    if number >= BANK_PROGRAMS {
+        panic!("Program number {} is out of range; banks have {} programs.", number, BANK_PROGRAMS);
+    }
+}
+
+// Panics unless `value` fits in a data byte.
+fn check_7bit(value: u8, what: &str) {
+    if value > 0x7F {
+        panic!("Mix {} {} is not a 7-bit value.", what, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use a6::parse_sysex;
+
+    // Strips start/end bytes and parses the message
+    fn parse(msg: &[u8]) -> (Opcode, &[u8]) {
+        parse_sysex(&msg[1..msg.len() - 1]).unwrap()
+    }
+
+    #[test]
+    fn requests() {
+        assert_eq!(parse(&request_program(15, 127)), (Opcode::PgmReq,     &[15, 127][..]));
+        assert_eq!(parse(&request_program_bank(3)),  (Opcode::PgmBankReq, &[3][..]));
+        assert_eq!(parse(&request_mix(1, 64)),       (Opcode::MixReq,     &[1, 64][..]));
+        assert_eq!(parse(&request_mix_bank(2)),      (Opcode::MixBankReq, &[2][..]));
+        assert_eq!(parse(&request_all()),            (Opcode::AllReq,     &[][..]));
+
+        assert_eq!(request_all(), [0xF0, 0x00, 0x00, 0x0E, 0x1D, 0x0C, 0xF7]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn request_program_bad_bank() {
+        request_program(16, 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn request_program_bad_number() {
+        request_program(0, 128);
+    }
+
+    #[test]
+    #[should_panic]
+    fn request_mix_bad_number() {
+        request_mix(0, 0x80);
+    }
+}
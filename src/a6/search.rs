@@ -0,0 +1,173 @@
+// This file is part of a6-tools.
+// Copyright (C) 2017 Jeffrey Sharp
+//
+// a6-tools is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License,
+// or (at your option) any later version.
+//
+// a6-tools is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See
+// the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with a6-tools.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::cmp;
+
+/// A name found by `find_names`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct NameMatch {
+    /// Index of the name in the searched sequence.
+    pub index: usize,
+
+    /// Distance of the name from the query.  Lower is better; 0 is an exact
+    /// match of every query word.
+    pub score: usize,
+}
+
+/// Searches the given `names` for those that match the given `query`
+/// approximately, ignoring case.  Returns matches ordered best first, and by
+/// index among equally good matches.
+///
+/// Each word of `query` must match some word of a name, either whole or as an
+/// abbreviation (prefix), within an edit distance of one typo per three
+/// characters.  The score of a name is the sum of its word distances, plus one
+/// for each word matched only as an abbreviation.
+pub fn find_names<'a, I>(query: &str, names: I) -> Vec<NameMatch>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let query = words(query);
+    if query.is_empty() {
+        return vec![]
+    }
+
+    let mut matches = names
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, name)| {
+            let name = words(name);
+            let mut score = 0;
+            for q in &query {
+                score += name.iter().filter_map(|w| word_distance(q, w)).min()?;
+            }
+            Some(NameMatch { index, score })
+        })
+        .collect::<Vec<_>>();
+
+    matches.sort_by_key(|m| (m.score, m.index));
+    matches
+}
+
+// Splits the given `text` into lowercase words.
+fn words(text: &str) -> Vec<Vec<char>> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.chars().flat_map(char::to_lowercase).collect())
+        .collect()
+}
+
+// Computes the distance of `word` from the query word `q`, or `None` if they
+// do not match within the allowed count of typos.
+fn word_distance(q: &[char], word: &[char]) -> Option<usize> {
+    let max = q.len() / 3;
+
+    // Whole word
+    let whole = Some(levenshtein(q, word)).filter(|&d| d <= max);
+
+    // Abbreviation: q matches a prefix of word
+    let prefix = if word.len() > q.len() {
+        Some(levenshtein(q, &word[..q.len()])).filter(|&d| d <= max).map(|d| d + 1)
+    } else {
+        None
+    };
+
+    match (whole, prefix) {
+        (Some(a), Some(b)) => Some(cmp::min(a, b)),
+        (a,       None   ) => a,
+        (None,    b      ) => b,
+    }
+}
+
+/// Computes the Levenshtein distance between the given character sequences:
+/// the minimum count of single-character insertions, deletions, and
+/// substitutions that transforms `a` into `b`.
+pub fn levenshtein(a: &[char], b: &[char]) -> usize {
+    // Single-row dynamic programming
+    let mut row = (0..b.len() + 1).collect::<Vec<_>>();
+
+    for (i, ca) in a.iter().enumerate() {
+        let mut diag = row[0];
+        row[0] = i + 1;
+
+        for (j, cb) in b.iter().enumerate() {
+            let sub = diag + if ca == cb { 0 } else { 1 };
+            diag = row[j + 1];
+            row[j + 1] = cmp::min(sub, cmp::min(row[j], row[j + 1]) + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(s: &str) -> Vec<char> {
+        s.chars().collect()
+    }
+
+    #[test]
+    fn levenshtein_fn() {
+        assert_eq!(levenshtein(&chars(""),        &chars("")),        0);
+        assert_eq!(levenshtein(&chars("abc"),     &chars("")),        3);
+        assert_eq!(levenshtein(&chars(""),        &chars("abc")),     3);
+        assert_eq!(levenshtein(&chars("kitten"),  &chars("sitting")), 3);
+        assert_eq!(levenshtein(&chars("jupitr"),  &chars("jupiter")), 1);
+        assert_eq!(levenshtein(&chars("flaw"),    &chars("lawn")),    2);
+    }
+
+    const NAMES: &[&str] = &[
+        "Saw Lead",
+        "Jupiter Pad 2",
+        "Jupiter Brass",
+        "Pad Sweep",
+        "JUP PAD",
+    ];
+
+    #[test]
+    fn find_typo() {
+        let found = find_names("jupitr pad", NAMES.iter().cloned());
+
+        assert_eq!(found, vec![
+            NameMatch { index: 1, score: 1 },
+        ]);
+    }
+
+    #[test]
+    fn find_abbreviation() {
+        let found = find_names("jup", NAMES.iter().cloned());
+
+        assert_eq!(found, vec![
+            NameMatch { index: 4, score: 0 },
+            NameMatch { index: 1, score: 1 },
+            NameMatch { index: 2, score: 1 },
+        ]);
+    }
+
+    #[test]
+    fn find_case_insensitive_exact() {
+        let found = find_names("PAD sweep", NAMES.iter().cloned());
+
+        assert_eq!(found, vec![NameMatch { index: 3, score: 0 }]);
+    }
+
+    #[test]
+    fn find_none() {
+        assert_eq!(find_names("organ", NAMES.iter().cloned()), vec![]);
+        assert_eq!(find_names("",      NAMES.iter().cloned()), vec![]);
+    }
+}
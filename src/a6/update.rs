@@ -14,9 +14,17 @@
 // You should have received a copy of the GNU General Public License
 // along with a6-tools.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::cell::{Cell, RefCell};
+use std::io::{self, BufRead};
+
+use a6::{recognize_sysex, Opcode, DATA_POS};
 use a6::block::*;
 use a6::error::BlockDecodeError;
 use a6::error::BlockDecodeError::*;
+use a6::image::Image;
+use io::ReadExt;
+use sysex::{decode_7bit, read_sysex, read_sysex_events, scan_sysex, SysExEvent};
+use sysex::SysExReadError::Overflow;
 use util::{BoolArray, Handler};
 
 /// Constructs a binary image from A6 OS/bootloader update blocks.
@@ -28,10 +36,87 @@ pub struct BlockDecoder<H> where H: Handler<BlockDecodeError> {
     /// Maximum image size.
     capacity: u32,
 
+    /// Whether to ignore messages that are not update blocks.
+    tolerant: bool,
+
+    /// Counts of messages and blocks decoded so far.
+    stats: DecodeStats,
+
+    /// Expected value of final-block padding bytes, if verified.
+    pad: Option<u8>,
+
+    /// Kind of the first update block decoded, or the kind selected.
+    kind: Option<BlockKind>,
+
+    /// Whether to ignore update blocks not of `kind`.
+    select: bool,
+
     /// Handler for error conditions.
     handler: H,
 }
 
+/// Counts of messages and blocks seen by a `BlockDecoder`.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct DecodeStats {
+    /// Count of SysEx messages decoded, including non-block messages.
+    pub messages: usize,
+
+    /// Count of distinct blocks written to the image.
+    pub blocks: usize,
+
+    /// Count of blocks that duplicated a block written already.
+    pub duplicates: usize,
+
+    /// Count of messages ignored in tolerant mode.
+    pub ignored: usize,
+}
+
+/// Results of verifying an image decoded from update blocks.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct VerificationReport {
+    /// Header of the first block decoded, or `None` if no block was decoded.
+    pub header: Option<BlockHeader>,
+
+    /// Kind of the update blocks decoded, or `None` if no block was decoded.
+    pub kind: Option<BlockKind>,
+
+    /// Conditions reported during decoding and verification, in order.
+    pub errors: Vec<BlockDecodeError>,
+}
+
+impl VerificationReport {
+    /// Returns `true` if the image is complete and its checksum matches the
+    /// one in the block headers.
+    pub fn is_valid(&self) -> bool {
+        self.header.is_some() && !self.errors.iter().any(|e| match *e {
+            MissingBlock     { .. }
+          | ChecksumMismatch { .. }
+          | MixedBlockKinds  { .. } => true,
+            _                       => false,
+        })
+    }
+}
+
+/// A verified OS or bootloader image decoded by `decode_update_file`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct UpdateImage {
+    /// Decoded image.
+    pub image: Image,
+
+    /// Header of the first block decoded.
+    pub header: BlockHeader,
+
+    /// Kind of the update blocks decoded.
+    pub kind: BlockKind,
+
+    /// Counts of messages and blocks seen.
+    pub stats: DecodeStats,
+
+    /// Conditions reported during decoding that do not invalidate the image,
+    /// such as duplicate blocks, in order.
+    pub warnings: Vec<BlockDecodeError>,
+}
+
 #[derive(Clone)]
 struct BlockDecoderState {
     /// First block metadata.
@@ -53,7 +138,158 @@ impl<H> BlockDecoder<H> where H: Handler<BlockDecodeError> {
                 capacity, IMAGE_MAX_BYTES
             );
         }
-        Self { state: None, capacity, handler }
+        Self {
+            state: None, capacity, tolerant: false, stats: DecodeStats::default(),
+            pad: None, kind: None, select: false, handler,
+        }
+    }
+
+    /// Sets whether the decoder ignores messages that are not update blocks.
+    ///
+    /// In tolerant mode, `decode_message` and `decode_sysex` count such
+    /// messages (see `ignored_count`) rather than reporting them to the
+    /// handler as `UnexpectedMessage` errors.
+    pub fn set_tolerant(&mut self, tolerant: bool) {
+        self.tolerant = tolerant;
+    }
+
+    /// Sets the expected value of the padding bytes that follow the image data
+    /// in the final block, or `None` to ignore their values.
+    ///
+    /// When set, each padding byte that differs from `pad` is reported to the
+    /// handler as an `InvalidPadding` error.
+    pub fn set_strict_padding(&mut self, pad: Option<u8>) {
+        self.pad = pad;
+    }
+
+    /// Sets the kind of update block to decode, or `None` to decode whichever
+    /// kind occurs first.
+    ///
+    /// When set, blocks of the other kind are handled as messages that are not
+    /// update blocks.  Otherwise, blocks of a kind other than the first are
+    /// reported to the handler as `MixedBlockKinds` errors and skipped.
+    pub fn set_kind(&mut self, kind: Option<BlockKind>) {
+        self.kind   = kind;
+        self.select = kind.is_some();
+    }
+
+    /// Gets the kind of the update blocks decoded, or the kind set by
+    /// `set_kind`, if any.
+    #[inline]
+    pub fn kind(&self) -> Option<BlockKind> {
+        self.kind
+    }
+
+    /// Gets the count of messages ignored in tolerant mode.
+    #[inline]
+    pub fn ignored_count(&self) -> usize {
+        self.stats.ignored
+    }
+
+    /// Gets the counts of messages and blocks decoded so far.
+    #[inline]
+    pub fn stats(&self) -> DecodeStats {
+        self.stats
+    }
+
+    /// Gets the header of the first block decoded, if any.
+    #[inline]
+    pub fn header(&self) -> Option<&BlockHeader> {
+        self.state.as_ref().map(|s| &s.header)
+    }
+
+    /// Consumes the decoder, returning its handler.
+    #[inline]
+    pub fn into_handler(self) -> H {
+        self.handler
+    }
+
+    /// Decodes update blocks from the given SysEx `input` stream, adding their
+    /// data to the image in progress.  Blocks can occur in any order and can
+    /// be interleaved with other messages and non-SysEx bytes.
+    ///
+    /// Non-SysEx bytes and malformed SysEx messages are skipped silently.
+    /// Messages other than update blocks are handled as in `decode_message`.
+    pub fn decode_sysex<R: BufRead>(&mut self, input: &mut R) -> io::Result<Result<(), ()>> {
+        let more = read_sysex_events(input, MESSAGE_CAP, |event| self.decode_event(&event))?;
+
+        Ok(if more { Ok(()) } else { Err(()) })
+    }
+
+    // Decodes a SysEx event read by `read_sysex_events`.  Returns `false` to
+    // stop reading.
+    fn decode_event(&mut self, event: &SysExEvent) -> bool {
+        match *event {
+            SysExEvent::Message { ref msg, .. } => {
+                self.decode_message(msg).is_ok()
+            },
+            SysExEvent::Error { len, err: Overflow, .. } => {
+                // Too long to be an update block
+                self.stats.messages += 1;
+                self.ignore(len).is_ok()
+            },
+            _ => true,
+        }
+    }
+
+    /// Decodes update blocks from the given in-memory SysEx `bytes`, such as
+    /// a memory-mapped file, as `decode_sysex` does for a stream.  Messages are
+    /// decoded in place, without copying them into a read buffer.
+    pub fn decode_bytes(&mut self, bytes: &[u8]) -> Result<(), ()> {
+        for event in scan_sysex(bytes) {
+            if let SysExEvent::Message { msg, len, .. } = event {
+                if msg.len() > MESSAGE_CAP {
+                    // Too long to be an update block
+                    self.stats.messages += 1;
+                    self.ignore(len)?;
+                } else {
+                    self.decode_message(&msg)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Decodes the given SysEx message `msg` (without start/end bytes), adding
+    /// its data to the image in progress if it is an update block.
+    pub fn decode_message(&mut self, msg: &[u8]) -> Result<(), ()> {
+        self.stats.messages += 1;
+
+        let (kind, data) = match recognize_sysex(msg) {
+            Some((opcode, data)) => match BlockKind::from_opcode(opcode) {
+                Some(kind) => (kind, data),
+                None       => return self.ignore(msg.len()),
+            },
+            None => return self.ignore(msg.len()),
+        };
+
+        match self.kind {
+            None => {
+                self.kind = Some(kind);
+            },
+            Some(expected) if expected != kind => {
+                if self.select {
+                    return self.ignore(msg.len())
+                }
+                return self.handler.on(&MixedBlockKinds { actual: kind, expected })
+            },
+            _ => (),
+        }
+
+        let mut block = Vec::with_capacity(BLOCK_HEAD_LEN + BLOCK_DATA_LEN);
+        decode_7bit(data, &mut block);
+        self.decode_block(&block)
+    }
+
+    // Counts a non-block message of the given length in tolerant mode, or
+    // reports it to the handler otherwise.
+    fn ignore(&mut self, len: usize) -> Result<(), ()> {
+        if self.tolerant {
+            self.stats.ignored += 1;
+            Ok(())
+        } else {
+            self.handler.on(&UnexpectedMessage { len })
+        }
     }
 
     /// Decodes the given `block`, adding its data to the image in progress.
@@ -69,22 +305,55 @@ impl<H> BlockDecoder<H> where H: Handler<BlockDecodeError> {
         let state = match self.state {
             None => {
                 // Initialize decoder state from first block header
-                block.header.check_len(&self.handler)?;
+                match block.header.check_len(&self.handler) {
+                    Ok(())     => (),
+                    Err(true)  => return Ok(()),    // continue
+                    Err(false) => return Err(()),   // abort
+                }
                 self.state = Some(BlockDecoderState::new(block.header));
                 self.state.as_mut().unwrap()
             },
             Some(ref mut state) => {
                 // Check that block's header matches the first block's header
-                block.header.check_match(&state.header, &self.handler)?;
+                match block.header.check_match(&state.header, &self.handler) {
+                    Ok(())     => (),
+                    Err(true)  => return Ok(()),    // continue
+                    Err(false) => return Err(()),   // abort
+                }
                 state
             },
         };
 
+        // Check block index
+        if !block.header.check_block_index(&self.handler)? {
+            return Ok(());
+        }
+
         // Write block data
         if state.write_block(block.header.block_index, block.data) {
+            self.stats.duplicates += 1;
             self.handler.on(&DuplicateBlock {
                 index: block.header.block_index,
             })?;
+        } else {
+            self.stats.blocks += 1;
+        }
+
+        // Check final block padding
+        if let Some(pad) = state.final_block_padding(block.header.block_index, block.data) {
+            let offset = state.header.length;
+
+            self.handler.on(&UnusedFinalBytes { count: pad.len() })?;
+
+            if let Some(expected) = self.pad {
+                for (i, &actual) in pad.iter().enumerate() {
+                    if actual != expected {
+                        self.handler.on(&InvalidPadding {
+                            actual, expected, offset: offset + i as u32,
+                        })?;
+                    }
+                }
+            }
         }
 
         Ok(())
@@ -120,12 +389,130 @@ impl<H> BlockDecoder<H> where H: Handler<BlockDecodeError> {
     }
 }
 
-fn checksum(bytes: &[u8]) -> u32 {
-    let mut sum = 0u32;
-    for &b in bytes {
-        sum = sum.wrapping_add(b as u32);
+/// Decodes an OS or bootloader image from the update blocks in the given SysEx
+/// `input` stream, ignoring other messages.
+///
+/// Returns the image along with a report of the conditions found while
+/// decoding and verifying it, and counts of the messages and blocks seen.  The
+/// image is returned even if verification fails; missing blocks read as zeros.
+pub fn extract_image<R: BufRead>(input: &mut R)
+    -> io::Result<(Image, VerificationReport, DecodeStats)>
+{
+    let mut decoder = BlockDecoder::new(IMAGE_MAX_BYTES, Collector::default());
+    decoder.set_tolerant(true);
+    decoder.decode_sysex(input)?.expect("Collector does not abort");
+
+    Ok(finish(decoder))
+}
+
+/// Decodes separate OS and bootloader images from the update blocks in the
+/// given SysEx `input` stream, ignoring other messages.
+///
+/// Returns an image, report, and counts as `extract_image` does for each kind
+/// of update block found: OS first, then bootloader.  The `kind` of each
+/// report identifies its image.
+pub fn extract_images<R: BufRead>(input: &mut R)
+    -> io::Result<Vec<(Image, VerificationReport, DecodeStats)>>
+{
+    let mut decoders = [BlockKind::Os, BlockKind::Boot].iter().map(|&kind| {
+        let mut decoder = BlockDecoder::new(IMAGE_MAX_BYTES, Collector::default());
+        decoder.set_tolerant(true);
+        decoder.set_kind(Some(kind));
+        decoder
+    }).collect::<Vec<_>>();
+
+    // Feed each decoder until it stops, and read until all decoders stop
+    let mut more = vec![true; decoders.len()];
+
+    read_sysex_events(input, MESSAGE_CAP, |event| {
+        for (decoder, more) in decoders.iter_mut().zip(&mut more) {
+            if *more { *more = decoder.decode_event(&event) }
+        }
+        more.contains(&true)
+    })?;
+
+    Ok(decoders.into_iter().filter(|d| d.header().is_some()).map(finish).collect())
+}
+
+/// Decodes and verifies an OS or bootloader image from the update blocks in the
+/// given SysEx `input` stream, such as a `.syx` update file, ignoring other
+/// messages.
+///
+/// Returns the image if it is complete and its checksum matches, or otherwise
+/// the report of the conditions that invalidate it.
+pub fn decode_update_file<R: BufRead>(input: &mut R)
+    -> io::Result<Result<UpdateImage, VerificationReport>>
+{
+    let (image, report, stats) = extract_image(input)?;
+
+    if !report.is_valid() {
+        return Ok(Err(report))
+    }
+
+    Ok(Ok(UpdateImage {
+        image,
+        header:   report.header.expect("valid report has a header"),
+        kind:     report.kind  .expect("valid report has a kind"),
+        stats,
+        warnings: report.errors,
+    }))
+}
+
+// Validates the image of the given `decoder` and returns it with its report
+// and counts.
+fn finish(decoder: BlockDecoder<Collector>) -> (Image, VerificationReport, DecodeStats) {
+    let image  = decoder.image().unwrap_or(&[]).to_vec();
+    let image  = Image::new(image).expect("image exceeds maximum length");
+    let header = decoder.header().cloned();
+    let kind   = header.and(decoder.kind());
+    let stats  = decoder.stats();
+    let errors = decoder.into_handler().0.into_inner();
+
+    (image, VerificationReport { header, kind, errors }, stats)
+}
+
+// Handler that records every condition and continues.
+#[derive(Clone, Default)]
+struct Collector(RefCell<Vec<BlockDecodeError>>);
+
+impl Handler<BlockDecodeError> for Collector {
+    fn on(&self, event: &BlockDecodeError) -> Result<(), ()> {
+        self.0.borrow_mut().push(*event);
+        Ok(())
     }
-    sum
+}
+
+// Maximum length of SysEx messages read by the decoder.  Longer messages are
+// reported as overflows, which is sufficient to reject them as blocks.
+const MESSAGE_CAP: usize = DATA_POS + BLOCK_7BIT_LEN;
+
+/// Reads the firmware version from the first OS or bootloader update block in
+/// the given SysEx `input` stream.  Returns `None` if the stream contains no
+/// update block.
+pub fn read_update_version<R: BufRead>(input: &mut R) -> io::Result<Option<u32>> {
+    let version = Cell::new(None);
+
+    read_sysex(
+        input, MESSAGE_CAP,
+        |_, msg| {
+            let data = match recognize_sysex(msg) {
+                Some((Opcode::OsBlock,   data)) => data,
+                Some((Opcode::BootBlock, data)) => data,
+                _                               => return true,
+            };
+
+            let mut bytes = vec![];
+            decode_7bit(data, &mut bytes);
+
+            match (&bytes[..]).read_u32() {
+                Ok(v)  => { version.set(Some(v)); false },
+                Err(_) => true,
+            }
+        },
+        |_, _, _| true,
+    )?;
+
+    Ok(version.get())
 }
 
 impl BlockDecoderState {
@@ -153,6 +540,19 @@ impl BlockDecoderState {
         self.block_map.first_false().map(|v| v as u16)
     }
 
+    /// Returns the padding bytes beyond the image length in the given block
+    /// `data`, if the given block `index` is of the final block and the image
+    /// length is not a multiple of the block length.
+    fn final_block_padding<'a>(&self, index: u16, data: &'a [u8]) -> Option<&'a [u8]> {
+        let used = self.header.length as usize % BLOCK_DATA_LEN;
+
+        if used != 0 && index + 1 == self.header.block_count {
+            Some(&data[used..])
+        } else {
+            None
+        }
+    }
+
     /// Writes the given block `data` at the given block `index`.  Returns `true`
     /// if the block has been written already, or `false` otherwise.
     fn write_block(&mut self, index: u16, data: &[u8]) -> bool {
@@ -176,6 +576,437 @@ mod tests {
         })
     }
 
+    use sysex::encode_7bit;
+
+    // Image of 3 blocks; final block partially used
+    fn test_image() -> Vec<u8> {
+        (0..600).map(|x| (x * 7) as u8).collect()
+    }
+
+    // Encodes the block at `index` of `image` as a SysEx message
+    fn block_message(image: &[u8], index: u16) -> Vec<u8> {
+        let mut block = vec![];
+        block.extend_from_slice(&[0x00, 0x00, 0x4E, 0x89]);                 // version
+        block.extend_from_slice(&checksum(image).to_be_bytes());            // checksum
+        block.extend_from_slice(&(image.len() as u32).to_be_bytes());       // length
+        block.extend_from_slice(&block_count_for(image.len() as u32).to_be_bytes());
+        block.extend_from_slice(&index.to_be_bytes());
+
+        let range = block_range(index);
+        let start = range.start.min(image.len());
+        let end   = range.end  .min(image.len());
+        block.extend_from_slice(&image[start..end]);
+        block.resize(BLOCK_HEAD_LEN + BLOCK_DATA_LEN, 0);
+
+        let mut msg = b"\xF0\x00\x00\x0E\x1D\x30".to_vec();
+        encode_7bit(&block, &mut msg);
+        msg.push(0xF7);
+        msg
+    }
+
+    // Encodes the block at `index` of `image` as a bootloader block message
+    fn boot_block_message(image: &[u8], index: u16) -> Vec<u8> {
+        let mut msg = block_message(image, index);
+        msg[5] = 0x3F;
+        msg
+    }
+
+    // A program dump, which is not an update block
+    fn program_message() -> Vec<u8> {
+        let mut msg = b"\xF0\x00\x00\x0E\x1D\x00\x00\x01".to_vec();
+        msg.extend(vec![0x55; 2341]);
+        msg.push(0xF7);
+        msg
+    }
+
+    #[test]
+    fn decode_sysex_in_order() {
+        let image = test_image();
+        let mut input = vec![];
+        for i in 0..3 {
+            input.extend(block_message(&image, i));
+        }
+
+        let handler = vec![
+            ( UnusedFinalBytes { count: 168 }, Ok(()) ),
+        ];
+        let mut decoder = BlockDecoder::new(IMAGE_MAX_BYTES, handler);
+
+        assert_eq!(decoder.decode_sysex(&mut &input[..]).unwrap(), Ok(()));
+        assert_eq!(decoder.image().unwrap(), &image[..]);
+    }
+
+    #[test]
+    fn decode_sysex_interleaved_tolerant() {
+        let image = test_image();
+        let mut input = vec![];
+        input.extend_from_slice(b"junk");
+        input.extend(block_message(&image, 2));
+        input.extend(program_message());
+        input.extend_from_slice(b"\xF0\x43\x10\x4C\xF7");  // other vendor
+        input.extend(block_message(&image, 0));
+        input.extend_from_slice(b"\xF8\x90\x40\x7F");       // clock, note on
+        input.extend(block_message(&image, 1));
+        input.extend(program_message());
+
+        let handler = vec![
+            ( UnusedFinalBytes { count: 168 }, Ok(()) ),
+        ];
+        let mut decoder = BlockDecoder::new(IMAGE_MAX_BYTES, handler);
+        decoder.set_tolerant(true);
+
+        assert_eq!(decoder.decode_sysex(&mut &input[..]).unwrap(), Ok(()));
+        assert_eq!(decoder.image().unwrap(), &image[..]);
+        assert_eq!(decoder.ignored_count(), 3);
+    }
+
+    #[test]
+    fn decode_sysex_interleaved_strict() {
+        let image = test_image();
+        let mut input = vec![];
+        input.extend(block_message(&image, 1));
+        input.extend_from_slice(b"\xF0\x43\x10\x4C\xF7");
+        input.extend(block_message(&image, 0));
+        input.extend(program_message());
+        input.extend(block_message(&image, 2));
+
+        let handler = vec![
+            ( UnexpectedMessage { len:    3 }, Ok(()) ),
+            ( UnexpectedMessage { len: 2350 }, Ok(()) ),
+            ( UnusedFinalBytes  { count: 168 }, Ok(()) ),
+        ];
+        let mut decoder = BlockDecoder::new(IMAGE_MAX_BYTES, handler);
+
+        assert_eq!(decoder.decode_sysex(&mut &input[..]).unwrap(), Ok(()));
+        assert_eq!(decoder.image().unwrap(), &image[..]);
+        assert_eq!(decoder.ignored_count(), 0);
+    }
+
+    #[test]
+    fn decode_bytes_matches_decode_sysex() {
+        let image = test_image();
+        let mut input = vec![];
+        input.extend(block_message(&image, 1));
+        input.extend(program_message());
+        input.extend_from_slice(b"\xF0\x43\x10\x4C\xF7");
+        input.extend(block_message(&image, 0));
+        input.extend(block_message(&image, 2));
+
+        let handler = vec![
+            ( UnexpectedMessage { len: 2350 }, Ok(()) ),
+            ( UnexpectedMessage { len:    3 }, Ok(()) ),
+            ( UnusedFinalBytes  { count: 168 }, Ok(()) ),
+        ];
+        let mut decoder = BlockDecoder::new(IMAGE_MAX_BYTES, handler);
+
+        assert_eq!(decoder.decode_bytes(&input), Ok(()));
+        assert_eq!(decoder.image().unwrap(), &image[..]);
+        assert_eq!(decoder.stats().messages, 5);
+    }
+
+    #[test]
+    fn decode_sysex_strict_abort() {
+        let image = test_image();
+        let mut input = vec![];
+        input.extend(block_message(&image, 0));
+        input.extend_from_slice(b"\xF0\x43\x10\x4C\xF7");
+        input.extend(block_message(&image, 1));
+
+        let handler = vec![
+            ( UnexpectedMessage { len: 3 }, Err(()) ),
+        ];
+        let mut decoder = BlockDecoder::new(IMAGE_MAX_BYTES, handler);
+
+        assert_eq!(decoder.decode_sysex(&mut &input[..]).unwrap(), Err(()));
+    }
+
+    #[test]
+    fn decode_sysex_duplicate_and_bad_index() {
+        let image = test_image();
+        let mut input = vec![];
+        input.extend(block_message(&image, 0));
+        input.extend(block_message(&image, 3));
+        input.extend(block_message(&image, 1));
+        input.extend(block_message(&image, 0));
+        input.extend(block_message(&image, 2));
+
+        let handler = vec![
+            ( InvalidBlockIndex { actual: 3, max: 2 }, Ok(()) ),
+            ( DuplicateBlock    { index:  0         }, Ok(()) ),
+            ( UnusedFinalBytes  { count: 168        }, Ok(()) ),
+        ];
+        let mut decoder = BlockDecoder::new(IMAGE_MAX_BYTES, handler);
+
+        assert_eq!(decoder.decode_sysex(&mut &input[..]).unwrap(), Ok(()));
+        assert_eq!(decoder.image().unwrap(), &image[..]);
+    }
+
+    #[test]
+    fn decode_sysex_strict_padding_ok() {
+        let image = test_image();
+        let mut input = vec![];
+        for i in 0..3 {
+            input.extend(block_message(&image, i));
+        }
+
+        let handler = vec![
+            ( UnusedFinalBytes { count: 168 }, Ok(()) ),
+        ];
+        let mut decoder = BlockDecoder::new(IMAGE_MAX_BYTES, handler);
+        decoder.set_strict_padding(Some(0x00));
+
+        assert_eq!(decoder.decode_sysex(&mut &input[..]).unwrap(), Ok(()));
+        assert_eq!(decoder.image().unwrap(), &image[..]);
+    }
+
+    #[test]
+    fn decode_sysex_strict_padding_invalid() {
+        let image = test_image();
+        let mut input = vec![];
+        for i in 0..3 {
+            input.extend(block_message(&image, i));
+        }
+
+        let handler = vec![
+            ( UnusedFinalBytes { count: 168 }, Ok(()) ),
+            ( InvalidPadding   { actual: 0x00, expected: 0xFF, offset: 600 }, Err(()) ),
+        ];
+        let mut decoder = BlockDecoder::new(IMAGE_MAX_BYTES, handler);
+        decoder.set_strict_padding(Some(0xFF));
+
+        assert_eq!(decoder.decode_sysex(&mut &input[..]).unwrap(), Err(()));
+    }
+
+    #[test]
+    fn extract_image_ok() {
+        let image = test_image();
+        let mut input = vec![];
+        input.extend(block_message(&image, 1));
+        input.extend(program_message());
+        input.extend(block_message(&image, 0));
+        input.extend(block_message(&image, 0));
+        input.extend(block_message(&image, 2));
+
+        let (result, report, stats) = extract_image(&mut &input[..]).unwrap();
+
+        assert_eq!(result.bytes(), &image[..]);
+        assert_eq!(report.header.map(|h| h.length), Some(600));
+        assert_eq!(report.errors, vec![
+            DuplicateBlock   { index: 0 },
+            UnusedFinalBytes { count: 168 },
+        ]);
+        assert!(report.is_valid());
+        assert_eq!(report.kind, Some(BlockKind::Os));
+        assert_eq!(stats, DecodeStats { messages: 5, blocks: 3, duplicates: 1, ignored: 1 });
+    }
+
+    #[test]
+    fn decode_sysex_mixed_kinds() {
+        let image = test_image();
+        let mut input = vec![];
+        input.extend(boot_block_message(&image, 0));
+        input.extend(block_message(&image, 1));
+
+        let handler = vec![
+            ( MixedBlockKinds { actual: BlockKind::Os, expected: BlockKind::Boot }, Err(()) ),
+        ];
+        let mut decoder = BlockDecoder::new(IMAGE_MAX_BYTES, handler);
+
+        assert_eq!(decoder.decode_sysex(&mut &input[..]).unwrap(), Err(()));
+        assert_eq!(decoder.kind(), Some(BlockKind::Boot));
+    }
+
+    #[test]
+    fn decode_sysex_selected_kind() {
+        let image = test_image();
+        let mut input = vec![];
+        for i in 0..3 {
+            input.extend(boot_block_message(&image, i));
+            input.extend(block_message(&image, i));
+        }
+
+        let handler = vec![
+            ( UnusedFinalBytes { count: 168 }, Ok(()) ),
+        ];
+        let mut decoder = BlockDecoder::new(IMAGE_MAX_BYTES, handler);
+        decoder.set_tolerant(true);
+        decoder.set_kind(Some(BlockKind::Os));
+
+        assert_eq!(decoder.decode_sysex(&mut &input[..]).unwrap(), Ok(()));
+        assert_eq!(decoder.image().unwrap(), &image[..]);
+        assert_eq!(decoder.ignored_count(), 3);
+    }
+
+    #[test]
+    fn extract_image_mixed() {
+        let image = test_image();
+        let mut input = vec![];
+        input.extend(block_message(&image, 0));
+        input.extend(boot_block_message(&image, 1));
+        input.extend(block_message(&image, 1));
+        input.extend(block_message(&image, 2));
+
+        let (_, report, _) = extract_image(&mut &input[..]).unwrap();
+
+        assert_eq!(report.errors[0], MixedBlockKinds {
+            actual: BlockKind::Boot, expected: BlockKind::Os
+        });
+        assert!(!report.is_valid());
+    }
+
+    #[test]
+    fn extract_images_both() {
+        let os   = test_image();
+        let boot = (0..300).map(|x| (x * 3) as u8).collect::<Vec<_>>();
+        let mut input = vec![];
+        input.extend(boot_block_message(&boot, 1));
+        input.extend(block_message(&os, 0));
+        input.extend(program_message());
+        input.extend(block_message(&os, 1));
+        input.extend(boot_block_message(&boot, 0));
+        input.extend(block_message(&os, 2));
+
+        let images = extract_images(&mut &input[..]).unwrap();
+
+        assert_eq!(images.len(), 2);
+        assert_eq!(images[0].0.bytes(), &os[..]);
+        assert_eq!(images[0].1.kind, Some(BlockKind::Os));
+        assert!(images[0].1.is_valid());
+        assert_eq!(images[1].0.bytes(), &boot[..]);
+        assert_eq!(images[1].1.kind, Some(BlockKind::Boot));
+        assert!(images[1].1.is_valid());
+        assert_eq!(images[1].2.ignored, 4);
+    }
+
+    #[test]
+    fn extract_images_bad_os_block() {
+        let os   = test_image();
+        let boot = (0..300).map(|x| (x * 3) as u8).collect::<Vec<_>>();
+        let mut input = vec![];
+        input.extend(block_message(&os, 0));
+        input.extend(block_message(&os, 7));    // index out of range
+        input.extend(boot_block_message(&boot, 0));
+        input.extend(block_message(&os, 1));
+        input.extend(boot_block_message(&boot, 1));
+        input.extend(block_message(&os, 2));
+
+        let images = extract_images(&mut &input[..]).unwrap();
+
+        assert_eq!(images.len(), 2);
+        assert_eq!(images[0].0.bytes(), &os[..]);
+        assert!(images[0].1.errors.contains(&InvalidBlockIndex { actual: 7, max: 2 }));
+        assert_eq!(images[1].0.bytes(), &boot[..]);
+        assert!(images[1].1.is_valid());
+    }
+
+    #[test]
+    fn decode_update_file_ok() {
+        let image = test_image();
+        let mut input = vec![];
+        input.extend(program_message());
+        for i in 0..3 {
+            input.extend(boot_block_message(&image, i));
+        }
+
+        let update = decode_update_file(&mut &input[..]).unwrap().unwrap();
+
+        assert_eq!(update.image.bytes(), &image[..]);
+        assert_eq!(update.header.version, 20105);
+        assert_eq!(update.kind, BlockKind::Boot);
+        assert_eq!(update.stats.ignored, 1);
+        assert_eq!(update.warnings, vec![UnusedFinalBytes { count: 168 }]);
+    }
+
+    #[test]
+    fn decode_update_file_invalid() {
+        let image = test_image();
+        let input = block_message(&image, 0);
+
+        let report = decode_update_file(&mut &input[..]).unwrap().unwrap_err();
+
+        assert_eq!(report.kind, Some(BlockKind::Os));
+        assert!(report.errors.contains(&MissingBlock { index: 1 }));
+    }
+
+    #[test]
+    fn extract_images_none() {
+        assert_eq!(extract_images(&mut &b"junk"[..]).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn extract_image_incomplete() {
+        let image = test_image();
+        let mut input = vec![];
+        input.extend(block_message(&image, 0));
+        input.extend(block_message(&image, 2));
+
+        let (result, report, stats) = extract_image(&mut &input[..]).unwrap();
+
+        assert_eq!(result.len(), 600);
+        assert_eq!(report.errors, vec![
+            UnusedFinalBytes { count: 168 },
+            MissingBlock     { index: 1 },
+            ChecksumMismatch { actual: checksum(&result.bytes()), expected: checksum(&image) },
+        ]);
+        assert!(!report.is_valid());
+        assert_eq!(stats.blocks, 2);
+    }
+
+    #[test]
+    fn extract_image_bad_block_skipped() {
+        let image = test_image();
+        let mut bad = block_message(&image, 1);
+        bad[6] = 0x01;  // Low bit of first version byte
+
+        let mut input = vec![];
+        input.extend(block_message(&image, 0));
+        input.extend(bad);
+        input.extend(block_message(&image, 1));
+        input.extend(block_message(&image, 2));
+
+        let (result, report, stats) = extract_image(&mut &input[..]).unwrap();
+
+        assert_eq!(result.bytes(), &image[..]);
+        assert_eq!(report.errors, vec![
+            InconsistentVersion { actual: 0x01004E89, expected: 0x00004E89, index: 1 },
+            UnusedFinalBytes    { count: 168 },
+        ]);
+        assert!(report.is_valid());
+        assert_eq!(stats, DecodeStats { messages: 4, blocks: 3, duplicates: 0, ignored: 0 });
+    }
+
+    #[test]
+    fn extract_image_empty() {
+        let (result, report, stats) = extract_image(&mut &b"junk"[..]).unwrap();
+
+        assert_eq!(result.len(), 0);
+        assert_eq!(report.header, None);
+        assert_eq!(report.errors, vec![MissingBlock { index: 0 }]);
+        assert!(!report.is_valid());
+        assert_eq!(stats, DecodeStats::default());
+    }
+
+    #[test]
+    fn read_update_version_found() {
+        let mut bytes = b"\xF0\x00\x00\x0E\x1D\x01\x00\xF7".to_vec(); // other opcode
+        bytes.extend_from_slice(b"\xF0\x00\x00\x0E\x1D\x30");
+        bytes.extend_from_slice(&[0x00, 0x00, 0x38, 0x4A, 0x08]); // 20105
+        bytes.push(0xF7);
+
+        let version = read_update_version(&mut &bytes[..]).unwrap();
+
+        assert_eq!(version, Some(20105));
+    }
+
+    #[test]
+    fn read_update_version_not_found() {
+        let bytes = b"\xF0\x00\x00\x0E\x1D\x01\x00\xF7";
+
+        let version = read_update_version(&mut &bytes[..]).unwrap();
+
+        assert_eq!(version, None);
+    }
+
     #[test]
     fn block_range_fn() {
         assert_eq!( block_range(    0),        0 ..      256 );
@@ -0,0 +1,1180 @@
+// This file is part of a6-tools.
+// Copyright (C) 2017 Jeffrey Sharp
+//
+// a6-tools is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License,
+// or (at your option) any later version.
+//
+// a6-tools is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See
+// the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with a6-tools.  If not, see <http://www.gnu.org/licenses/>.
+
+#[cfg(feature = "std")]
+use std::error;
+use std::fmt;
+#[cfg(feature = "std")]
+use std::io::{self, Write};
+
+#[cfg(not(feature = "std"))]
+use heap::*;
+
+#[cfg(feature = "arbitrary")]
+use arbitrary::{Arbitrary, Unstructured};
+
+/// Returns the count of 7-bit values that encode `n` bytes.
+#[inline]
+pub fn encoded_len_7bit(n: usize) -> usize {
+    n + n / 7 + (n % 7 != 0) as usize
+}
+
+/// Returns the count of bytes that `n` 7-bit values decode to.  Any leftover
+/// bits are not counted.
+#[inline]
+pub fn decoded_len_7bit(n: usize) -> usize {
+    n / 8 * 7 + n % 8 * 7 / 8
+}
+
+/// Encodes a sequence of bytes into a sequence of 7-bit values.
+pub fn encode_7bit(src: &[u8], dst: &mut Vec<u8>)
+{
+    encode_7bit_with(src, dst, true)
+}
+
+// Encodes as `encode_7bit` does, using CPU-specific instructions only if
+// `simd` is `true` and the CPU supports them.  Public for benchmarks.
+#[doc(hidden)]
+pub fn encode_7bit_with(src: &[u8], dst: &mut Vec<u8>, simd: bool)
+{
+    dst.reserve(encoded_len_7bit(src.len()));
+
+    // Every 7 input bytes yield exactly 8 values, so whole groups can be
+    // encoded independently, 64 bits at a time.
+    let mut groups = src.chunks_exact(7);
+
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    {
+        if simd && is_x86_feature_detected!("ssse3") {
+            unsafe { simd::encode_groups(&mut groups, dst) };
+        }
+    }
+
+    #[cfg(all(feature = "simd", target_arch = "aarch64", target_endian = "little"))]
+    {
+        if simd {
+            unsafe { simd::encode_groups(&mut groups, dst) };
+        }
+    }
+
+    for group in &mut groups {
+        let mut bytes = [0; 8];
+        bytes[..7].copy_from_slice(group);
+        let data = u64::from_le_bytes(bytes);
+
+        let mut values = 0u64;
+        for i in 0..8 {
+            values |= (data >> (7 * i) & 0x7F) << (8 * i);
+        }
+        dst.extend_from_slice(&values.to_le_bytes());
+    }
+
+    encode_7bit_scalar(groups.remainder(), dst)
+}
+
+/// Encodes a sequence of bytes yielded by an iterator, such as a chain of
+/// header and data bytes, into a sequence of 7-bit values, as `encode_7bit`
+/// does for a slice.
+pub fn encode_7bit_iter<I>(src: I, dst: &mut Vec<u8>)
+where
+    I: IntoIterator<Item=u8>,
+{
+    dst.extend(Encode7Bit::new(src))
+}
+
+// Encodes a sequence of bytes into a sequence of 7-bit values, one byte at a
+// time.
+fn encode_7bit_scalar(src: &[u8], dst: &mut Vec<u8>)
+{
+    // Iteration
+    // |  Leftover bits
+    // |  |         7-bit output
+    // |  |         |
+    // 0: ........ 00000000 -> yield 7 bits
+    // 1: .......1 11111110 -> yield 7 bits
+    // 2: ......22 22222211 -> yield 7 bits
+    // 3: .....333 33333222 -> yield 7 bits
+    // 4: ....4444 44443333 -> yield 7 bits
+    // 5: ...55555 55544444 -> yield 7 bits
+    // 6: ..666666 66555555 -> yield 7 bits, then
+    //    ........ .6666666 -> yield 7 bits again
+    // 7: (repeats)
+
+    let mut data = 0u16;    // a shift register where bytes become bits
+    let mut bits = 0;       // how many leftover bits from previous iteration
+
+    for v in src {
+        // Add 8 input bits.
+        data |= (*v as u16) << bits;
+
+        // Yield 7 bits.  Accrue 1 leftover bit for next iteration.
+        dst.push((data & 0x7F) as u8);
+        data >>= 7;
+        bits  += 1;
+
+        // Every 7 iterations, 7 leftover bits have accrued.
+        // Consume them to yield another 7-bit output.
+        if bits == 7 {
+            dst.push((data & 0x7F) as u8);
+            data = 0;
+            bits = 0;
+        }
+    }
+
+    // Yield final leftover bits, if any.
+    if bits > 0 {
+        dst.push((data & 0x7F) as u8);
+    }
+}
+
+/// Decodes a sequence of 7-bit values into a sequence of bytes.
+pub fn decode_7bit(src: &[u8], dst: &mut Vec<u8>)
+{
+    decode_7bit_with(src, dst, true)
+}
+
+// Decodes as `decode_7bit` does, using CPU-specific instructions only if
+// `simd` is `true` and the CPU supports them.  Public for benchmarks.
+#[doc(hidden)]
+pub fn decode_7bit_with(src: &[u8], dst: &mut Vec<u8>, simd: bool)
+{
+    dst.reserve(decoded_len_7bit(src.len()));
+
+    // Every 8 input values yield exactly 7 bytes, so whole groups can be
+    // decoded independently, 64 bits at a time.
+    let mut groups = src.chunks_exact(8);
+
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    {
+        if simd && is_x86_feature_detected!("ssse3") {
+            unsafe { simd::decode_groups(&mut groups, dst) };
+        }
+    }
+
+    #[cfg(all(feature = "simd", target_arch = "aarch64", target_endian = "little"))]
+    {
+        if simd {
+            unsafe { simd::decode_groups(&mut groups, dst) };
+        }
+    }
+
+    for group in &mut groups {
+        let mut values = [0; 8];
+        values.copy_from_slice(group);
+        let values = u64::from_le_bytes(values);
+
+        let mut data = 0u64;
+        for i in 0..8 {
+            data |= (values >> (8 * i) & 0x7F) << (7 * i);
+        }
+        dst.extend_from_slice(&data.to_le_bytes()[..7]);
+    }
+
+    decode_7bit_scalar(groups.remainder(), dst)
+}
+
+// Decodes a sequence of 7-bit values into a sequence of bytes, one value at a
+// time.
+fn decode_7bit_scalar(src: &[u8], dst: &mut Vec<u8>)
+{
+    // Iteration
+    // |  Leftover bits
+    // |  |        Byte output
+    // |  |        |
+    // 0: ........ .0000000 (not enough bits for a byte)
+    // 1: ..111111 10000000 -> yield byte
+    // 2: ...22222 22111111 -> yield byte
+    // 3: ....3333 33322222 -> yield byte
+    // 4: .....444 44443333 -> yield byte
+    // 5: ......55 55555444 -> yield byte
+    // 6: .......6 66666655 -> yield byte
+    // 7: ........ 77777776 -> yield byte
+    // 8: (repeats)
+
+    let mut data = 0u16;    // a shift register where bits become bytes
+    let mut bits = 0;       // how many leftover bits from previous iteration
+
+    for v in src {
+        // Isolate 7 input bits.
+        let v = (*v & 0x7F) as u16;
+
+        if bits == 0 {
+            // Initially, and after every 8 iterations, there are no leftover
+            // bits from the previous iteration.  With only 7 new bits, there
+            // aren't enough to make a byte.  Just let those bits become the
+            // leftovers for the next iteration.
+            data = v;
+            bits = 7;
+        } else {
+            // For other iterations, there are leftover bits from the previous
+            // iteration.  Consider those as least significant, and the 7 new
+            // bits as most significant, and yield a byte.  Any unused bits
+            // become leftovers for the next iteration to use.
+            data |= v << bits;
+            dst.push((data & 0xFF) as u8);
+            data >>= 8;
+            bits  -= 1;
+        }
+    }
+}
+
+// Group codecs using 128-bit vector instructions, two groups at a time.  A
+// byte shuffle moves the two bytes that hold each 7-bit field (or each output
+// byte) into a 16-bit lane of its own.  A multiply then shifts each lane left
+// by a different amount, so that the wanted bits land in the lane's high byte.
+// These are SSSE3 instructions on x86-64 and NEON instructions on AArch64.
+#[cfg(all(
+    feature = "simd",
+    any(target_arch = "x86_64", all(target_arch = "aarch64", target_endian = "little"))
+))]
+mod simd {
+    use std::slice::ChunksExact;
+
+    // Shuffle of two 7-byte groups for encoding.  Value i of a group is bits
+    // 7i to 7i+6, within bytes 7i/8 and 7i/8+1.  Index 0xFF yields zero.
+    const ENCODE_LO: [u8; 16] = [0, 1, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 0xFF];
+    const ENCODE_HI: [u8; 16] = [7, 8, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13, 0xFF];
+
+    // Multipliers that shift value i left by 8 - 7i mod 8 bits
+    const ENCODE_MUL: [u16; 8] = [256, 2, 4, 8, 16, 32, 64, 128];
+
+    // Shuffle of two 8-value groups for decoding.  Byte i of a group is bits
+    // 8i to 8i+7, within values i and i+1.
+    const DECODE_LO: [u8; 16] = [0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 0xFF, 0xFF];
+    const DECODE_HI: [u8; 16] = [8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13, 14, 14, 15, 0xFF, 0xFF];
+
+    // Multipliers that shift byte i left by 8 - i bits, once values i and i+1
+    // are joined into 14 contiguous bits
+    const DECODE_MUL: [u16; 8] = [256, 128, 64, 32, 16, 8, 4, 0];
+
+    #[cfg(target_arch = "x86_64")]
+    #[target_feature(enable = "ssse3")]
+    pub unsafe fn encode_groups(groups: &mut ChunksExact<u8>, dst: &mut Vec<u8>) {
+        use std::arch::x86_64::*;
+
+        let lo   = _mm_loadu_si128(ENCODE_LO .as_ptr() as *const __m128i);
+        let hi   = _mm_loadu_si128(ENCODE_HI .as_ptr() as *const __m128i);
+        let mul  = _mm_loadu_si128(ENCODE_MUL.as_ptr() as *const __m128i);
+        let mask = _mm_set1_epi8(0x7F);
+
+        while groups.len() >= 2 {
+            let mut bytes = [0; 16];
+            bytes[ ..7 ].copy_from_slice(groups.next().unwrap());
+            bytes[7..14].copy_from_slice(groups.next().unwrap());
+            let bytes = _mm_loadu_si128(bytes.as_ptr() as *const __m128i);
+
+            let a = _mm_srli_epi16(_mm_mullo_epi16(_mm_shuffle_epi8(bytes, lo), mul), 8);
+            let b = _mm_srli_epi16(_mm_mullo_epi16(_mm_shuffle_epi8(bytes, hi), mul), 8);
+
+            let mut values = [0; 16];
+            let v = _mm_and_si128(_mm_packus_epi16(a, b), mask);
+            _mm_storeu_si128(values.as_mut_ptr() as *mut __m128i, v);
+            dst.extend_from_slice(&values);
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[target_feature(enable = "ssse3")]
+    pub unsafe fn decode_groups(groups: &mut ChunksExact<u8>, dst: &mut Vec<u8>) {
+        use std::arch::x86_64::*;
+
+        let lo     = _mm_loadu_si128(DECODE_LO .as_ptr() as *const __m128i);
+        let hi     = _mm_loadu_si128(DECODE_HI .as_ptr() as *const __m128i);
+        let mul    = _mm_loadu_si128(DECODE_MUL.as_ptr() as *const __m128i);
+        let mask_v = _mm_set1_epi16(0x007F);
+        let mask_w = _mm_set1_epi16(0x7F00);
+
+        // Joins values i and i+1 of each lane into 14 contiguous bits
+        let join = |x| _mm_or_si128(
+            _mm_and_si128(x, mask_v),
+            _mm_srli_epi16(_mm_and_si128(x, mask_w), 1)
+        );
+
+        while groups.len() >= 2 {
+            let mut values = [0; 16];
+            values[ ..8 ].copy_from_slice(groups.next().unwrap());
+            values[8..16].copy_from_slice(groups.next().unwrap());
+            let values = _mm_loadu_si128(values.as_ptr() as *const __m128i);
+
+            let a = join(_mm_shuffle_epi8(values, lo));
+            let b = join(_mm_shuffle_epi8(values, hi));
+            let a = _mm_srli_epi16(_mm_mullo_epi16(a, mul), 8);
+            let b = _mm_srli_epi16(_mm_mullo_epi16(b, mul), 8);
+
+            let mut bytes = [0; 16];
+            _mm_storeu_si128(bytes.as_mut_ptr() as *mut __m128i, _mm_packus_epi16(a, b));
+            dst.extend_from_slice(&bytes[ ..7 ]);
+            dst.extend_from_slice(&bytes[8..15]);
+        }
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    pub unsafe fn encode_groups(groups: &mut ChunksExact<u8>, dst: &mut Vec<u8>) {
+        use std::arch::aarch64::*;
+
+        let lo   = vld1q_u8 (ENCODE_LO .as_ptr());
+        let hi   = vld1q_u8 (ENCODE_HI .as_ptr());
+        let mul  = vld1q_u16(ENCODE_MUL.as_ptr());
+        let mask = vdupq_n_u8(0x7F);
+
+        while groups.len() >= 2 {
+            let mut bytes = [0; 16];
+            bytes[ ..7 ].copy_from_slice(groups.next().unwrap());
+            bytes[7..14].copy_from_slice(groups.next().unwrap());
+            let bytes = vld1q_u8(bytes.as_ptr());
+
+            let a = vreinterpretq_u16_u8(vqtbl1q_u8(bytes, lo));
+            let b = vreinterpretq_u16_u8(vqtbl1q_u8(bytes, hi));
+            let a = vshrq_n_u16::<8>(vmulq_u16(a, mul));
+            let b = vshrq_n_u16::<8>(vmulq_u16(b, mul));
+
+            let mut values = [0; 16];
+            let v = vandq_u8(vcombine_u8(vmovn_u16(a), vmovn_u16(b)), mask);
+            vst1q_u8(values.as_mut_ptr(), v);
+            dst.extend_from_slice(&values);
+        }
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    pub unsafe fn decode_groups(groups: &mut ChunksExact<u8>, dst: &mut Vec<u8>) {
+        use std::arch::aarch64::*;
+
+        let lo     = vld1q_u8 (DECODE_LO .as_ptr());
+        let hi     = vld1q_u8 (DECODE_HI .as_ptr());
+        let mul    = vld1q_u16(DECODE_MUL.as_ptr());
+        let mask_v = vdupq_n_u16(0x007F);
+        let mask_w = vdupq_n_u16(0x7F00);
+
+        // Joins values i and i+1 of each lane into 14 contiguous bits
+        let join = |x| vorrq_u16(
+            vandq_u16(x, mask_v),
+            vshrq_n_u16::<1>(vandq_u16(x, mask_w))
+        );
+
+        while groups.len() >= 2 {
+            let mut values = [0; 16];
+            values[ ..8 ].copy_from_slice(groups.next().unwrap());
+            values[8..16].copy_from_slice(groups.next().unwrap());
+            let values = vld1q_u8(values.as_ptr());
+
+            let a = join(vreinterpretq_u16_u8(vqtbl1q_u8(values, lo)));
+            let b = join(vreinterpretq_u16_u8(vqtbl1q_u8(values, hi)));
+            let a = vshrq_n_u16::<8>(vmulq_u16(a, mul));
+            let b = vshrq_n_u16::<8>(vmulq_u16(b, mul));
+
+            let mut bytes = [0; 16];
+            vst1q_u8(bytes.as_mut_ptr(), vcombine_u8(vmovn_u16(a), vmovn_u16(b)));
+            dst.extend_from_slice(&bytes[ ..7 ]);
+            dst.extend_from_slice(&bytes[8..15]);
+        }
+    }
+}
+
+/// Schemes for packing a sequence of bytes into a sequence of 7-bit values
+/// suitable for System Exclusive message data.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Packing {
+    /// Bytes are packed as a continuous stream of bits, least significant bit
+    /// first, 7 bits per value.  The A6 uses this scheme.  See `encode_7bit`.
+    BitStream,
+
+    /// Each group of up to 7 bytes is packed as one value holding the most
+    /// significant bits of the bytes, followed by the remaining 7 bits of each
+    /// byte.  Bit `i` of the first value belongs to byte `i` of the group.
+    MsbBlock,
+
+    /// Each byte is packed as two values, its low 4 bits then its high 4 bits.
+    Nibble,
+}
+
+impl Packing {
+    /// All packing schemes.
+    pub const ALL: [Packing; 3] = [Packing::BitStream, Packing::MsbBlock, Packing::Nibble];
+
+    /// Returns the count of 7-bit values that encode `n` bytes.
+    pub fn encoded_len(self, n: usize) -> usize {
+        match self {
+            Packing::BitStream => encoded_len_7bit(n),
+            Packing::MsbBlock  => n + n / 7 + (n % 7 != 0) as usize,
+            Packing::Nibble    => n * 2,
+        }
+    }
+
+    /// Returns the count of bytes that `n` 7-bit values decode to.  Any
+    /// incomplete trailing value is not counted.
+    pub fn decoded_len(self, n: usize) -> usize {
+        match self {
+            Packing::BitStream => decoded_len_7bit(n),
+            Packing::MsbBlock  => n / 8 * 7 + (n % 8).saturating_sub(1),
+            Packing::Nibble    => n / 2,
+        }
+    }
+
+    /// Encodes a sequence of bytes into a sequence of 7-bit values.
+    pub fn encode(self, src: &[u8], dst: &mut Vec<u8>) {
+        match self {
+            Packing::BitStream => encode_7bit(src, dst),
+            Packing::MsbBlock  => {
+                dst.reserve(self.encoded_len(src.len()));
+                for group in src.chunks(7) {
+                    let msbs = group.iter().enumerate()
+                        .fold(0, |m, (i, &v)| m | (v >> 7) << i);
+                    dst.push(msbs);
+                    dst.extend(group.iter().map(|&v| v & 0x7F));
+                }
+            },
+            Packing::Nibble => {
+                dst.reserve(self.encoded_len(src.len()));
+                for &v in src {
+                    dst.push(v & 0x0F);
+                    dst.push(v >> 4);
+                }
+            },
+        }
+    }
+
+    /// Decodes a sequence of 7-bit values into a sequence of bytes.  Unused
+    /// bits are ignored.
+    pub fn decode(self, src: &[u8], dst: &mut Vec<u8>) {
+        match self {
+            Packing::BitStream => decode_7bit(src, dst),
+            Packing::MsbBlock  => {
+                dst.reserve(self.decoded_len(src.len()));
+                for group in src.chunks(8) {
+                    let msbs = group[0];
+                    dst.extend(group[1..].iter().enumerate().map(|(i, &v)| {
+                        v & 0x7F | (msbs >> i & 1) << 7
+                    }));
+                }
+            },
+            Packing::Nibble => {
+                dst.reserve(self.decoded_len(src.len()));
+                dst.extend(src.chunks(2).filter(|p| p.len() == 2).map(|p| {
+                    p[0] & 0x0F | (p[1] & 0x0F) << 4
+                }));
+            },
+        }
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a> Arbitrary<'a> for Packing {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        u.choose(&Packing::ALL).map(|&p| p)
+    }
+}
+
+/// Decodes a sequence of 7-bit values into a sequence of bytes, as
+/// `decode_7bit` does, but first checks that each value is a valid 7-bit
+/// value.  If a value has its high bit set, returns an error locating the
+/// value, and leaves `dst` unchanged.
+pub fn try_decode_7bit(src: &[u8], dst: &mut Vec<u8>) -> Result<(), Invalid7BitError> {
+    if let Some(offset) = src.iter().position(|&v| v & 0x80 != 0) {
+        return Err(Invalid7BitError { offset, byte: src[offset] })
+    }
+    decode_7bit(src, dst);
+    Ok(())
+}
+
+/// Error returned by `try_decode_7bit` for an input byte with its high bit set.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Invalid7BitError {
+    /// Offset of the invalid byte within the input.
+    pub offset: usize,
+
+    /// The invalid byte.
+    pub byte: u8,
+}
+
+impl Invalid7BitError {
+    /// Gets the stable diagnostic code of the error.  See `SysExReadError::code`.
+    #[inline]
+    pub fn code(&self) -> &'static str {
+        "A6-SYX-007"
+    }
+}
+
+impl fmt::Display for Invalid7BitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f, "{}: Invalid 7-bit value {:02X} at offset {}. \
+                7-bit values must not have the high bit set.",
+            self.code(), self.byte, self.offset
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for Invalid7BitError { }
+
+#[cfg(feature = "std")]
+impl From<Invalid7BitError> for io::Error {
+    fn from(e: Invalid7BitError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+/// Encodes a sequence of bytes into a sequence of 7-bit values, as
+/// `encode_7bit` does, storing the values at the start of the given `dst`
+/// slice.  Returns the count of values stored, or an error if `dst` is too
+/// small to hold them, in which case `dst` is unchanged.
+pub fn encode_7bit_into(src: &[u8], dst: &mut [u8]) -> Result<usize, BufferTooSmallError> {
+    let len = encoded_len_7bit(src.len());
+    store_iter(Encode7Bit::new(src.iter().cloned()), len, dst)
+}
+
+/// Decodes a sequence of 7-bit values into a sequence of bytes, as
+/// `decode_7bit` does, storing the bytes at the start of the given `dst`
+/// slice.  Returns the count of bytes stored, or an error if `dst` is too
+/// small to hold them, in which case `dst` is unchanged.
+pub fn decode_7bit_into(src: &[u8], dst: &mut [u8]) -> Result<usize, BufferTooSmallError> {
+    let len = decoded_len_7bit(src.len());
+    store_iter(Decode7Bit::new(src.iter().cloned()), len, dst)
+}
+
+// Stores the `len` bytes yielded by `iter` at the start of the given `dst`
+// slice.
+fn store_iter<I>(iter: I, len: usize, dst: &mut [u8]) -> Result<usize, BufferTooSmallError>
+where
+    I: Iterator<Item=u8>,
+{
+    if dst.len() < len {
+        return Err(BufferTooSmallError { required: len, actual: dst.len() })
+    }
+
+    for (slot, v) in dst.iter_mut().zip(iter) {
+        *slot = v;
+    }
+    Ok(len)
+}
+
+/// Error returned when an output buffer is too small.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BufferTooSmallError {
+    /// Length required of the buffer.
+    pub required: usize,
+
+    /// Actual length of the buffer.
+    pub actual: usize,
+}
+
+impl BufferTooSmallError {
+    /// Gets the stable diagnostic code of the error.  See `SysExReadError::code`.
+    #[inline]
+    pub fn code(&self) -> &'static str {
+        "A6-SYX-008"
+    }
+}
+
+impl fmt::Display for BufferTooSmallError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f, "{}: Output buffer too small: {} byte(s). \
+                The output requires {} bytes.",
+            self.code(), self.actual, self.required
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for BufferTooSmallError { }
+
+/// Encodes a sequence of bytes into a sequence of 7-bit values, as
+/// `encode_7bit` does, writing the values to the given `dst` stream.
+#[cfg(feature = "std")]
+pub fn encode_7bit_to<W: Write>(src: &[u8], dst: &mut W) -> io::Result<()> {
+    write_iter(Encode7Bit::new(src.iter().cloned()), dst)
+}
+
+/// Decodes a sequence of 7-bit values into a sequence of bytes, as
+/// `decode_7bit` does, writing the bytes to the given `dst` stream.
+#[cfg(feature = "std")]
+pub fn decode_7bit_to<W: Write>(src: &[u8], dst: &mut W) -> io::Result<()> {
+    write_iter(Decode7Bit::new(src.iter().cloned()), dst)
+}
+
+// Writes the bytes yielded by `iter` to the given `dst` stream, in chunks.
+#[cfg(feature = "std")]
+fn write_iter<I, W>(mut iter: I, dst: &mut W) -> io::Result<()>
+where
+    I: Iterator<Item=u8>,
+    W: Write,
+{
+    let mut buf = [0u8; 1024];
+    loop {
+        let mut len = 0;
+        for (slot, v) in buf.iter_mut().zip(&mut iter) {
+            *slot = v;
+            len  += 1;
+        }
+        if len == 0 { return Ok(()) }
+        dst.write_all(&buf[..len])?;
+    }
+}
+
+/// An iterator adapter that encodes a sequence of bytes into a sequence of
+/// 7-bit values, as `encode_7bit` does, without buffering.
+#[derive(Clone, Debug)]
+pub struct Encode7Bit<I> {
+    inner:   I,
+    data:    u16,           // a shift register where bytes become bits
+    bits:    usize,         // how many leftover bits from previous byte
+    pending: Option<u8>,    // a second 7-bit value yielded by previous byte
+}
+
+impl<I: Iterator<Item=u8>> Encode7Bit<I> {
+    /// Creates an adapter that encodes the bytes yielded by `inner`.
+    pub fn new<T: IntoIterator<IntoIter=I, Item=u8>>(inner: T) -> Self {
+        Self { inner: inner.into_iter(), data: 0, bits: 0, pending: None }
+    }
+}
+
+impl<I: Iterator<Item=u8>> Iterator for Encode7Bit<I> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        // See encode_7bit for the algorithm.
+        if let Some(v) = self.pending.take() {
+            return Some(v)
+        }
+
+        match self.inner.next() {
+            Some(v) => {
+                self.data |= (v as u16) << self.bits;
+                let v = (self.data & 0x7F) as u8;
+                self.data >>= 7;
+                self.bits  += 1;
+
+                if self.bits == 7 {
+                    self.pending = Some((self.data & 0x7F) as u8);
+                    self.data    = 0;
+                    self.bits    = 0;
+                }
+                Some(v)
+            },
+            None if self.bits > 0 => {
+                let v = (self.data & 0x7F) as u8;
+                self.data = 0;
+                self.bits = 0;
+                Some(v)
+            },
+            None => None,
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // Count of 7-bit values yielded for n more input bytes
+        let bits  = self.bits;
+        let extra = self.pending.is_some() as usize;
+        let count = |n: usize| {
+            let total = n.checked_add(bits)?;
+            n.checked_add(total / 7 + (total % 7 != 0) as usize + extra)
+        };
+
+        let (lo, hi) = self.inner.size_hint();
+        (count(lo).unwrap_or(usize::MAX), hi.and_then(count))
+    }
+}
+
+/// An iterator adapter that decodes a sequence of 7-bit values into a sequence
+/// of bytes, as `decode_7bit` does, without buffering.
+#[derive(Clone, Debug)]
+pub struct Decode7Bit<I> {
+    inner: I,
+    data:  u16,     // a shift register where bits become bytes
+    bits:  usize,   // how many leftover bits from previous value
+}
+
+impl<I: Iterator<Item=u8>> Decode7Bit<I> {
+    /// Creates an adapter that decodes the 7-bit values yielded by `inner`.
+    pub fn new<T: IntoIterator<IntoIter=I, Item=u8>>(inner: T) -> Self {
+        Self { inner: inner.into_iter(), data: 0, bits: 0 }
+    }
+}
+
+impl<I: Iterator<Item=u8>> Iterator for Decode7Bit<I> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        // See decode_7bit for the algorithm.
+        loop {
+            let v = (self.inner.next()? & 0x7F) as u16;
+
+            if self.bits == 0 {
+                self.data = v;
+                self.bits = 7;
+            } else {
+                self.data |= v << self.bits;
+                let v = (self.data & 0xFF) as u8;
+                self.data >>= 8;
+                self.bits  -= 1;
+                return Some(v)
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // Count of bytes yielded for n more 7-bit values
+        let bits  = self.bits;
+        let count = |n: usize| Some(n.checked_mul(7)?.checked_add(bits)? / 8);
+
+        let (lo, hi) = self.inner.size_hint();
+        (count(lo).unwrap_or(lo), hi.and_then(count))
+    }
+}
+
+/// Cross-checks of the 7-bit codec implementations against each other, for
+/// fuzzing and property tests of the faster variants.  Each check panics if
+/// any implementation disagrees with the byte-at-a-time reference.
+#[cfg(any(test, feature = "differential"))]
+pub mod differential {
+    use super::*;
+
+    /// Checks that every encoder yields the same values for `src`, and that
+    /// every decoder recovers `src` from them.
+    pub fn check_7bit(src: &[u8]) {
+        let mut expected = vec![];
+        encode_7bit_scalar(src, &mut expected);
+
+        let mut actual = vec![];
+        encode_7bit_with(src, &mut actual, false);
+        assert_eq!(actual, expected, "encode_7bit (64-bit groups) diverges");
+
+        let mut actual = vec![];
+        encode_7bit_with(src, &mut actual, true);
+        assert_eq!(actual, expected, "encode_7bit (SIMD) diverges");
+
+        let actual = Encode7Bit::new(src.iter().cloned()).collect::<Vec<_>>();
+        assert_eq!(actual, expected, "Encode7Bit diverges");
+
+        let mut actual = vec![0; encoded_len_7bit(src.len())];
+        let len = encode_7bit_into(src, &mut actual).unwrap();
+        assert_eq!(&actual[..len], &expected[..], "encode_7bit_into diverges");
+
+        assert_eq!(check_decode_7bit(&expected), src, "decoding does not round-trip");
+    }
+
+    /// Checks that every decoder yields the same bytes for `src`, which may
+    /// contain invalid values.  Returns the decoded bytes.
+    pub fn check_decode_7bit(src: &[u8]) -> Vec<u8> {
+        let mut expected = vec![];
+        decode_7bit_scalar(src, &mut expected);
+
+        let mut actual = vec![];
+        decode_7bit_with(src, &mut actual, false);
+        assert_eq!(actual, expected, "decode_7bit (64-bit groups) diverges");
+
+        let mut actual = vec![];
+        decode_7bit_with(src, &mut actual, true);
+        assert_eq!(actual, expected, "decode_7bit (SIMD) diverges");
+
+        let actual = Decode7Bit::new(src.iter().cloned()).collect::<Vec<_>>();
+        assert_eq!(actual, expected, "Decode7Bit diverges");
+
+        let mut actual = vec![0; decoded_len_7bit(src.len())];
+        let len = decode_7bit_into(src, &mut actual).unwrap();
+        assert_eq!(&actual[..len], &expected[..], "decode_7bit_into diverges");
+
+        let mut actual = vec![];
+        match try_decode_7bit(src, &mut actual) {
+            Ok(()) => assert_eq!(actual, expected, "try_decode_7bit diverges"),
+            Err(e) => assert!(src[e.offset] & 0x80 != 0, "try_decode_7bit rejects a valid value"),
+        }
+
+        expected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(not(feature = "std"))]
+    use alloc::string::ToString;
+
+    #[test]
+    fn test_encode_7bit() {
+        let data8 = [
+            0xF1, 0xE2, 0xD3, 0xC4, 0xB5, 0xA6, 0x97, 0x88, 0x79, 0x6A,
+        ];
+        let mut data7 = vec![];
+
+        encode_7bit(&data8, &mut data7);
+
+        assert_eq!(data7.len(), 12);
+        //                       always 0
+        //                       | new bits
+        //                       | |    leftover bits
+        //                       | |    |
+        //                    0b_x_xxxx_xxx
+        assert_eq!(data7[ 0], 0b_0_1110001_);
+        assert_eq!(data7[ 1], 0b_0_100010_1);
+        assert_eq!(data7[ 2], 0b_0_10011_11);
+        assert_eq!(data7[ 3], 0b_0_0100_110);
+        assert_eq!(data7[ 4], 0b_0_101_1100);
+        assert_eq!(data7[ 5], 0b_0_10_10110);
+        assert_eq!(data7[ 6], 0b_0_1_101001);
+        assert_eq!(data7[ 7], 0b_0__1001011);
+        assert_eq!(data7[ 8], 0b_0_0001000_);
+        assert_eq!(data7[ 9], 0b_0_111001_1);
+        assert_eq!(data7[10], 0b_0_01010_01);
+        assert_eq!(data7[11], 0b_0_0000_011);
+        //                         |    |
+        //                         |    final leftover bits
+        //                         0-padding
+    }
+
+    #[test]
+    fn test_decode_7bit() {
+        let data7 = [
+        //     don't care
+        //     | leftover bits
+        //     | |    new bits
+        //     | |    |
+        //  0b_x_xxxx_xxx
+            0b_1_1110001_,
+            0b_0_100010_1,
+            0b_1_10011_11,
+            0b_0_0100_110,
+            0b_1_101_1100,
+            0b_0_10_10110,
+            0b_1_1_101001,
+            0b_0__1001011,
+            0b_1_0001000_,
+            0b_0_111001_1,
+            0b_1_01010_01,
+            0b_0_1111_011,
+        ];
+        let mut data8 = vec![];
+
+        decode_7bit(&data7, &mut data8);
+
+        assert_eq!(data8.len(), 10);
+        assert_eq!(data8[0], 0xF1);
+        assert_eq!(data8[1], 0xE2);
+        assert_eq!(data8[2], 0xD3);
+        assert_eq!(data8[3], 0xC4);
+        assert_eq!(data8[4], 0xB5);
+        assert_eq!(data8[5], 0xA6);
+        assert_eq!(data8[6], 0x97);
+        assert_eq!(data8[7], 0x88);
+        assert_eq!(data8[8], 0x79);
+        assert_eq!(data8[9], 0x6A);
+        // Final leftover 4 bits go unused.
+    }
+
+    #[test]
+    fn test_try_decode_7bit() {
+        let mut expected = vec![];
+        decode_7bit(b"\x71\x45\x4F\x26", &mut expected);
+
+        let mut data8 = vec![];
+        assert_eq!(try_decode_7bit(b"\x71\x45\x4F\x26", &mut data8), Ok(()));
+        assert_eq!(data8, expected);
+    }
+
+    #[test]
+    fn test_try_decode_7bit_invalid() {
+        let mut data8 = vec![];
+        let result    = try_decode_7bit(b"\x71\x45\xCF\x26\xFF", &mut data8);
+
+        assert_eq!(result, Err(Invalid7BitError { offset: 2, byte: 0xCF }));
+        assert_eq!(data8, b"");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "A6-SYX-007: Invalid 7-bit value CF at offset 2. \
+             7-bit values must not have the high bit set."
+        );
+    }
+
+    #[test]
+    fn test_packing_msb_block() {
+        let data8 = [0x81, 0x02, 0x83, 0x04, 0x05, 0x06, 0xFF, 0x88];
+        let mut data7 = vec![];
+        let mut round = vec![];
+
+        Packing::MsbBlock.encode(&data8, &mut data7);
+        Packing::MsbBlock.decode(&data7, &mut round);
+
+        assert_eq!(data7, [
+            0b_1000101, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x7F,
+            0b_0000001, 0x08,
+        ]);
+        assert_eq!(round, data8);
+    }
+
+    #[test]
+    fn test_packing_nibble() {
+        let data8 = [0x81, 0x2F];
+        let mut data7 = vec![];
+        let mut round = vec![];
+
+        Packing::Nibble.encode(&data8, &mut data7);
+        Packing::Nibble.decode(&data7, &mut round);
+
+        assert_eq!(data7, [0x01, 0x08, 0x0F, 0x02]);
+        assert_eq!(round, data8);
+    }
+
+    #[test]
+    fn test_packing_lengths() {
+        for &packing in &[Packing::BitStream, Packing::MsbBlock, Packing::Nibble] {
+            for n in 0..30 {
+                let data = (0..n).map(|i| (i * 37 + 0x85) as u8).collect::<Vec<_>>();
+                let mut encoded = vec![];
+                let mut decoded = vec![];
+                packing.encode(&data, &mut encoded);
+                packing.decode(&data, &mut decoded);
+
+                assert_eq!(packing.encoded_len(n), encoded.len(), "{:?} {}", packing, n);
+                assert_eq!(packing.decoded_len(n), decoded.len(), "{:?} {}", packing, n);
+
+                let mut round = vec![];
+                packing.decode(&encoded, &mut round);
+                assert_eq!(round, data, "{:?} {}", packing, n);
+            }
+        }
+    }
+
+    #[test]
+    fn test_encode_7bit_into() {
+        let data8 = (0..20).map(|i| (i * 37 + 0x85) as u8).collect::<Vec<_>>();
+        let mut expected = vec![];
+        encode_7bit(&data8, &mut expected);
+
+        let mut buf = [0xAA; 30];
+        assert_eq!(encode_7bit_into(&data8, &mut buf), Ok(23));
+        assert_eq!(&buf[..23], &expected[..]);
+        assert_eq!(buf[23], 0xAA);
+    }
+
+    #[test]
+    fn test_decode_7bit_into() {
+        let data7 = (0..20).map(|i| (i * 37 + 0x05) as u8 & 0x7F).collect::<Vec<_>>();
+        let mut expected = vec![];
+        decode_7bit(&data7, &mut expected);
+
+        let mut buf = [0xAA; 17];
+        assert_eq!(decode_7bit_into(&data7, &mut buf), Ok(17));
+        assert_eq!(&buf[..], &expected[..]);
+    }
+
+    #[test]
+    fn test_7bit_into_too_small() {
+        let mut buf = [0xAA; 8];
+
+        assert_eq!(
+            encode_7bit_into(&[0; 8], &mut buf),
+            Err(BufferTooSmallError { required: 10, actual: 8 })
+        );
+        assert_eq!(
+            decode_7bit_into(&[0; 11], &mut buf[..5]),
+            Err(BufferTooSmallError { required: 9, actual: 5 })
+        );
+        assert_eq!(buf, [0xAA; 8]);
+    }
+
+    #[test]
+    fn test_encode_7bit_iter() {
+        let head = [0xF1, 0xE2, 0xD3];
+        let data = (0..20).map(|i| (i * 37 + 0x85) as u8).collect::<Vec<_>>();
+
+        let mut expected = vec![];
+        let mut actual   = vec![0x55];
+        encode_7bit(&[&head[..], &data[..]].concat(), &mut expected);
+        encode_7bit_iter(head.iter().cloned().chain(data), &mut actual);
+
+        assert_eq!(actual[0], 0x55);
+        assert_eq!(&actual[1..], &expected[..]);
+    }
+
+    #[test]
+    fn test_encode_7bit_matches_scalar() {
+        for n in 0..100 {
+            let data8 = (0..n).map(|i| (i * 151 + 0x3D) as u8).collect::<Vec<_>>();
+            let mut expected = vec![];
+            let mut actual   = vec![];
+
+            encode_7bit_scalar(&data8, &mut expected);
+            encode_7bit       (&data8, &mut actual);
+
+            assert_eq!(actual, expected, "for length {}", n);
+        }
+    }
+
+    #[test]
+    fn test_decode_7bit_matches_scalar() {
+        for n in 0..100 {
+            let data7 = (0..n).map(|i| (i * 151 + 0x3D) as u8).collect::<Vec<_>>();
+            let mut expected = vec![];
+            let mut actual   = vec![];
+
+            decode_7bit_scalar(&data7, &mut expected);
+            decode_7bit       (&data7, &mut actual);
+
+            assert_eq!(actual, expected, "for length {}", n);
+        }
+    }
+
+    #[test]
+    fn test_7bit_lengths() {
+        for n in 0..30 {
+            let data = vec![0x55; n];
+            let mut encoded = vec![];
+            let mut decoded = vec![];
+            encode_7bit(&data, &mut encoded);
+            decode_7bit(&data, &mut decoded);
+
+            assert_eq!(encoded_len_7bit(n), encoded.len());
+            assert_eq!(decoded_len_7bit(n), decoded.len());
+        }
+
+        assert_eq!(decoded_len_7bit(encoded_len_7bit(2048)), 2048);
+        assert_eq!(decoded_len_7bit(usize::MAX), usize::MAX / 8 * 7 + 6);
+    }
+
+    #[test]
+    fn test_encode_7bit_adapter() {
+        for n in 0..30 {
+            let data8 = (0..n).map(|i| (i * 37 + 0x85) as u8).collect::<Vec<_>>();
+            let mut expected = vec![];
+            encode_7bit(&data8, &mut expected);
+
+            let iter = Encode7Bit::new(data8.iter().cloned());
+            assert_eq!(iter.size_hint(), (expected.len(), Some(expected.len())));
+            assert_eq!(iter.collect::<Vec<_>>(), expected);
+        }
+    }
+
+    #[test]
+    fn test_decode_7bit_adapter() {
+        for n in 0..30 {
+            let data7 = (0..n).map(|i| (i * 37 + 0x85) as u8).collect::<Vec<_>>();
+            let mut expected = vec![];
+            decode_7bit(&data7, &mut expected);
+
+            let iter = Decode7Bit::new(data7.iter().cloned());
+            assert_eq!(iter.size_hint(), (expected.len(), Some(expected.len())));
+            assert_eq!(iter.collect::<Vec<_>>(), expected);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_encode_7bit_to() {
+        let data8 = (0..3000).map(|i| (i * 37 + 0x85) as u8).collect::<Vec<_>>();
+        let mut expected = vec![];
+        encode_7bit(&data8, &mut expected);
+
+        let mut actual = io::Cursor::new(vec![]);
+        encode_7bit_to(&data8, &mut actual).unwrap();
+
+        assert_eq!(actual.into_inner(), expected);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_decode_7bit_to() {
+        let data7 = (0..3000).map(|i| (i * 37 + 0x85) as u8).collect::<Vec<_>>();
+        let mut expected = vec![];
+        decode_7bit(&data7, &mut expected);
+
+        let mut actual = io::Cursor::new(vec![]);
+        decode_7bit_to(&data7, &mut actual).unwrap();
+
+        assert_eq!(actual.into_inner(), expected);
+    }
+
+    #[test]
+    fn test_7bit_adapters_round_trip() {
+        let data8 = (0..=255u8).collect::<Vec<_>>();
+
+        let round = Decode7Bit::new(Encode7Bit::new(data8.iter().cloned()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(round, data8);
+    }
+
+    #[cfg(feature = "std")]
+    mod props {
+        use super::*;
+        use proptest::prelude::*;
+
+        proptest! {
+            #[test]
+            fn prop_7bit_round_trip(data8 in prop::collection::vec(any::<u8>(), 0..1024)) {
+                let mut data7 = vec![];
+                encode_7bit(&data8, &mut data7);
+                prop_assert_eq!(data7.len(), encoded_len_7bit(data8.len()));
+                prop_assert!(data7.iter().all(|&v| v < 0x80));
+
+                let mut round = vec![];
+                decode_7bit(&data7, &mut round);
+                prop_assert_eq!(round, data8);
+            }
+
+            #[test]
+            fn prop_7bit_variants_agree(data in prop::collection::vec(any::<u8>(), 0..1024)) {
+                differential::check_7bit(&data);
+                differential::check_decode_7bit(&data);
+            }
+
+            #[test]
+            fn prop_packing_round_trip(
+                packing in prop::sample::select(Packing::ALL.to_vec()),
+                data8   in prop::collection::vec(any::<u8>(), 0..256),
+            ) {
+                let mut data7 = vec![];
+                packing.encode(&data8, &mut data7);
+                prop_assert_eq!(data7.len(), packing.encoded_len(data8.len()));
+                prop_assert!(data7.iter().all(|&v| v < 0x80));
+
+                let mut round = vec![];
+                packing.decode(&data7, &mut round);
+                prop_assert_eq!(round, data8);
+            }
+
+            #[test]
+            fn prop_decode_any(
+                packing in prop::sample::select(Packing::ALL.to_vec()),
+                data7   in prop::collection::vec(any::<u8>(), 0..1024),
+            ) {
+                // Garbage in, but no panic, and the promised length out
+                let mut data8 = vec![];
+                packing.decode(&data7, &mut data8);
+                prop_assert_eq!(data8.len(), packing.decoded_len(data7.len()));
+
+                let mut checked = vec![];
+                match try_decode_7bit(&data7, &mut checked) {
+                    Ok(())  => prop_assert!(data7.iter().all(|&v| v < 0x80)),
+                    Err(e)  => prop_assert_eq!(data7[e.offset], e.byte),
+                }
+            }
+
+            #[test]
+            fn prop_adapters_match(data8 in prop::collection::vec(any::<u8>(), 0..256)) {
+                let mut data7 = vec![];
+                encode_7bit(&data8, &mut data7);
+
+                let iter7 = Encode7Bit::new(data8.iter().cloned()).collect::<Vec<_>>();
+                prop_assert_eq!(&iter7, &data7);
+
+                let iter8 = Decode7Bit::new(data7.iter().cloned()).collect::<Vec<_>>();
+                prop_assert_eq!(iter8, data8);
+            }
+        }
+    }
+}
@@ -14,8 +14,17 @@
 // You should have received a copy of the GNU General Public License
 // along with a6-tools.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::cmp;
+use std::error;
+use std::fmt;
+use std::fs::{self, File};
 use std::io::prelude::*;
-use std::io::{self, Error};
+use std::io::{self, BufWriter, Cursor, Error};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
 use std::io::ErrorKind::{Interrupted, UnexpectedEof};
 use util::FindBits;
 
@@ -23,6 +32,14 @@ use util::FindBits;
 pub trait ErrorExt {
     /// Returns `true` if the error is a transient error, `false` otherwise.
     fn is_transient(&self) -> bool;
+
+    /// Returns `true` if the error reports that an operation was cancelled via
+    /// a `CancelToken`, `false` otherwise.
+    fn is_cancelled(&self) -> bool;
+
+    /// Returns `true` if the error reports that an operation exceeded its
+    /// memory limit, `false` otherwise.
+    fn is_memory_limit(&self) -> bool;
 }
 
 impl ErrorExt for Error {
@@ -30,14 +47,113 @@ impl ErrorExt for Error {
     fn is_transient(&self) -> bool {
         self.kind() == Interrupted
     }
+
+    #[inline]
+    fn is_cancelled(&self) -> bool {
+        self.get_ref().map_or(false, |e| e.is::<Cancelled>())
+    }
+
+    #[inline]
+    fn is_memory_limit(&self) -> bool {
+        self.get_ref().map_or(false, |e| e.is::<MemoryLimitExceeded>())
+    }
+}
+
+/// A flag by which one thread can ask a long-running operation on another
+/// thread to stop.  Clones of a token share the same flag.
+#[derive(Clone, Default, Debug)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// Creates a `CancelToken` that is not cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requests cancellation of operations observing the token.
+    #[inline]
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst)
+    }
+
+    /// Returns `true` if cancellation has been requested, `false` otherwise.
+    #[inline]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// The error reported by an operation stopped via a `CancelToken`.  Converts
+/// to an `io::Error` for which `is_cancelled` returns `true`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Cancelled;
+
+impl Cancelled {
+    /// Gets the stable diagnostic code of the error.
+    #[inline]
+    pub fn code(&self) -> &'static str {
+        "A6-IO-001"
+    }
+}
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: The operation was cancelled.", self.code())
+    }
+}
+
+impl error::Error for Cancelled { }
+
+impl From<Cancelled> for Error {
+    fn from(c: Cancelled) -> Self {
+        Error::new(io::ErrorKind::Other, c)
+    }
+}
+
+/// The error reported by an operation that needed more memory than its limit
+/// allows.  Converts to an `io::Error` for which `is_memory_limit` returns
+/// `true`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MemoryLimitExceeded {
+    /// The limit, in bytes.
+    pub limit: usize,
+
+    /// The memory in use when the limit was exceeded, in bytes.
+    pub used: usize,
+}
+
+impl MemoryLimitExceeded {
+    /// Gets the stable diagnostic code of the error.
+    #[inline]
+    pub fn code(&self) -> &'static str {
+        "A6-IO-002"
+    }
+}
+
+impl fmt::Display for MemoryLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f, "{}: Memory limit exceeded: {} byte(s) in use. \
+                The limit is {} bytes.",
+            self.code(), self.used, self.limit
+        )
+    }
+}
+
+impl error::Error for MemoryLimitExceeded { }
+
+impl From<MemoryLimitExceeded> for Error {
+    fn from(e: MemoryLimitExceeded) -> Self {
+        Error::new(io::ErrorKind::OutOfMemory, e)
+    }
 }
 
 macro_rules! def_read {
     {
-        $( $name:ident ( $n:expr, $v:ident: $t:ty ) { $e:expr } )*
+        $( $name:ident ( $t:ty, $from:ident ) $doc:expr; )*
     } => {
         $(
-            /// Reads a `$t`.
+            #[doc = $doc]
             ///
             /// # Errors
             ///
@@ -46,18 +162,15 @@ macro_rules! def_read {
             /// * `ErrorKind::Interrupted` errors are ignored.
             ///
             /// * Other errors indicate failure.  Actual number of bytes read is
-            ///   unspecified, other than <= size of `$t`.
+            ///   unspecified, other than <= size of the value type.
             ///
             fn $name(&mut self) -> io::Result<$t> {
-                use std::mem;
-
                 // Read into temporary buffer
-                let mut buf = [0; $n];
+                let mut buf = [0; ::std::mem::size_of::<$t>()];
                 self.read_exact(&mut buf)?;
 
                 // Interpret as desired type
-                let $v: $t = unsafe { mem::transmute(buf) };
-                Ok($e)
+                Ok(<$t>::$from(buf))
             }
         )*
     }
@@ -65,9 +178,18 @@ macro_rules! def_read {
 
 pub trait ReadExt: Read {
     def_read! {
-        read_u8  (1, v: u8 ) { v         }
-        read_u16 (2, v: u16) { v.to_be() }
-        read_u32 (4, v: u32) { v.to_be() }
+        read_u8     (u8,  from_be_bytes) "Reads a `u8`.";
+        read_i8     (i8,  from_be_bytes) "Reads an `i8`.";
+        read_u16    (u16, from_be_bytes) "Reads a big-endian `u16`.";
+        read_u16_le (u16, from_le_bytes) "Reads a little-endian `u16`.";
+        read_i16    (i16, from_be_bytes) "Reads a big-endian `i16`.";
+        read_i16_le (i16, from_le_bytes) "Reads a little-endian `i16`.";
+        read_u32    (u32, from_be_bytes) "Reads a big-endian `u32`.";
+        read_u32_le (u32, from_le_bytes) "Reads a little-endian `u32`.";
+        read_i32    (i32, from_be_bytes) "Reads a big-endian `i32`.";
+        read_i32_le (i32, from_le_bytes) "Reads a little-endian `i32`.";
+        read_u64    (u64, from_be_bytes) "Reads a big-endian `u64`.";
+        read_u64_le (u64, from_le_bytes) "Reads a little-endian `u64`.";
     }
 }
 
@@ -124,7 +246,8 @@ pub trait BufReadExt {
         -> io::Result<(usize, Option<u8>)>
     {
         self.scan_until_bits(bits, mask, |bytes| {
-            buf.write(bytes).unwrap();
+            // Excess bytes are discarded by design
+            let _ = buf.write(bytes);
         })
     }
 }
@@ -170,6 +293,395 @@ impl<R: BufRead> BufReadExt for R {
     }
 }
 
+/// Trait for input streams that track the position of the next byte to be
+/// read.  Positions reported by the readers and parsers in this crate are
+/// based on this trait, so that every front end reports the same location
+/// for a given condition.
+pub trait TrackedRead {
+    /// Gets the offset of the next byte to be read, in bytes from the start
+    /// of the stream.
+    fn offset(&self) -> usize;
+
+    /// Gets the time at which the next byte to be read arrived, relative to
+    /// the start of the stream, or `None` if the stream is not timed.
+    #[inline]
+    fn timestamp(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl<T: AsRef<[u8]>> TrackedRead for Cursor<T> {
+    #[inline]
+    fn offset(&self) -> usize {
+        self.position() as usize
+    }
+}
+
+/// A reader that counts the bytes read from an underlying reader.
+#[derive(Clone, Debug)]
+pub struct CountingReader<R> {
+    inner:  R,
+    offset: usize,
+}
+
+impl<R> CountingReader<R> {
+    /// Creates a `CountingReader` that reads from the given `inner` reader.
+    pub fn new(inner: R) -> Self {
+        Self::with_offset(inner, 0)
+    }
+
+    /// Creates a `CountingReader` that reads from the given `inner` reader,
+    /// counting from the given initial `offset`.
+    pub fn with_offset(inner: R, offset: usize) -> Self {
+        Self { inner, offset }
+    }
+
+    /// Gets a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Unwraps the `CountingReader`, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.offset += n;
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for CountingReader<R> {
+    #[inline]
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    #[inline]
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt);
+        self.offset += amt;
+    }
+}
+
+impl<R> TrackedRead for CountingReader<R> {
+    #[inline]
+    fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+/// A reader that reads a sequence of readers as one logical stream, and that
+/// attributes stream offsets to the individual readers.
+#[derive(Clone, Debug)]
+pub struct MultiReader<R> {
+    /// Readers, in order.
+    readers: Vec<R>,
+
+    /// Index of the current reader.
+    index: usize,
+
+    /// Stream offsets at which the readers started, for readers reached.
+    starts: Vec<usize>,
+
+    /// Stream offset of the next byte to be read.
+    offset: usize,
+}
+
+impl<R> MultiReader<R> {
+    /// Creates a `MultiReader` that reads from the given `readers` in order.
+    pub fn new(readers: Vec<R>) -> Self {
+        Self { readers, index: 0, starts: vec![0], offset: 0 }
+    }
+
+    /// Gets the index of the reader currently being read.
+    #[inline]
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Locates the given stream `offset`, returning the index of the reader
+    /// that contains it and the offset relative to the start of that reader.
+    /// Returns `None` if the offset has not been reached yet.
+    pub fn locate(&self, offset: usize) -> Option<(usize, usize)> {
+        if offset > self.offset {
+            return None
+        }
+
+        // Find last reader starting at or before offset
+        let index = match self.starts.binary_search(&offset) {
+            Ok(mut i) => {
+                // Skip empty readers
+                while i + 1 < self.starts.len() && self.starts[i + 1] == offset { i += 1 }
+                i
+            },
+            Err(i) => i - 1,
+        };
+
+        Some((index, offset - self.starts[index]))
+    }
+
+    /// Unwraps the `MultiReader`, returning the underlying readers.
+    pub fn into_inner(self) -> Vec<R> {
+        self.readers
+    }
+}
+
+impl<R: BufRead> Read for MultiReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = {
+            let src = self.fill_buf()?;
+            let n   = src.len().min(buf.len());
+            buf[..n].copy_from_slice(&src[..n]);
+            n
+        };
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for MultiReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        // Advance past exhausted readers
+        while self.index < self.readers.len() {
+            if self.readers[self.index].fill_buf()?.len() != 0 {
+                return self.readers[self.index].fill_buf()
+            }
+            if self.index + 1 < self.readers.len() {
+                self.starts.push(self.offset);
+            }
+            self.index += 1;
+        }
+
+        Ok(&[])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if let Some(r) = self.readers.get_mut(self.index) {
+            r.consume(amt);
+            self.offset += amt;
+        }
+    }
+}
+
+impl<R> TrackedRead for MultiReader<R> {
+    #[inline]
+    fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+/// A reader that decodes hexadecimal text, such as `F0 00 00 0E 1D …`, from an
+/// underlying reader.  Each byte is a pair of hex digits of either case.  Pairs
+/// can be separated by whitespace, but a pair cannot be split.  Any other
+/// character is an `InvalidData` error.
+#[derive(Clone, Debug)]
+pub struct HexReader<R> {
+    inner: R,
+    buf:   Vec<u8>,     // Decoded bytes
+    pos:   usize,       // Position of next unread byte in buf
+    high:  Option<u8>,  // High digit of pair in progress
+}
+
+impl<R> HexReader<R> {
+    /// Creates a `HexReader` that decodes text from the given `inner` reader.
+    pub fn new(inner: R) -> Self {
+        Self { inner, buf: vec![], pos: 0, high: None }
+    }
+
+    /// Gets a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Unwraps the `HexReader`, returning the underlying reader.  Any decoded
+    /// but unread bytes are lost.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+// Decodes the given hex `text`, appending bytes to `buf`.  The pending high
+// digit of a pair split across calls is kept in `high`.  Returns the count of
+// text bytes decoded, which excludes any invalid character.
+fn decode_hex(text: &[u8], buf: &mut Vec<u8>, high: &mut Option<u8>)
+    -> (usize, io::Result<()>)
+{
+    for (i, &c) in text.iter().enumerate() {
+        let digit = match c {
+            b'0'...b'9' => c - b'0',
+            b'A'...b'F' => c - b'A' + 10,
+            b'a'...b'f' => c - b'a' + 10,
+            b' ' | b'\t' | b'\r' | b'\n' | b'\x0C' => {
+                if high.is_some() {
+                    return (i, Err(invalid_hex("Hex digit pair is split by whitespace.")))
+                }
+                continue
+            },
+            _ => {
+                return (i, Err(invalid_hex("Invalid character in hex text.")))
+            },
+        };
+
+        match high.take() {
+            None    => *high = Some(digit),
+            Some(h) => buf.push(h << 4 | digit),
+        }
+    }
+    (text.len(), Ok(()))
+}
+
+fn invalid_hex(msg: &'static str) -> Error {
+    Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl<R: BufRead> Read for HexReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = {
+            let src = self.fill_buf()?;
+            let n   = src.len().min(buf.len());
+            buf[..n].copy_from_slice(&src[..n]);
+            n
+        };
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for HexReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        // Decode text until at least one byte is available, or until EOF
+        while self.pos == self.buf.len() {
+            self.buf.clear();
+            self.pos = 0;
+
+            let (n, result) = {
+                let text = self.inner.fill_buf()?;
+                decode_hex(text, &mut self.buf, &mut self.high)
+            };
+
+            // On error, consume only the valid text, whose bytes remain to be
+            // read, so that a retry does not decode it again
+            self.inner.consume(n);
+            result?;
+
+            if n == 0 {
+                if self.high.is_some() {
+                    return Err(invalid_hex("Hex text ends with an incomplete digit pair."))
+                }
+                break
+            }
+        }
+
+        Ok(&self.buf[self.pos..])
+    }
+
+    #[inline]
+    fn consume(&mut self, amt: usize) {
+        self.pos = cmp::min(self.pos + amt, self.buf.len());
+    }
+}
+
+/// A file writer that protects the destination file from partial writes.
+///
+/// Data is written to a temporary file in the same directory as the
+/// destination.  On `commit`, the temporary file replaces the destination
+/// atomically.  If the writer is dropped without being committed, the
+/// temporary file is removed, and the destination is left untouched.
+///
+/// For filesystems that do not support atomic replacement, the writer can
+/// instead write to the destination directly.
+#[derive(Debug)]
+pub struct AtomicFile {
+    /// Destination file path.
+    path: PathBuf,
+
+    /// Temporary file path, if writing atomically.
+    temp: Option<PathBuf>,
+
+    /// Writer for the file being written.
+    file: Option<BufWriter<File>>,
+}
+
+// Number of temporary files created by this process, to make their names unique
+static TEMP_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+impl AtomicFile {
+    /// Creates an `AtomicFile` that writes atomically to the given `path`.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::create_with(path, true)
+    }
+
+    /// Creates an `AtomicFile` that writes to the given `path`, atomically if
+    /// `atomic` is `true`, or directly otherwise.
+    pub fn create_with<P: AsRef<Path>>(path: P, atomic: bool) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+
+        let temp = if atomic {
+            let mut name = path.file_name()
+                .ok_or_else(|| Error::new(io::ErrorKind::InvalidInput, "Path has no file name."))?
+                .to_os_string();
+            let n = TEMP_COUNT.fetch_add(1, Ordering::Relaxed);
+            name.push(format!(".{}.{}.tmp", process::id(), n));
+            Some(path.with_file_name(name))
+        } else {
+            None
+        };
+
+        let file = File::create(temp.as_ref().unwrap_or(&path))?;
+
+        Ok(Self { path, temp, file: Some(BufWriter::new(file)) })
+    }
+
+    /// Gets the destination file path.
+    #[inline]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Completes writing, replacing the destination file with the written
+    /// data if writing atomically.
+    pub fn commit(mut self) -> io::Result<()> {
+        let file = self.file.take().unwrap();
+        let file = file.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+
+        // On failure, keep the temporary file path so that drop removes it
+        if let Some(ref temp) = self.temp {
+            fs::rename(temp, &self.path)?;
+        }
+
+        self.temp = None;
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.as_mut().unwrap().write(buf)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.as_mut().unwrap().flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        // Discard uncommitted temporary file
+        if let Some(ref temp) = self.temp {
+            self.file.take();
+            let _ = fs::remove_file(temp);
+        }
+    }
+}
+
 // Saved from prevous work:
 //
 //  /// Returns an unexpected-EOF error at the current offset.
@@ -180,6 +692,49 @@ impl<R: BufRead> BufReadExt for R {
 //      )
 //  }
 
+/// A read-only memory-mapped file, whose bytes can be scanned in place, such
+/// as by `scan_sysex` or `BlockDecoder::decode_bytes`.
+#[cfg(feature = "mmap")]
+#[derive(Debug)]
+pub struct MappedFile {
+    map: ::memmap2::Mmap,
+}
+
+#[cfg(feature = "mmap")]
+impl MappedFile {
+    /// Maps the file at the given `path` into memory.
+    ///
+    /// The file must not be modified while mapped.  If another process
+    /// modifies it, the mapped bytes can change unpredictably.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let map  = unsafe { ::memmap2::Mmap::map(&file)? };
+        Ok(Self { map })
+    }
+
+    /// Gets the bytes of the file.
+    #[inline]
+    pub fn bytes(&self) -> &[u8] {
+        &self.map
+    }
+
+    /// Converts the file into a reader of its bytes.  The reader implements
+    /// `TrackedRead`, so that stream readers such as `read_sysex_tracked` can
+    /// report positions and progress within the file.
+    #[inline]
+    pub fn into_reader(self) -> Cursor<Self> {
+        Cursor::new(self)
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl AsRef<[u8]> for MappedFile {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        &self.map
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -218,6 +773,234 @@ mod tests {
         assert_eq!(src.read_u32().err().unwrap().kind(), UnexpectedEof);
     }
 
+    #[test]
+    fn read_i8() {
+        //  index      0     1
+        let bytes   = [0x12, 0xFE];
+        let mut src = Cursor::new(&bytes);
+
+        assert_eq!(src.read_i8().unwrap(), 0x12);
+        assert_eq!(src.read_i8().unwrap(), -2);
+        assert_eq!(src.read_i8().err().unwrap().kind(), UnexpectedEof);
+    }
+
+    #[test]
+    fn read_u16_le() {
+        //  index      0           1           -
+        let bytes   = [0x12, 0x34, 0x56, 0x78, 0x9A];
+        let mut src = Cursor::new(&bytes);
+
+        assert_eq!(src.read_u16_le().unwrap(), 0x3412);
+        assert_eq!(src.read_u16_le().unwrap(), 0x7856);
+        assert_eq!(src.read_u16_le().err().unwrap().kind(), UnexpectedEof);
+    }
+
+    #[test]
+    fn read_i16() {
+        //  index      0           1           -
+        let bytes   = [0x12, 0x34, 0xFF, 0xFE, 0x9A];
+        let mut src = Cursor::new(&bytes);
+
+        assert_eq!(src.read_i16().unwrap(), 0x1234);
+        assert_eq!(src.read_i16().unwrap(), -2);
+        assert_eq!(src.read_i16().err().unwrap().kind(), UnexpectedEof);
+    }
+
+    #[test]
+    fn read_i16_le() {
+        //  index      0           1           -
+        let bytes   = [0x12, 0x34, 0xFE, 0xFF, 0x9A];
+        let mut src = Cursor::new(&bytes);
+
+        assert_eq!(src.read_i16_le().unwrap(), 0x3412);
+        assert_eq!(src.read_i16_le().unwrap(), -2);
+        assert_eq!(src.read_i16_le().err().unwrap().kind(), UnexpectedEof);
+    }
+
+    #[test]
+    fn read_u32_le() {
+        //  index      0                       1                       -
+        let bytes   = [0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC, 0xDE, 0xF0, 0xA5];
+        let mut src = Cursor::new(&bytes);
+
+        assert_eq!(src.read_u32_le().unwrap(), 0x78563412);
+        assert_eq!(src.read_u32_le().unwrap(), 0xF0DEBC9A);
+        assert_eq!(src.read_u32_le().err().unwrap().kind(), UnexpectedEof);
+    }
+
+    #[test]
+    fn read_i32() {
+        //  index      0                       1                       -
+        let bytes   = [0x12, 0x34, 0x56, 0x78, 0xFF, 0xFF, 0xFF, 0xFE, 0xA5];
+        let mut src = Cursor::new(&bytes);
+
+        assert_eq!(src.read_i32().unwrap(), 0x12345678);
+        assert_eq!(src.read_i32().unwrap(), -2);
+        assert_eq!(src.read_i32().err().unwrap().kind(), UnexpectedEof);
+    }
+
+    #[test]
+    fn read_i32_le() {
+        //  index      0                       1                       -
+        let bytes   = [0x12, 0x34, 0x56, 0x78, 0xFE, 0xFF, 0xFF, 0xFF, 0xA5];
+        let mut src = Cursor::new(&bytes);
+
+        assert_eq!(src.read_i32_le().unwrap(), 0x78563412);
+        assert_eq!(src.read_i32_le().unwrap(), -2);
+        assert_eq!(src.read_i32_le().err().unwrap().kind(), UnexpectedEof);
+    }
+
+    #[test]
+    fn counting_reader_read() {
+        let bytes   = [0x12, 0x34, 0x56, 0x78];
+        let mut src = CountingReader::new(&bytes[..]);
+
+        assert_eq!(src.offset(), 0);
+        assert_eq!(src.read_u16().unwrap(), 0x1234);
+        assert_eq!(src.offset(), 2);
+        assert_eq!(src.read_u8().unwrap(), 0x56);
+        assert_eq!(src.offset(), 3);
+        assert_eq!(src.timestamp(), None);
+    }
+
+    #[test]
+    fn counting_reader_buf_read() {
+        let bytes   = [0x12, 0x34, 0x56, 0x78];
+        let mut src = CountingReader::with_offset(&bytes[..], 100);
+
+        assert_eq!(src.skip_until_bits(0x56, 0xFF).unwrap(), (3, Some(0x56)));
+        assert_eq!(src.offset(), 103);
+    }
+
+    #[test]
+    fn cursor_offset() {
+        let bytes   = [0x12, 0x34, 0x56, 0x78];
+        let mut src = Cursor::new(&bytes);
+
+        src.read_u8().unwrap();
+
+        assert_eq!(TrackedRead::offset(&src), 1);
+    }
+
+    #[test]
+    fn multi_reader_read() {
+        let readers: Vec<&[u8]> = vec![&[0x12, 0x34], &[], &[0x56], &[0x78, 0x9A]];
+        let mut src = MultiReader::new(readers);
+
+        assert_eq!(src.read_u32().unwrap(), 0x12345678);
+        assert_eq!(src.offset(), 4);
+        assert_eq!(src.index(), 3);
+        assert_eq!(src.read_u8().unwrap(), 0x9A);
+        assert_eq!(src.read_u8().err().unwrap().kind(), UnexpectedEof);
+    }
+
+    #[test]
+    fn multi_reader_locate() {
+        let readers: Vec<&[u8]> = vec![&[0x12, 0x34], &[], &[0x56], &[0x78, 0x9A]];
+        let mut src = MultiReader::new(readers);
+
+        assert_eq!(src.skip_until_bits(0x0A, 0xFF).unwrap(), (5, None));
+        assert_eq!(src.locate(0), Some((0, 0)));
+        assert_eq!(src.locate(1), Some((0, 1)));
+        assert_eq!(src.locate(2), Some((2, 0)));
+        assert_eq!(src.locate(3), Some((3, 0)));
+        assert_eq!(src.locate(4), Some((3, 1)));
+        assert_eq!(src.locate(5), Some((3, 2)));
+        assert_eq!(src.locate(6), None);
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        let mut path = ::std::env::temp_dir();
+        path.push(format!("a6-tools-test-{}-{}", process::id(), name));
+        path
+    }
+
+    #[test]
+    fn atomic_file_commit() {
+        let path = temp_path("commit");
+        fs::write(&path, b"old").unwrap();
+
+        let mut file = AtomicFile::create(&path).unwrap();
+        file.write_all(b"new").unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"old");
+        file.commit().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn atomic_file_abort() {
+        let path = temp_path("abort");
+        fs::write(&path, b"old").unwrap();
+
+        {
+            let mut file = AtomicFile::create(&path).unwrap();
+            file.write_all(b"new").unwrap();
+            // dropped without commit
+        }
+
+        assert_eq!(fs::read(&path).unwrap(), b"old");
+        assert_eq!(leftovers(&path), 0);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn atomic_file_commit_failed() {
+        let path = temp_path("commit-failed");
+        fs::create_dir(&path).unwrap();
+
+        let mut file = AtomicFile::create(&path).unwrap();
+        file.write_all(b"new").unwrap();
+
+        assert!(file.commit().is_err());
+        assert_eq!(leftovers(&path), 0);
+
+        fs::remove_dir(&path).unwrap();
+    }
+
+    #[test]
+    fn atomic_file_concurrent() {
+        let path = temp_path("concurrent");
+
+        let mut a = AtomicFile::create(&path).unwrap();
+        let mut b = AtomicFile::create(&path).unwrap();
+        a.write_all(b"a").unwrap();
+        b.write_all(b"b").unwrap();
+
+        a.commit().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"a");
+        b.commit().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"b");
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    // Counts the files beside `path` whose names extend its name
+    fn leftovers(path: &Path) -> usize {
+        let dir  = path.parent().unwrap();
+        let name = path.file_name().unwrap().to_str().unwrap().to_string();
+        fs::read_dir(dir).unwrap()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_name().to_str().map_or(false, |n| n.starts_with(&name) && n != name))
+            .count()
+    }
+
+    #[test]
+    fn atomic_file_direct() {
+        let path = temp_path("direct");
+
+        let mut file = AtomicFile::create_with(&path, false).unwrap();
+        file.write_all(b"new").unwrap();
+        file.commit().unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+
+        fs::remove_file(&path).unwrap();
+    }
+
     #[test]
     fn skip_until_bits_found() {
         let bytes   = [0x12, 0x34, 0x56, 0x78];
@@ -257,5 +1040,122 @@ mod tests {
         assert_eq!(buf[0], 0x12);
         assert_eq!(buf[1], 0x34);
     }
-}
 
+    #[test]
+    fn hex_reader() {
+        let text    = b"F0 00 00 0e 1D\r\n\t3000f7\n";
+        let mut src = HexReader::new(&text[..]);
+        let mut buf = vec![];
+
+        src.read_to_end(&mut buf).unwrap();
+
+        assert_eq!(buf, b"\xF0\x00\x00\x0E\x1D\x30\x00\xF7");
+    }
+
+    #[test]
+    fn hex_reader_chunked() {
+        // Digit pair split across chunks of the underlying reader
+        let text    = io::BufReader::with_capacity(3, &b"F0 41 F7"[..]);
+        let mut src = HexReader::new(text);
+        let mut buf = vec![];
+
+        src.read_to_end(&mut buf).unwrap();
+
+        assert_eq!(buf, b"\xF0\x41\xF7");
+    }
+
+    #[test]
+    fn hex_reader_invalid_char() {
+        let mut src = HexReader::new(&b"F0 4G"[..]);
+        let mut buf = vec![];
+
+        let err = src.read_to_end(&mut buf).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn hex_reader_retry() {
+        let mut src = HexReader::new(&b"F0 41 zz"[..]);
+        let mut buf = [0; 8];
+
+        assert_eq!(src.read(&mut buf).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(src.read(&mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], b"\xF0\x41");
+        assert_eq!(src.read(&mut buf).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn hex_reader_split_pair() {
+        let mut src = HexReader::new(&b"F 0"[..]);
+        let mut buf = vec![];
+
+        let err = src.read_to_end(&mut buf).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn hex_reader_incomplete() {
+        let mut src = HexReader::new(&b"F0 4"[..]);
+        let mut buf = vec![];
+
+        let err = src.read_to_end(&mut buf).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn mapped_file() {
+        let path = temp_path("mapped");
+        fs::write(&path, b"\xF0abc\xF7").unwrap();
+
+        let bytes = MappedFile::open(&path).unwrap().bytes().to_vec();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(bytes, b"\xF0abc\xF7");
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn mapped_file_reader() {
+        use std::cell::RefCell;
+        use sysex::read_sysex_tracked;
+
+        let path = temp_path("mapped-reader");
+        fs::write(&path, b"ab\xF0cd\xF7").unwrap();
+
+        let mut src = MappedFile::open(&path).unwrap().into_reader();
+        let msgs    = RefCell::new(vec![]);
+        let result  = read_sysex_tracked(
+            &mut src, 10,
+            |pos, msg| { msgs.borrow_mut().push((pos, msg.to_vec())); true },
+            |_, _, _| true,
+        );
+        fs::remove_file(&path).unwrap();
+
+        assert!(result.unwrap());
+        assert_eq!(msgs.into_inner(), vec![(2, b"cd".to_vec())]);
+        assert_eq!(src.offset(), 6);
+    }
+
+    #[test]
+    fn cancel_token() {
+        let token = CancelToken::new();
+        let clone = token.clone();
+        assert!(!token.is_cancelled());
+
+        clone.cancel();
+
+        assert!(token.is_cancelled());
+    }
+
+    #[test]
+    fn cancelled_error() {
+        let err: Error = Cancelled.into();
+
+        assert!(err.is_cancelled());
+        assert!(!Error::new(io::ErrorKind::Other, "other").is_cancelled());
+    }
+}
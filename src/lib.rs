@@ -17,8 +17,62 @@
 // Squelch noise while experimenting
 #![allow(warnings)]
 
+// Without std, only the codec and block parsing core is available.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(feature = "std"))]
+#[macro_use]
+extern crate alloc;
+
+// Lets the core modules use `std::` paths with or without std.
+#[cfg(not(feature = "std"))]
+extern crate core as std;
+
+#[cfg(feature = "arbitrary")]
+extern crate arbitrary;
+
+#[cfg(feature = "mmap")]
+extern crate memmap2;
+
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+
+#[cfg(all(test, feature = "std"))]
+extern crate proptest;
+
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+
+// Supporting modules are public for the tools in this repository, but their
+// supported items are those re-exported by `prelude`.
 pub mod a6;
+#[doc(hidden)]
+pub mod codec;
+#[cfg(feature = "std")]
+#[doc(hidden)]
 pub mod io;
+#[cfg(feature = "std")]
+#[doc(hidden)]
+pub mod midi;
+#[cfg(feature = "std")]
+#[doc(hidden)]
+pub mod pipeline;
+pub mod prelude;
+#[cfg(feature = "std")]
+#[doc(hidden)]
+pub mod repair;
+#[cfg(feature = "std")]
+#[doc(hidden)]
 pub mod sysex;
+#[doc(hidden)]
 pub mod util;
 
+// Heap types not in the core prelude.
+#[cfg(not(feature = "std"))]
+mod heap {
+    pub use alloc::borrow::Cow;
+    pub use alloc::boxed::Box;
+    pub use alloc::vec::Vec;
+}
+
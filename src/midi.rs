@@ -0,0 +1,403 @@
+// This file is part of a6-tools.
+// Copyright (C) 2017 Jeffrey Sharp
+//
+// a6-tools is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License,
+// or (at your option) any later version.
+//
+// a6-tools is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See
+// the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with a6-tools.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::mem;
+
+use sysex::*;
+
+/// MIDI messages reported by `MidiParser`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum MidiEvent {
+    // Channel voice messages (channel is 0-based)
+    NoteOff         { channel: u8, key: u8, velocity: u8 },
+    NoteOn          { channel: u8, key: u8, velocity: u8 },
+    PolyPressure    { channel: u8, key: u8, pressure: u8 },
+    ControlChange   { channel: u8, controller: u8, value: u8 },
+    ProgramChange   { channel: u8, program: u8 },
+    ChannelPressure { channel: u8, pressure: u8 },
+    PitchBend       { channel: u8, value: u16 },
+
+    /// A System Exclusive message.  The data excludes the start/end bytes and
+    /// any interleaved real-time bytes.
+    SysEx(Vec<u8>),
+
+    // System common messages
+    TimeCode(u8),
+    SongPosition(u16),
+    SongSelect(u8),
+    TuneRequest,
+
+    /// A system real-time message.
+    RealTime(u8),
+
+    /// Bytes that do not form a valid message: data bytes without a status,
+    /// messages interrupted or truncated, and undefined status bytes.
+    Invalid { len: usize },
+}
+
+/// An incremental parser of complete MIDI traffic: channel voice messages,
+/// System Exclusive messages, system common messages, and system real-time
+/// messages.
+///
+/// A `MidiParser` consumes input in chunks of arbitrary size via `feed` and
+/// reports each message with its position.  Running status is supported.
+/// Real-time messages interleaved within other messages are reported at once,
+/// before the message that contains them.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct MidiParser {
+    /// Position of next unfed byte.
+    offset: usize,
+
+    /// Start position of message in progress.
+    start: usize,
+
+    /// Status of message in progress, if any.
+    status: Option<u8>,
+
+    /// Running status, if any.
+    running: Option<u8>,
+
+    /// Data bytes of channel or system common message in progress.
+    data: [u8; 2],
+
+    /// Count of data bytes of channel or system common message in progress.
+    count: usize,
+
+    /// Data of System Exclusive message in progress.
+    sysex: Vec<u8>,
+
+    /// Start position and length of invalid bytes in progress, if any.
+    junk: Option<(usize, usize)>,
+}
+
+impl MidiParser {
+    /// Creates a `MidiParser`.
+    pub fn new() -> Self {
+        Self {
+            offset: 0, start: 0, status: None, running: None,
+            data: [0; 2], count: 0, sysex: vec![], junk: None,
+        }
+    }
+
+    /// Gets the position of the next byte to be fed to the parser.
+    #[inline]
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Parses the given chunk of `bytes`, invoking the handler `on_event` with
+    /// the position of each message.
+    ///
+    /// Returns `true` if all bytes were consumed.  Returns `false` if the
+    /// handler returned `false` (stop); in that case, `offset()` indicates how
+    /// many bytes were consumed.
+    pub fn feed<F>(&mut self, bytes: &[u8], mut on_event: F) -> bool
+    where
+        F: FnMut(usize, MidiEvent) -> bool,
+    {
+        macro_rules! fire {
+            ($pos:expr, $event:expr) => {
+                if !on_event($pos, $event) { return false }
+            }
+        }
+
+        // Events for bytes before the current one fire before the current byte
+        // counts as consumed, so that resuming at `offset()` after a stop
+        // handles the current byte.
+        for &b in bytes {
+            let pos = self.offset;
+
+            // Real-time messages interrupt nothing
+            if b >= SYSRT_MIN {
+                if let Some(e) = self.flush_junk() { fire!(e.0, e.1) }
+                self.offset = pos + 1;
+                match b {
+                    0xF9 | 0xFD => fire!(pos, MidiEvent::Invalid { len: 1 }),
+                    _           => fire!(pos, MidiEvent::RealTime(b)),
+                }
+                continue
+            }
+
+            // Data bytes continue the message in progress
+            if b <= DATA_MAX {
+                self.offset = pos + 1;
+
+                if self.status == Some(SYSEX_START) {
+                    self.sysex.push(b);
+                    continue
+                }
+
+                if self.status.is_none() {
+                    match self.running {
+                        Some(s) => { self.begin(pos, s) },
+                        None    => { self.add_junk(pos, 1); continue },
+                    }
+                }
+
+                self.data[self.count] = b;
+                self.count += 1;
+
+                let status = self.status.unwrap();
+                if self.count == data_len(status) {
+                    let event = make_event(status, self.data);
+                    let start = self.start;
+                    self.status = None;
+                    fire!(start, event);
+                }
+                continue
+            }
+
+            // Status bytes end any message in progress
+            if b == SYSEX_END && self.status == Some(SYSEX_START) {
+                self.offset = pos + 1;
+                let data = mem::replace(&mut self.sysex, vec![]);
+                let start = self.start;
+                self.status = None;
+                fire!(start, MidiEvent::SysEx(data));
+                continue
+            }
+
+            if let Some(e) = self.interrupt(pos) { fire!(e.0, e.1) }
+            if let Some(e) = self.flush_junk() { fire!(e.0, e.1) }
+            self.offset = pos + 1;
+
+            match b {
+                STATUS_MIN...STATUS_MAX => {
+                    self.running = Some(b);
+                    self.begin(pos, b);
+                },
+                SYSEX_START => {
+                    self.running = None;
+                    self.begin(pos, b);
+                },
+                0xF6 => {
+                    self.running = None;
+                    fire!(pos, MidiEvent::TuneRequest);
+                },
+                0xF1 | 0xF2 | 0xF3 => {
+                    self.running = None;
+                    self.begin(pos, b);
+                },
+                _ => {
+                    // F4, F5 (undefined), F7 (stray end)
+                    self.running = None;
+                    fire!(pos, MidiEvent::Invalid { len: 1 });
+                },
+            }
+        }
+
+        true
+    }
+
+    /// Ends parsing, reporting any incomplete message as invalid to the
+    /// handler `on_event`.
+    ///
+    /// Returns `false` if the handler returned `false` (stop), `true`
+    /// otherwise.
+    pub fn finish<F>(&mut self, mut on_event: F) -> bool
+    where
+        F: FnMut(usize, MidiEvent) -> bool,
+    {
+        let junk = self.flush_junk();
+        let msg  = self.interrupt(self.offset);
+        self.running = None;
+
+        junk.map_or(true, |e| on_event(e.0, e.1)) &&
+        msg .map_or(true, |e| on_event(e.0, e.1))
+    }
+
+    // Begins a message with the given `status` at the given position.
+    fn begin(&mut self, pos: usize, status: u8) {
+        self.start  = pos;
+        self.status = Some(status);
+        self.count  = 0;
+        self.sysex.clear();
+    }
+
+    // Abandons the message in progress, if any, at the given end position,
+    // returning it as invalid.
+    fn interrupt(&mut self, end: usize) -> Option<(usize, MidiEvent)> {
+        self.status.take().map(|_| {
+            (self.start, MidiEvent::Invalid { len: end - self.start })
+        })
+    }
+
+    // Adds `len` invalid bytes at the given position.
+    fn add_junk(&mut self, pos: usize, len: usize) {
+        self.junk = match self.junk {
+            Some((start, n)) => Some((start, n + len)),
+            None             => Some((pos, len)),
+        };
+    }
+
+    // Takes the invalid bytes in progress, if any, as an event.
+    fn flush_junk(&mut self) -> Option<(usize, MidiEvent)> {
+        self.junk.take().map(|(pos, len)| (pos, MidiEvent::Invalid { len }))
+    }
+}
+
+impl Default for MidiParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Returns the count of data bytes that follow the given status byte.
+fn data_len(status: u8) -> usize {
+    match status & 0xF0 {
+        0xC0 | 0xD0 => 1,
+        0xF0        => match status { 0xF2 => 2, _ => 1 },
+        _           => 2,
+    }
+}
+
+// Constructs the event for the given `status` and `data` bytes.
+fn make_event(status: u8, data: [u8; 2]) -> MidiEvent {
+    use self::MidiEvent::*;
+
+    let channel = status & 0x0F;
+    let (a, b)  = (data[0], data[1]);
+
+    match status & 0xF0 {
+        0x80 => NoteOff         { channel, key: a, velocity: b },
+        0x90 => NoteOn          { channel, key: a, velocity: b },
+        0xA0 => PolyPressure    { channel, key: a, pressure: b },
+        0xB0 => ControlChange   { channel, controller: a, value: b },
+        0xC0 => ProgramChange   { channel, program: a },
+        0xD0 => ChannelPressure { channel, pressure: a },
+        0xE0 => PitchBend       { channel, value: a as u16 | (b as u16) << 7 },
+        _    => match status {
+            0xF1 => TimeCode(a),
+            0xF2 => SongPosition(a as u16 | (b as u16) << 7),
+            _    => SongSelect(a),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::MidiEvent::*;
+
+    fn parse(chunks: &[&[u8]]) -> Vec<(usize, MidiEvent)> {
+        let mut events = vec![];
+        let mut parser = MidiParser::new();
+
+        for chunk in chunks {
+            assert!(parser.feed(chunk, |pos, e| { events.push((pos, e)); true }));
+        }
+        assert!(parser.finish(|pos, e| { events.push((pos, e)); true }));
+        events
+    }
+
+    #[test]
+    fn channel_messages() {
+        let events = parse(&[b"\x91\x40\x7F\x81\x40\x00\xB2\x07\x64\xC3\x05\xD4\x20\xE5\x00\x40\xA6\x3C\x10"]);
+
+        assert_eq!(events, vec![
+            ( 0, NoteOn          { channel: 1, key: 0x40, velocity: 0x7F }),
+            ( 3, NoteOff         { channel: 1, key: 0x40, velocity: 0x00 }),
+            ( 6, ControlChange   { channel: 2, controller: 7, value: 100 }),
+            ( 9, ProgramChange   { channel: 3, program: 5 }),
+            (11, ChannelPressure { channel: 4, pressure: 0x20 }),
+            (13, PitchBend       { channel: 5, value: 0x2000 }),
+            (16, PolyPressure    { channel: 6, key: 0x3C, pressure: 0x10 }),
+        ]);
+    }
+
+    #[test]
+    fn stop_and_resume() {
+        let mut parser = MidiParser::new();
+        let mut events = vec![];
+        let input      = b"\x01\x90\x40\x7F";
+
+        assert!(!parser.feed(input, |pos, e| { events.push((pos, e)); false }));
+        assert_eq!(parser.offset(), 1);
+        assert!(parser.feed(&input[parser.offset()..], |pos, e| { events.push((pos, e)); true }));
+
+        assert_eq!(events, vec![
+            (0, Invalid { len: 1 }),
+            (1, NoteOn  { channel: 0, key: 0x40, velocity: 0x7F }),
+        ]);
+    }
+
+    #[test]
+    fn stop_and_resume_every_event() {
+        let input    = b"\x01\x02\xF8\x03\x90\x40\xB0\x07\x01\x04\
+                         \xF0\x11\xFE\x12\xC0\x05\xF6\x06\xF4";
+        let expected = parse(&[input]);
+
+        // Stop after each event, then resume at the reported offset
+        let mut parser = MidiParser::new();
+        let mut events = vec![];
+        while !parser.feed(&input[parser.offset()..], |pos, e| { events.push((pos, e)); false }) { }
+        assert!(parser.finish(|pos, e| { events.push((pos, e)); true }));
+
+        assert_eq!(events, expected);
+    }
+
+    #[test]
+    fn running_status_across_chunks() {
+        let events = parse(&[b"\x90\x40", b"\x7F\x41", b"\x7F\xC0\x01\x02"]);
+
+        assert_eq!(events, vec![
+            (0, NoteOn        { channel: 0, key: 0x40, velocity: 0x7F }),
+            (3, NoteOn        { channel: 0, key: 0x41, velocity: 0x7F }),
+            (5, ProgramChange { channel: 0, program: 1 }),
+            (7, ProgramChange { channel: 0, program: 2 }),
+        ]);
+    }
+
+    #[test]
+    fn sysex_and_real_time() {
+        let events = parse(&[b"\xF0\x00\x00\xF8\x0E\xF7\x90\x40\xFE\x7F"]);
+
+        assert_eq!(events, vec![
+            (3, RealTime(0xF8)),
+            (0, SysEx(vec![0x00, 0x00, 0x0E])),
+            (8, RealTime(0xFE)),
+            (6, NoteOn { channel: 0, key: 0x40, velocity: 0x7F }),
+        ]);
+    }
+
+    #[test]
+    fn system_common() {
+        let events = parse(&[b"\xF1\x23\xF2\x01\x02\xF3\x05\xF6\x10"]);
+
+        assert_eq!(events, vec![
+            (0, TimeCode(0x23)),
+            (2, SongPosition(0x0101)),
+            (5, SongSelect(5)),
+            (7, TuneRequest),
+            (8, Invalid { len: 1 }),    // system common cancels running status
+        ]);
+    }
+
+    #[test]
+    fn invalid() {
+        let events = parse(&[b"\x01\x02\x90\x40\xF0\x01\xB0\x07\xF4\xF7\x80\x40"]);
+
+        assert_eq!(events, vec![
+            ( 0, Invalid { len: 2 }),   // data without status
+            ( 2, Invalid { len: 2 }),   // note on interrupted by F0
+            ( 4, Invalid { len: 2 }),   // SysEx interrupted by B0
+            ( 6, Invalid { len: 2 }),   // control change interrupted by F4
+            ( 8, Invalid { len: 1 }),   // F4 undefined
+            ( 9, Invalid { len: 1 }),   // F7 stray
+            (10, Invalid { len: 2 }),   // note off truncated at EOF
+        ]);
+    }
+}
@@ -0,0 +1,306 @@
+// This file is part of a6-tools.
+// Copyright (C) 2017 Jeffrey Sharp
+//
+// a6-tools is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License,
+// or (at your option) any later version.
+//
+// a6-tools is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See
+// the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with a6-tools.  If not, see <http://www.gnu.org/licenses/>.
+
+
+// Composable stages for SysEx processing.
+//
+// A pipeline is a chain of sinks passed to `read_sysex_with`.  Each adapter
+// here wraps a downstream sink, so a chain reads from the last stage back to
+// the first: `store.map_messages(rename).filter(is_program)` filters, then
+// renames, then stores.  Cancellation is shared through
+// `ReadOptions::cancel`, and a `SyncSender` stage applies backpressure by
+// blocking reading while its channel is full.
+
+use std::borrow::Cow;
+use std::io::{self, Write};
+
+use sysex::{SysExEvent, SysExSink, SYSEX_START, SYSEX_END};
+
+/// Extension methods for chaining `SysExSink` stages.
+pub trait SysExSinkExt: SysExSink + Sized {
+    /// Returns a sink that passes to this sink only the events for which
+    /// `pred` returns `true`.
+    fn filter<P>(self, pred: P) -> Filter<Self, P>
+        where P: FnMut(&SysExEvent) -> bool
+    {
+        Filter { sink: self, pred }
+    }
+
+    /// Returns a sink that replaces the data of each message with the result
+    /// of `f`, or drops the message if `f` returns `None`.  Other events pass
+    /// unchanged.  Replaced messages have no `raw` bytes.
+    fn map_messages<F>(self, f: F) -> MapMessages<Self, F>
+        where F: FnMut(&[u8]) -> Option<Vec<u8>>
+    {
+        MapMessages { sink: self, f }
+    }
+
+    /// Returns a sink that passes each event to both this sink and `other`.
+    /// Reading stops if either stops.
+    fn tee<T: SysExSink>(self, other: T) -> Tee<Self, T> {
+        Tee { a: self, b: other }
+    }
+
+    /// Returns a sink that counts the events passing to this sink.
+    fn counted(self) -> Counted<Self> {
+        Counted { sink: self, stats: SinkStats::default() }
+    }
+}
+
+impl<S: SysExSink> SysExSinkExt for S { }
+
+/// A sink that passes on events matching a predicate.  See
+/// `SysExSinkExt::filter`.
+#[derive(Clone, Debug)]
+pub struct Filter<S, P> {
+    sink: S,
+    pred: P,
+}
+
+impl<S, P> SysExSink for Filter<S, P>
+    where S: SysExSink, P: FnMut(&SysExEvent) -> bool
+{
+    fn on_event(&mut self, event: SysExEvent) -> bool {
+        !(self.pred)(&event) || self.sink.on_event(event)
+    }
+
+    fn on_progress(&mut self, read: usize, total: Option<usize>) -> bool {
+        self.sink.on_progress(read, total)
+    }
+}
+
+/// A sink that transforms message data.  See `SysExSinkExt::map_messages`.
+#[derive(Clone, Debug)]
+pub struct MapMessages<S, F> {
+    sink: S,
+    f:    F,
+}
+
+impl<S, F> SysExSink for MapMessages<S, F>
+    where S: SysExSink, F: FnMut(&[u8]) -> Option<Vec<u8>>
+{
+    fn on_event(&mut self, event: SysExEvent) -> bool {
+        match event {
+            SysExEvent::Message { pos, len, msg, .. } => match (self.f)(&msg) {
+                Some(msg) => self.sink.on_event(SysExEvent::Message {
+                    pos, len, msg: Cow::Owned(msg), raw: None
+                }),
+                None => true,
+            },
+            event => self.sink.on_event(event),
+        }
+    }
+
+    fn on_progress(&mut self, read: usize, total: Option<usize>) -> bool {
+        self.sink.on_progress(read, total)
+    }
+}
+
+/// A sink that passes events to two sinks.  See `SysExSinkExt::tee`.
+#[derive(Clone, Debug)]
+pub struct Tee<A, B> {
+    a: A,
+    b: B,
+}
+
+impl<A: SysExSink, B: SysExSink> SysExSink for Tee<A, B> {
+    fn on_event(&mut self, event: SysExEvent) -> bool {
+        let go = self.a.on_event(event.clone());
+        go && self.b.on_event(event)
+    }
+
+    fn on_progress(&mut self, read: usize, total: Option<usize>) -> bool {
+        let go = self.a.on_progress(read, total);
+        go && self.b.on_progress(read, total)
+    }
+}
+
+/// Counts of events that passed a `Counted` sink.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct SinkStats {
+    /// Count of messages.
+    pub messages: usize,
+
+    /// Count of message bytes, including start/end bytes.
+    pub message_bytes: usize,
+
+    /// Count of error conditions, including incomplete messages.
+    pub errors: usize,
+}
+
+/// A sink that counts events.  See `SysExSinkExt::counted`.
+#[derive(Clone, Debug)]
+pub struct Counted<S> {
+    sink:  S,
+    stats: SinkStats,
+}
+
+impl<S> Counted<S> {
+    /// Gets the counts of events so far.
+    pub fn stats(&self) -> SinkStats {
+        self.stats
+    }
+
+    /// Unwraps the downstream sink.
+    pub fn into_inner(self) -> S {
+        self.sink
+    }
+}
+
+impl<S: SysExSink> SysExSink for Counted<S> {
+    fn on_event(&mut self, event: SysExEvent) -> bool {
+        match event {
+            SysExEvent::Message    { len, .. } => {
+                self.stats.messages      += 1;
+                self.stats.message_bytes += len;
+            },
+            SysExEvent::Error      { .. }      => self.stats.errors += 1,
+            SysExEvent::Incomplete { .. }      => self.stats.errors += 1,
+            SysExEvent::RealTime   { .. }      => { },
+        }
+        self.sink.on_event(event)
+    }
+
+    fn on_progress(&mut self, read: usize, total: Option<usize>) -> bool {
+        self.sink.on_progress(read, total)
+    }
+}
+
+/// A sink that writes each message, framed with start/end bytes, to an output
+/// stream, such as a file or a MIDI port.  Other events are ignored.  Reading
+/// stops at the first write error, which `finish` returns.
+#[derive(Debug)]
+pub struct WriteSink<W> {
+    output: W,
+    error:  Option<io::Error>,
+}
+
+impl<W: Write> WriteSink<W> {
+    /// Creates a `WriteSink` writing to the given `output`.
+    pub fn new(output: W) -> Self {
+        Self { output, error: None }
+    }
+
+    /// Flushes the output and returns it, or returns the first write error.
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(e) = self.error.take() {
+            return Err(e)
+        }
+        self.output.flush()?;
+        Ok(self.output)
+    }
+}
+
+impl<W: Write> SysExSink for WriteSink<W> {
+    fn on_event(&mut self, event: SysExEvent) -> bool {
+        if let SysExEvent::Message { msg, .. } = event {
+            let result = self.output.write_all(&[SYSEX_START])
+                .and_then(|_| self.output.write_all(&msg))
+                .and_then(|_| self.output.write_all(&[SYSEX_END]));
+
+            if let Err(e) = result {
+                self.error = Some(e);
+                return false
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sysex::{read_sysex_with, ReadOptions, ReadPolicy};
+
+    fn run<S: SysExSink>(bytes: &[u8], sink: &mut S) -> bool {
+        let mut options = ReadOptions::new(None);
+        options.policy = ReadPolicy::Lenient;
+        read_sysex_with(&mut &bytes[..], &options, sink).unwrap()
+    }
+
+    #[test]
+    fn filter_map_write() {
+        let input = b"\xF0abc\xF7\xF0xyz\xF7\xF0aq\xF7";
+
+        let mut sink = WriteSink::new(vec![])
+            .map_messages(|m| Some(m.to_ascii_uppercase()))
+            .filter(|e| match *e {
+                SysExEvent::Message { ref msg, .. } => msg.starts_with(b"a"),
+                _                                   => true,
+            });
+
+        assert!(run(input, &mut sink));
+        assert_eq!(sink.sink.sink.finish().unwrap(), b"\xF0ABC\xF7\xF0AQ\xF7");
+    }
+
+    #[test]
+    fn map_drop() {
+        let mut sink = WriteSink::new(vec![]).map_messages(|m| match m.len() {
+            1 => None,
+            _ => Some(m.to_vec()),
+        });
+
+        run(b"\xF0a\xF7\xF0bc\xF7", &mut sink);
+
+        assert_eq!(sink.sink.finish().unwrap(), b"\xF0bc\xF7");
+    }
+
+    #[test]
+    fn tee_counted() {
+        let mut seen = vec![];
+        {
+            let mut sink = (|e: SysExEvent| { seen.push(e.into_owned()); true })
+                .tee(WriteSink::new(vec![]))
+                .counted();
+
+            run(b"\xF0a\xF7zz\xF0bc\xF7\xF0", &mut sink);
+
+            assert_eq!(sink.stats(), SinkStats { messages: 2, message_bytes: 7, errors: 2 });
+            assert_eq!(sink.into_inner().b.finish().unwrap(), b"\xF0a\xF7\xF0bc\xF7");
+        }
+        assert_eq!(seen.len(), 4);
+    }
+
+    #[test]
+    fn stop() {
+        let mut count = 0;
+        {
+            let mut sink = (|_: SysExEvent| { count += 1; false })
+                .filter(|e| match *e { SysExEvent::Message { .. } => true, _ => false });
+
+            assert!(!run(b"zz\xF0a\xF7\xF0b\xF7", &mut sink));
+        }
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn write_error() {
+        #[derive(Debug)]
+        struct Broken;
+
+        impl Write for Broken {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::BrokenPipe, "broken"))
+            }
+            fn flush(&mut self) -> io::Result<()> { Ok(()) }
+        }
+
+        let mut sink = WriteSink::new(Broken);
+
+        assert!(!run(b"\xF0a\xF7", &mut sink));
+        assert_eq!(sink.finish().unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+    }
+}
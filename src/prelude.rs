@@ -0,0 +1,84 @@
+// This file is part of a6-tools.
+// Copyright (C) 2017 Jeffrey Sharp
+//
+// a6-tools is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License,
+// or (at your option) any later version.
+//
+// a6-tools is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See
+// the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with a6-tools.  If not, see <http://www.gnu.org/licenses/>.
+
+// The supported public API, for import with `use a6::prelude::*`.
+//
+// Items here follow semantic versioning.  Items reachable only through other
+// paths may change in any release.
+
+// Messages
+pub use a6::{A6Message, Opcode, build_message, parse_sysex};
+pub use a6::{recognize_sysex, recognize_sysex_detailed};
+pub use a6::{CustomOpcode, OpcodeRegistry, Recognized};
+pub use a6::{request_identity, IdentityReply, ALL_CALL};
+pub use a6::{ProgramDump, PROGRAM_DATA_LEN, PROGRAM_7BIT_LEN};
+pub use a6::{EditBufferDump, EDIT_BUFFER_COUNT, PROGRAM_MODE_BUFFER};
+pub use a6::{request_program, request_program_bank, request_mix, request_mix_bank, request_all};
+
+// Update images
+pub use a6::{Block, BlockHeader, BlockKind};
+#[cfg(feature = "std")]
+pub use a6::{Image, BlockDecoder, BlockEncoder, DecodeStats, VerificationReport};
+#[cfg(feature = "std")]
+pub use a6::{extract_image, extract_images, read_update_version};
+#[cfg(feature = "std")]
+pub use a6::{decode_update_file, UpdateImage};
+
+// Errors
+pub use a6::{BlockDecodeError, BlockEncodeError, NameError, MessageError};
+#[cfg(feature = "std")]
+pub use a6::LintError;
+pub use codec::{Invalid7BitError, BufferTooSmallError};
+#[cfg(feature = "std")]
+pub use sysex::{SysExReadError, SysExError};
+#[cfg(feature = "std")]
+pub use io::{ErrorExt, Cancelled, MemoryLimitExceeded};
+
+// 7-bit codec
+pub use codec::{encode_7bit, decode_7bit, try_decode_7bit, Packing};
+pub use codec::{encode_7bit_into, decode_7bit_into, encoded_len_7bit, decoded_len_7bit};
+pub use codec::{encode_7bit_iter, Encode7Bit, Decode7Bit};
+#[cfg(feature = "std")]
+pub use codec::{encode_7bit_to, decode_7bit_to};
+
+// SysEx streams
+#[cfg(feature = "std")]
+pub use sysex::{read_sysex, read_sysex_with, scan_sysex};
+#[cfg(feature = "std")]
+pub use sysex::{read_sysex_events, read_sysex_tracked, read_sysex_joined};
+#[cfg(feature = "std")]
+pub use sysex::{ReadOptions, ReadPolicy, SysExEvent, SysExParser, SysExSink, ScanSummary};
+#[cfg(feature = "std")]
+pub use sysex::{ScanSysEx, SYSEX_START, SYSEX_END};
+#[cfg(feature = "std")]
+pub use repair::{repair_sysex, Repair};
+#[cfg(feature = "std")]
+pub use midi::{MidiEvent, MidiParser};
+
+// Pipelines
+#[cfg(feature = "std")]
+pub use pipeline::{SysExSinkExt, WriteSink};
+#[cfg(feature = "std")]
+pub use pipeline::{Filter, MapMessages, Tee, Counted, SinkStats};
+
+// Reading and writing
+#[cfg(feature = "std")]
+pub use io::{CancelToken, TrackedRead, CountingReader, MultiReader, HexReader, AtomicFile};
+#[cfg(feature = "mmap")]
+pub use io::MappedFile;
+
+// Handlers
+pub use util::Handler;
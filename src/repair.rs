@@ -0,0 +1,236 @@
+// This file is part of a6-tools.
+// Copyright (C) 2017 Jeffrey Sharp
+//
+// a6-tools is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License,
+// or (at your option) any later version.
+//
+// a6-tools is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See
+// the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with a6-tools.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::fmt;
+
+use sysex::{scan_sysex, SysExEvent, SYSEX_START, SYSEX_END, SYSRT_MIN};
+use sysex::SysExReadError::*;
+
+use self::Repair::*;
+
+/// Repairs performed by `repair_sysex`.  Positions are relative to the start
+/// of the concatenated inputs.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Repair {
+    /// A message lacked its end byte and was terminated.
+    MissingEnd        { pos: usize                },
+
+    /// A redundant start byte was removed.
+    DoubledStart      { pos: usize                },
+
+    /// A system real-time byte within a message was removed.
+    StrayRealTime     { pos: usize, byte: u8      },
+
+    /// A message begun in one input was continued in input `file` and was
+    /// joined into one message.
+    JoinedAcrossFiles { pos: usize, file: usize   },
+
+    /// Bytes outside of any message were removed.
+    DroppedJunk       { pos: usize, len: usize    },
+}
+
+impl Repair {
+    /// Gets the stable diagnostic code of the repair, such as `A6-RPR-001`.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            MissingEnd        { .. } => "A6-RPR-001",
+            DoubledStart      { .. } => "A6-RPR-002",
+            StrayRealTime     { .. } => "A6-RPR-003",
+            JoinedAcrossFiles { .. } => "A6-RPR-004",
+            DroppedJunk       { .. } => "A6-RPR-005",
+        }
+    }
+}
+
+impl fmt::Display for Repair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: ", self.code())?;
+        match *self {
+            MissingEnd { pos } => write!(
+                f, "At offset {}: terminated message lacking an end byte.",
+                pos
+            ),
+            DoubledStart { pos } => write!(
+                f, "At offset {}: removed redundant start byte.",
+                pos
+            ),
+            StrayRealTime { pos, byte } => write!(
+                f, "At offset {}: removed real-time byte {:02X} within message.",
+                pos, byte
+            ),
+            JoinedAcrossFiles { pos, file } => write!(
+                f, "At offset {}: joined message continued in input {}.",
+                pos, file
+            ),
+            DroppedJunk { pos, len } => write!(
+                f, "At offset {}: removed {} byte(s) outside of any message.",
+                pos, len
+            ),
+        }
+    }
+}
+
+/// Repairs common corruptions in captured SysEx data.
+///
+/// The given `inputs` are treated as one logical stream, so that a message
+/// split across inputs is joined.  Messages lacking an end byte are
+/// terminated, redundant start bytes and real-time bytes within messages are
+/// removed, and bytes outside of any message are removed.  The cleaned stream
+/// is appended to `output`.
+///
+/// Returns the list of repairs performed, in order of position.
+pub fn repair_sysex(inputs: &[&[u8]], output: &mut Vec<u8>) -> Vec<Repair> {
+    // Concatenate inputs, noting the start position of each
+    let mut bytes  = vec![];
+    let mut starts = vec![];
+    for input in inputs {
+        starts.push(bytes.len());
+        bytes.extend_from_slice(input);
+    }
+
+    let mut repairs = vec![];
+
+    // Notes joins for a message occupying the given range
+    let joins = |pos: usize, end: usize, repairs: &mut Vec<Repair>| {
+        for (file, &start) in starts.iter().enumerate() {
+            if pos < start && start < end {
+                repairs.push(JoinedAcrossFiles { pos, file });
+            }
+        }
+    };
+
+    for event in scan_sysex(&bytes) {
+        match event {
+            SysExEvent::Message { pos, len, msg, .. } => {
+                joins(pos, pos + len, &mut repairs);
+                write_message(output, &msg);
+            },
+            SysExEvent::RealTime { pos, byte } => {
+                repairs.push(StrayRealTime { pos, byte });
+            },
+            SysExEvent::Error { pos, len, err: NotSysEx } => {
+                repairs.push(DroppedJunk { pos, len });
+            },
+            SysExEvent::Error { pos, len, .. } | SysExEvent::Incomplete { pos, len, .. } => {
+                // Message interrupted by a status byte or EOF
+                let data = bytes[pos + 1..pos + len]
+                    .iter().cloned()
+                    .filter(|&b| b < SYSRT_MIN)
+                    .collect::<Vec<_>>();
+
+                if data.len() == 0 && bytes.get(pos + len) == Some(&SYSEX_START) {
+                    repairs.push(DoubledStart { pos });
+                } else if data.len() == 0 && pos + len == bytes.len() {
+                    repairs.push(DroppedJunk { pos, len });
+                } else {
+                    repairs.push(MissingEnd { pos });
+                    joins(pos, pos + len, &mut repairs);
+                    write_message(output, &data);
+                }
+            },
+        }
+    }
+
+    repairs.sort_by_key(|r| match *r {
+        MissingEnd        { pos, .. } => pos,
+        DoubledStart      { pos, .. } => pos,
+        StrayRealTime     { pos, .. } => pos,
+        JoinedAcrossFiles { pos, .. } => pos,
+        DroppedJunk       { pos, .. } => pos,
+    });
+    repairs
+}
+
+// Writes a framed SysEx message with the given `data` to `output`.
+fn write_message(output: &mut Vec<u8>, data: &[u8]) {
+    output.push(SYSEX_START);
+    output.extend_from_slice(data);
+    output.push(SYSEX_END);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(inputs: &[&[u8]]) -> (Vec<u8>, Vec<Repair>) {
+        let mut output = vec![];
+        let repairs = repair_sysex(inputs, &mut output);
+        (output, repairs)
+    }
+
+    #[test]
+    fn clean() {
+        let (output, repairs) = run(&[b"\xF0ab\xF7\xF0cd\xF7"]);
+
+        assert_eq!(output, b"\xF0ab\xF7\xF0cd\xF7");
+        assert_eq!(repairs, vec![]);
+    }
+
+    #[test]
+    fn missing_end() {
+        let (output, repairs) = run(&[b"\xF0ab\xF0cd\xF7\xF0ef\x90\xF0gh"]);
+
+        assert_eq!(output, b"\xF0ab\xF7\xF0cd\xF7\xF0ef\xF7\xF0gh\xF7");
+        assert_eq!(repairs, vec![
+            MissingEnd  { pos:  0         },
+            MissingEnd  { pos:  7         },
+            DroppedJunk { pos: 10, len: 1 },
+            MissingEnd  { pos: 11         },
+        ]);
+    }
+
+    #[test]
+    fn doubled_start() {
+        let (output, repairs) = run(&[b"\xF0\xF0ab\xF7"]);
+
+        assert_eq!(output, b"\xF0ab\xF7");
+        assert_eq!(repairs, vec![DoubledStart { pos: 0 }]);
+    }
+
+    #[test]
+    fn stray_real_time() {
+        let (output, repairs) = run(&[b"\xF0a\xF8b\xFE\xF7"]);
+
+        assert_eq!(output, b"\xF0ab\xF7");
+        assert_eq!(repairs, vec![
+            StrayRealTime { pos: 2, byte: 0xF8 },
+            StrayRealTime { pos: 4, byte: 0xFE },
+        ]);
+    }
+
+    #[test]
+    fn joined_across_files() {
+        let (output, repairs) = run(&[b"\xF0ab", b"cd\xF7", b"\xF0ef"]);
+
+        assert_eq!(output, b"\xF0abcd\xF7\xF0ef\xF7");
+        assert_eq!(repairs, vec![
+            JoinedAcrossFiles { pos: 0, file: 1 },
+            MissingEnd        { pos: 6          },
+        ]);
+    }
+
+    #[test]
+    fn junk() {
+        let (output, repairs) = run(&[b"xy\xF0ab\xF7z\xF0"]);
+
+        assert_eq!(output, b"\xF0ab\xF7");
+        assert_eq!(repairs, vec![
+            DroppedJunk { pos: 0, len: 2 },
+            DroppedJunk { pos: 6, len: 1 },
+            DroppedJunk { pos: 7, len: 1 },
+        ]);
+    }
+}
@@ -14,403 +14,2350 @@
 // You should have received a copy of the GNU General Public License
 // along with a6-tools.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::borrow::Cow;
 use std::cmp;
+use std::collections::HashSet;
+use std::error;
+use std::fmt;
 use std::io;
 use std::io::prelude::*;
+use std::ops::Range;
+use std::sync::mpsc::{Sender, SyncSender};
+use std::thread;
+use std::time::{Duration, Instant};
 use io::*;
+use util::FindBits;
+
+pub use codec::*;
 use self::SysExReadError::*;
 
 // MIDI byte ranges
-const DATA_MIN:    u8 = 0x00; // \_ Data bytes
-const DATA_MAX:    u8 = 0x7F; // / 
-const STATUS_MIN:  u8 = 0x80; // \_ Status bytes
-const STATUS_MAX:  u8 = 0xEF; // /
-const SYSEX_START: u8 = 0xF0; // \_ System exlusive messages
-const SYSEX_END:   u8 = 0xF7; // /
-const SYSCOM_MIN:  u8 = 0xF1; // \_ System common messages
-const SYSCOM_MAX:  u8 = 0xF6; // /
-const SYSRT_MIN:   u8 = 0xF8; // \_ System real-time messages
-const SYSRT_MAX:   u8 = 0xFF; // /
+pub const DATA_MIN:    u8 = 0x00; // \_ Data bytes
+pub const DATA_MAX:    u8 = 0x7F; // /
+pub const STATUS_MIN:  u8 = 0x80; // \_ Status bytes
+pub const STATUS_MAX:  u8 = 0xEF; // /
+pub const SYSEX_START: u8 = 0xF0; // \_ System exlusive messages
+pub const SYSEX_END:   u8 = 0xF7; // /
+pub const SYSCOM_MIN:  u8 = 0xF1; // \_ System common messages
+pub const SYSCOM_MAX:  u8 = 0xF6; // /
+pub const SYSRT_MIN:   u8 = 0xF8; // \_ System real-time messages
+pub const SYSRT_MAX:   u8 = 0xFF; // /
 
 // Masks
 const ALL_BITS:    u8 = 0xFF;
 const STATUS_BIT:  u8 = 0x80;
 
 /// Consumes the given `input` stream and detects MIDI System Exclusive messages
-/// of length `cap` or less.  Invokes the handler `on_msg` for each detected
-/// message and the handler `on_err` for each error condition.
-pub fn read_sysex<R, M, E>(
+/// of length `cap` or less, or of any length if `cap` is `None`.  Invokes the
+/// handler `on_msg` for each detected message and the handler `on_err` for
+/// each error condition.
+///
+/// The message buffer grows on demand, so a generous `cap` costs memory only
+/// if the input actually contains messages that long.
+///
+/// Returns a summary of the messages, error conditions, and bytes read.  The
+/// summary's `complete` field is `true` if the entire stream was consumed, or
+/// `false` if a handler returned `false` to stop reading early.
+pub fn read_sysex<R, N, M, E>(
     input:  &mut R,
-    cap:    usize,
+    cap:    N,
     on_msg: M,
     on_err: E,
-)   ->      io::Result<bool>
+)   ->      io::Result<ScanSummary>
 where
     R: BufRead,
+    N: Into<Option<usize>>,
     M: Fn(usize, &[u8])                 -> bool,
     E: Fn(usize, usize, SysExReadError) -> bool,
 {
-    let mut start = 0;  // Start position of message or skipped chunk
-    let mut next  = 0;  // Position of next unread byte
-    let mut len   = 0;  // Length of message data (no start/end bytes) or skipped chunk (all bytes)
+    let cap           = cap.into();
+    let mut summary   = ScanSummary::default();
+    let mut msg_bytes = 0;
+    let mut parser    = SysExParser::new(cap);
+    let mut on_event  = adapt(on_msg, on_err);
 
-    // Message data, without SysEx start/end bytes
-    let mut buf = vec![0u8; cap].into_boxed_slice();
+    summary.complete = {
+        let mut sink = |event: SysExEvent| {
+            match event {
+                SysExEvent::Message { len, .. } => { summary.messages += 1; msg_bytes += len },
+                SysExEvent::Error   { ..      } => { summary.errors   += 1; },
+                SysExEvent::RealTime { ..     } => { },
+                SysExEvent::Incomplete { .. } => { summary.errors += 1; },
+            }
+            on_event(event)
+        };
+        drive(input, &mut parser, &mut sink, &ReadOptions::new(cap))?
+    };
 
-    // Helper for invoking the on_msg/on_err handlers
-    macro_rules! fire {
-        ($fn:ident, $($arg:expr),+) => {
-            if !$fn($($arg),+) { return Ok(false) }
-        }
+    summary.bytes         = parser.offset();
+    summary.skipped_bytes = summary.bytes - msg_bytes;
+    Ok(summary)
+}
+
+/// Totals for a stream read by `read_sysex`.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct ScanSummary {
+    /// Count of messages detected.
+    pub messages: usize,
+
+    /// Count of error conditions detected.
+    pub errors: usize,
+
+    /// Count of bytes consumed from the input.
+    pub bytes: usize,
+
+    /// Count of bytes consumed that were not part of a detected message.
+    pub skipped_bytes: usize,
+
+    /// Whether the entire stream was consumed.
+    pub complete: bool,
+}
+
+/// Consumes the given `input` stream and detects MIDI System Exclusive messages
+/// as configured by the given `options`.  Reports each detected message, error
+/// condition, and system real-time byte interleaved within a message to the
+/// given `sink`.
+///
+/// Returns `Ok(true)` if the entire stream was consumed, or `Ok(false)` if
+/// reading stopped early, either because `sink` returned `false` or because
+/// the error policy in `options` called for it.
+pub fn read_sysex_with<R, S>(
+    input:   &mut R,
+    options: &ReadOptions,
+    sink:    &mut S,
+)   ->       io::Result<bool>
+where
+    R: BufRead,
+    S: SysExSink,
+{
+    let mut parser = SysExParser::new(options.cap);
+    if let Some(ref id) = options.id_filter {
+        parser.set_filter(id);
     }
+    parser.set_keep_raw(options.raw);
+    parser.set_keep_incomplete(options.keep_incomplete);
 
-    loop {
-        // State A: Not In SysEx Message
-        {
-            let (read, found) = input.skip_until_bits(SYSEX_START, ALL_BITS)?;
-            next += read;
-
-            let end = match found {
-                Some(_) => next - 1,
-                None    => next,
+    let mut sink = PolicySink { sink, policy: options.policy, errors: 0 };
+    drive(input, &mut parser, &mut sink, options)
+}
+
+/// Consumes the given `input` stream and detects MIDI System Exclusive messages
+/// of length `cap` or less, as `read_sysex` does.  Invokes the handler
+/// `on_event` for each detected message, error condition, and system
+/// real-time byte interleaved within a message.
+pub fn read_sysex_events<R, N, F>(
+    input:        &mut R,
+    cap:          N,
+    mut on_event: F,
+)   ->            io::Result<bool>
+where
+    R: BufRead,
+    N: Into<Option<usize>>,
+    F: FnMut(SysExEvent) -> bool,
+{
+    let cap = cap.into();
+    drive(input, &mut SysExParser::new(cap), &mut on_event, &ReadOptions::new(cap))
+}
+
+/// Consumes the given position-tracking `input` stream and detects MIDI System
+/// Exclusive messages of length `cap` or less, as `read_sysex` does.  Positions
+/// reported to the handlers are relative to the start of the stream, rather
+/// than to the position of `input` when called.
+pub fn read_sysex_tracked<R, N, M, E>(
+    input:  &mut R,
+    cap:    N,
+    on_msg: M,
+    on_err: E,
+)   ->      io::Result<bool>
+where
+    R: BufRead + TrackedRead,
+    N: Into<Option<usize>>,
+    M: Fn(usize, &[u8])                 -> bool,
+    E: Fn(usize, usize, SysExReadError) -> bool,
+{
+    let cap        = cap.into();
+    let mut parser = SysExParser::with_offset(cap, input.offset());
+    drive(input, &mut parser, &mut adapt(on_msg, on_err), &ReadOptions::new(cap))
+}
+
+/// Consumes the given `input` stream and detects MIDI System Exclusive messages
+/// as `read_sysex` does, reassembling messages that a capture tool split into
+/// multiple `F0 … F7` fragments.
+///
+/// A message for which the predicate `is_complete` returns `false` is held,
+/// and the data of each subsequent message is appended to it, until the
+/// predicate returns `true` for the joined data or until an error condition
+/// or EOF occurs.  The joined message is then passed to `on_msg` with the
+/// position of its first fragment.  The length limit `cap` applies to each
+/// fragment separately.
+pub fn read_sysex_joined<R, N, C, M, E>(
+    input:       &mut R,
+    cap:         N,
+    is_complete: C,
+    on_msg:      M,
+    on_err:      E,
+)   ->           io::Result<bool>
+where
+    R: BufRead,
+    N: Into<Option<usize>>,
+    C: Fn(&[u8])                        -> bool,
+    M: Fn(usize, &[u8])                 -> bool,
+    E: Fn(usize, usize, SysExReadError) -> bool,
+{
+    use std::cell::RefCell;
+
+    // Position and data of partial message, if any
+    let partial = RefCell::new(None::<(usize, Vec<u8>)>);
+
+    // Delivers partial message, if any
+    let flush = || match partial.borrow_mut().take() {
+        Some((pos, data)) => on_msg(pos, &data),
+        None              => true,
+    };
+
+    let more = read_sysex(
+        input, cap,
+        |pos, msg| {
+            let (pos, data) = match partial.borrow_mut().take() {
+                Some((pos, mut data)) => {
+                    data.extend_from_slice(msg);
+                    (pos, data)
+                },
+                None if is_complete(msg) => {
+                    return on_msg(pos, msg)
+                },
+                None => {
+                    (pos, msg.to_vec())
+                },
             };
 
-            let len = end - start;
-            if len != 0 {
-                fire!(on_err, start, len, NotSysEx);
+            if is_complete(&data) {
+                on_msg(pos, &data)
+            } else {
+                *partial.borrow_mut() = Some((pos, data));
+                true
             }
+        },
+        |pos, len, err| {
+            flush() && on_err(pos, len, err)
+        },
+    )?;
 
-            match found {
-                Some(_) => start = end,
-                None    => return Ok(true),
-            }
+    Ok(more.complete && flush())
+}
+
+// Feeds the given `input` stream to the given `parser` until EOF or until the
+// sink returns `false` (stop).
+fn drive<R, S>(
+    input:      &mut R,
+    parser:     &mut SysExParser,
+    sink:       &mut S,
+    options:    &ReadOptions,
+)   ->          io::Result<bool>
+where
+    R: BufRead,
+    S: SysExSink,
+{
+    let interval = options.progress_interval.map(|n| cmp::max(n, 1));
+    let mut read = 0;   // Count of bytes consumed
+    let mut next = 0;   // Count of bytes at which to report progress next
+    let mut leading = options.ignore_edge_junk;  // Whether to ignore leading junk
+    let mut seen    = if options.skip_duplicates {   // Payloads of messages seen
+        Some(HashSet::<Box<[u8]>>::new())
+    } else {
+        None
+    };
+    let mut seen_bytes = 0; // Total length of payloads seen
+
+    let timed     = options.timeout.is_some() || options.message_timeout.is_some();
+    let started   = Instant::now();
+    let mut since = None;   // Start position and first-seen time of message
+
+    loop {
+        if options.cancel.as_ref().map_or(false, |c| c.is_cancelled()) {
+            return Err(Cancelled.into())
         }
 
-        // State B: In SysEx Message
-        len = 0;
-        loop {
-            let idx           = cmp::min(len, cap);
-            let (read, found) = input.read_until_bits(STATUS_BIT, STATUS_BIT, &mut buf[idx..])?;
-            next += read;
-            
-            match found {
-                Some(SYSRT_MIN...SYSRT_MAX) => {
-                    len += read - 1;
-                    // remain in state B
-                },
-                Some(SYSEX_START) => {
-                    let end = next - 1;
-                    fire!(on_err, start, end - start, UnexpectedByte);
-                    start = end;
-                    len   = 0;
-                    // restart state B
-                },
-                Some(SYSEX_END) => {
-                    len += read - 1;
-                    if len > cap {
-                        fire!(on_err, start, next - start, Overflow)
+        // Check deadlines
+        if timed {
+            let now = Instant::now();
+
+            if let Some(limit) = options.message_timeout {
+                since = match since {
+                    _ if !parser.in_message()                => None,
+                    Some((pos, t)) if pos == parser.start()  => Some((pos, t)),
+                    _                                        => Some((parser.start(), now)),
+                };
+
+                if let Some((_, t)) = since {
+                    if now - t >= limit {
+                        since = None;
+                        if !parser.abandon_events(Timeout, |e| sink.on_event(e)) {
+                            return Ok(false)
+                        }
+                    }
+                }
+            }
+
+            if let Some(limit) = options.timeout {
+                if now - started >= limit {
+                    // Report message in progress, or else the stream position
+                    if parser.in_message() {
+                        parser.abandon_events(Timeout, |e| sink.on_event(e));
                     } else {
-                        fire!(on_msg, start, &buf[..len])
+                        let pos = parser.offset();
+                        sink.on_event(SysExEvent::Error { pos, len: 0, err: Timeout });
                     }
-                    start = next;
-                    break // to state A
-                },
-                Some(_) => {
-                    let end = next - 1;
-                    fire!(on_err, start, end - start, UnexpectedByte);
-                    start = end;
-                    break // to State A
+                    return Ok(false)
+                }
+            }
+        }
+
+        let (more, count) = {
+            // Get next chunk from the stream
+            let buf = match input.fill_buf() {
+                Ok(b) if b.len() == 0 /*EOF*/  => break,
+                Ok(b)                          => b,
+                Err(ref e) if e.is_transient() => continue,
+                Err(ref e) if timed && is_idle(e) => {
+                    // Input has no data yet; wait, then check deadlines
+                    thread::sleep(IDLE_WAIT);
+                    continue
                 },
-                None => {
-                    fire!(on_err, start, next - start, UnexpectedEof);
-                    return Ok(true)
+                Err(e)                         => return Err(e),
+            };
+
+            // Parse chunk, noting how much of it the parser consumed
+            let offset = parser.offset();
+            let more   = parser.feed_events(buf, |e| {
+                match e {
+                    SysExEvent::Error { err: NotSysEx, .. } if leading => {
+                        // Ignore leading junk
+                        leading = false;
+                        true
+                    },
+                    SysExEvent::Message { pos, len, ref msg, .. } if seen.is_some() => {
+                        leading = false;
+                        let seen = seen.as_mut().unwrap();
+                        if seen.contains(&msg[..]) {
+                            // Skip repeated message
+                            sink.on_event(SysExEvent::Error { pos, len, err: Duplicate })
+                        } else {
+                            seen.insert(msg.to_vec().into_boxed_slice());
+                            seen_bytes += msg.len();
+                            sink.on_event(e)
+                        }
+                    },
+                    _ => {
+                        leading = false;
+                        sink.on_event(e)
+                    },
                 }
+            });
+            (more, parser.offset() - offset)
+        };
+
+        input.consume(count);
+        read += count;
+
+        if !more { return Ok(false) }
+
+        // Check memory held across chunks
+        if let Some(limit) = options.memory_limit {
+            let used = parser.memory_used() + seen_bytes;
+            if used > limit {
+                return Err(MemoryLimitExceeded { limit, used }.into())
             }
         }
-    }
 
-    Ok(true)
-}
+        // Report progress
+        if let Some(n) = interval {
+            if read >= next {
+                next = read - read % n + n;
+                if !sink.on_progress(read, options.total_len) { return Ok(false) }
+            }
+        }
+    }
 
-/// Possible error conditions encountered by `read_sysex`.
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
-pub enum SysExReadError {
-    /// The bytes did not contain a System Exclusive message.
-    NotSysEx,
+    // EOF
+    let trailing = options.ignore_edge_junk && !parser.in_message();
+    if !trailing && !parser.finish_events(|e| sink.on_event(e)) {
+        return Ok(false)
+    }
 
-    /// A System Exclusive message exceeded the maximum allowed length.
-    Overflow,
+    // Report final progress
+    Ok(interval.is_none() || sink.on_progress(read, options.total_len))
+}
 
-    /// A System Exclusive message was interrupted by an unexpected byte.
-    UnexpectedByte,
+// Time to wait before polling again an input that reported no data.
+const IDLE_WAIT: Duration = Duration::from_millis(1);
 
-    /// A System Exclusive message was interrupted by end-of-file.
-    UnexpectedEof,
+// Returns `true` if the given error indicates that an input has no data yet,
+// as reported by nonblocking inputs or inputs with read timeouts.
+fn is_idle(e: &io::Error) -> bool {
+    match e.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => true,
+        _                                                   => false,
+    }
 }
 
-/// Encodes a sequence of bytes into a sequence of 7-bit values.
-pub fn encode_7bit(src: &[u8], dst: &mut Vec<u8>)
+// Adapts a pair of message/error handlers to an event handler.
+fn adapt<M, E>(on_msg: M, on_err: E) -> impl FnMut(SysExEvent) -> bool
+where
+    M: Fn(usize, &[u8])                 -> bool,
+    E: Fn(usize, usize, SysExReadError) -> bool,
 {
-    // Iteration
-    // |  Leftover bits
-    // |  |         7-bit output
-    // |  |         |
-    // 0: ........ 00000000 -> yield 7 bits
-    // 1: .......1 11111110 -> yield 7 bits
-    // 2: ......22 22222211 -> yield 7 bits
-    // 3: .....333 33333222 -> yield 7 bits
-    // 4: ....4444 44443333 -> yield 7 bits
-    // 5: ...55555 55544444 -> yield 7 bits
-    // 6: ..666666 66555555 -> yield 7 bits, then
-    //    ........ .6666666 -> yield 7 bits again
-    // 7: (repeats)
+    move |event| match event {
+        SysExEvent::Message  { pos, msg, ..  } => on_msg(pos, &msg),
+        SysExEvent::Error    { pos, len, err } => on_err(pos, len, err),
+        SysExEvent::RealTime { ..            } => true,
+        SysExEvent::Incomplete { pos, len, .. } => on_err(pos, len, UnexpectedEof),
+    }
+}
 
-    let mut data = 0u16;    // a shift register where bytes become bits
-    let mut bits = 0;       // how many leftover bits from previous iteration
+/// Trait for types that consume `SysExEvent`s.
+pub trait SysExSink {
+    /// Consumes the given `event`.  Returns `true` to continue reading or
+    /// `false` to stop.
+    fn on_event(&mut self, event: SysExEvent) -> bool;
 
-    for v in src {
-        // Add 8 input bits.
-        data |= (*v as u16) << bits;
+    /// Notes progress of reading: `read` bytes have been consumed of `total`
+    /// bytes, if known.  Returns `true` to continue reading or `false` to
+    /// stop.  Invoked only if enabled by `ReadOptions::progress_interval`.
+    #[inline]
+    fn on_progress(&mut self, _read: usize, _total: Option<usize>) -> bool {
+        true
+    }
+}
 
-        // Yield 7 bits.  Accrue 1 leftover bit for next iteration.
-        dst.push((data & 0x7F) as u8);
-        data >>= 7;
-        bits  += 1;
+impl<F> SysExSink for F where F: FnMut(SysExEvent) -> bool {
+    #[inline]
+    fn on_event(&mut self, event: SysExEvent) -> bool {
+        self(event)
+    }
+}
 
-        // Every 7 iterations, 7 leftover bits have accrued.
-        // Consume them to yield another 7-bit output.
-        if bits == 7 {
-            dst.push((data & 0x7F) as u8);
-            data = 0;
-            bits = 0;
-        }
+/// A sender is a sink that sends each event to its channel, so that events can
+/// be processed on another thread.  Reading stops if the receiver hangs up.
+impl SysExSink for Sender<SysExEvent<'static>> {
+    #[inline]
+    fn on_event(&mut self, event: SysExEvent) -> bool {
+        self.send(event.into_owned()).is_ok()
     }
+}
 
-    // Yield final leftover bits, if any.
-    if bits > 0 {
-        dst.push((data & 0x7F) as u8);
+/// A sender is a sink that sends each event to its channel, so that events can
+/// be processed on another thread.  Reading blocks while the channel is full
+/// and stops if the receiver hangs up.
+impl SysExSink for SyncSender<SysExEvent<'static>> {
+    #[inline]
+    fn on_event(&mut self, event: SysExEvent) -> bool {
+        self.send(event.into_owned()).is_ok()
     }
 }
 
-/// Decodes a sequence of 7-bit values into a sequence of bytes.
-pub fn decode_7bit(src: &[u8], dst: &mut Vec<u8>)
-{
-    // Iteration
-    // |  Leftover bits
-    // |  |        Byte output
-    // |  |        |
-    // 0: ........ .0000000 (not enough bits for a byte)
-    // 1: ..111111 10000000 -> yield byte
-    // 2: ...22222 22111111 -> yield byte
-    // 3: ....3333 33322222 -> yield byte
-    // 4: .....444 44443333 -> yield byte
-    // 5: ......55 55555444 -> yield byte
-    // 6: .......6 66666655 -> yield byte
-    // 7: ........ 77777776 -> yield byte
-    // 8: (repeats)
-
-    let mut data = 0u16;    // a shift register where bits become bytes
-    let mut bits = 0;       // how many leftover bits from previous iteration
-
-    for v in src {
-        // Isolate 7 input bits.
-        let v = (*v & 0x7F) as u16;
-
-        if bits == 0 {
-            // Initially, and after every 8 iterations, there are no leftover
-            // bits from the previous iteration.  With only 7 new bits, there
-            // aren't enough to make a byte.  Just let those bits become the
-            // leftovers for the next iteration.
-            data = v;
-            bits = 7;
-        } else {
-            // For other iterations, there are leftover bits from the previous
-            // iteration.  Consider those as least significant, and the 7 new
-            // bits as most significant, and yield a byte.  Any unused bits
-            // become leftovers for the next iteration to use.
-            data |= v << bits;
-            dst.push((data & 0xFF) as u8);
-            data >>= 8;
-            bits  -= 1;
+/// Policies for handling error conditions while reading SysEx messages.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ReadPolicy {
+    /// Stop reading after the first error condition.
+    Strict,
+
+    /// Continue reading after any error condition.
+    Lenient,
+
+    /// Stop reading after the given count of error conditions.
+    AbortAfter(usize),
+}
+
+/// Options for reading SysEx messages with `read_sysex_with`.
+#[derive(Clone, Debug)]
+pub struct ReadOptions {
+    /// Maximum length of messages to detect, or `None` for no limit.  Longer
+    /// messages are reported as `Overflow` errors.  The message buffer grows
+    /// on demand up to this length.
+    pub cap: Option<usize>,
+
+    /// Policy for handling error conditions.
+    pub policy: ReadPolicy,
+
+    /// Interval, in bytes consumed, at which to report progress to the sink,
+    /// or `None` to not report progress.  Progress is reported at most once
+    /// per chunk read from the input, and once more at EOF.
+    pub progress_interval: Option<usize>,
+
+    /// Total length of the input, if known, for progress reports.
+    pub total_len: Option<usize>,
+
+    /// Initial bytes that message data must have, such as a manufacturer ID,
+    /// for a message to be reported, or `None` to report all messages.
+    pub id_filter: Option<Vec<u8>>,
+
+    /// Whether to report the on-wire bytes of each message, including
+    /// start/end bytes and interleaved real-time bytes, in the `raw` field of
+    /// the `Message` event.
+    pub raw: bool,
+
+    /// Token by which another thread can stop reading, or `None` if reading
+    /// cannot be cancelled.  The token is checked before each chunk is read
+    /// from the input.  If cancelled, reading fails with an error for which
+    /// `ErrorExt::is_cancelled` returns `true`.
+    pub cancel: Option<CancelToken>,
+
+    /// Maximum time to read, or `None` for no limit.  When exceeded, reading
+    /// stops after reporting a `Timeout` error for the message in progress or,
+    /// if none, for the current position with length 0.
+    pub timeout: Option<Duration>,
+
+    /// Maximum time to wait for a message to end after it starts, or `None`
+    /// for no limit.  When exceeded, the message is reported as a `Timeout`
+    /// error and abandoned, and reading continues.
+    ///
+    /// Deadlines are checked between chunks, so they are effective only for
+    /// inputs that return periodically: either with data or with `WouldBlock`
+    /// or `TimedOut` errors, which are retried while either limit is set.
+    pub message_timeout: Option<Duration>,
+
+    /// Whether to ignore non-SysEx bytes before the first message and after
+    /// the last message, rather than reporting them as `NotSysEx` errors.
+    /// Non-SysEx bytes between messages are reported regardless.
+    pub ignore_edge_junk: bool,
+
+    /// Whether to report a message truncated by the end of input as an
+    /// `Incomplete` event carrying the partial message data, so that it can be
+    /// salvaged, rather than as an `UnexpectedEof` error.  For the error
+    /// policy, an `Incomplete` event counts as an error.
+    pub keep_incomplete: bool,
+
+    /// Whether to skip a message whose data is identical to that of an earlier
+    /// message, reporting it as a `Duplicate` error instead.  For the error
+    /// policy, a `Duplicate` error does not count as an error.
+    pub skip_duplicates: bool,
+
+    /// Maximum memory, in bytes, that reading may hold: the message buffer,
+    /// the `raw` bytes of the message in progress, and the messages kept to
+    /// detect duplicates.  Checked after each chunk read from the input.  If
+    /// exceeded, reading fails with an error for which
+    /// `ErrorExt::is_memory_limit` returns `true`.  `None` for no limit.
+    pub memory_limit: Option<usize>,
+}
+
+impl ReadOptions {
+    /// Creates `ReadOptions` to detect messages of length `cap` or less, or of
+    /// any length if `cap` is `None`, with the `Lenient` error policy and no
+    /// progress reports.
+    pub fn new<N: Into<Option<usize>>>(cap: N) -> Self {
+        Self {
+            cap:               cap.into(),
+            policy:            ReadPolicy::Lenient,
+            progress_interval: None,
+            total_len:         None,
+            id_filter:         None,
+            raw:               false,
+            cancel:            None,
+            timeout:           None,
+            message_timeout:   None,
+            ignore_edge_junk:  false,
+            keep_incomplete:   false,
+            skip_duplicates:   false,
+            memory_limit:      None,
         }
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use self::ReadEvent::*;
+// A sink that applies an error policy to events before passing them on to
+// another sink.
+struct PolicySink<'s, S: 's> {
+    sink:   &'s mut S,
+    policy: ReadPolicy,
+    errors: usize,
+}
 
-    #[derive(Clone, PartialEq, Eq, Debug)]
-    enum ReadEvent {
-        Message { pos: usize, msg: Vec<u8> },
-        Error   { pos: usize, len: usize, err: SysExReadError },
+impl<'s, S: SysExSink> SysExSink for PolicySink<'s, S> {
+    fn on_event(&mut self, event: SysExEvent) -> bool {
+        let is_error = match event {
+            SysExEvent::Error { err: Duplicate, .. } => false,
+            SysExEvent::Error      { .. }            => true,
+            SysExEvent::Incomplete { .. }            => true,
+            _                                        => false,
+        };
+
+        if !self.sink.on_event(event) {
+            return false
+        }
+
+        if !is_error {
+            return true
+        }
+
+        self.errors += 1;
+
+        match self.policy {
+            ReadPolicy::Strict        => false,
+            ReadPolicy::Lenient       => true,
+            ReadPolicy::AbortAfter(n) => self.errors < n,
+        }
     }
 
-    fn run_read(mut bytes: &[u8], cap: usize) -> Vec<ReadEvent> {
-        use std::cell::RefCell;
-        let events = RefCell::new(vec![]);
+    #[inline]
+    fn on_progress(&mut self, read: usize, total: Option<usize>) -> bool {
+        self.sink.on_progress(read, total)
+    }
+}
 
-        let result = read_sysex(
-            &mut bytes, cap,
-            |pos, msg| {
-                events.borrow_mut().push(Message { pos, msg: msg.to_vec() });
-                true
+/// Events reported by `SysExParser`, `read_sysex_events`, and `scan_sysex`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum SysExEvent<'a> {
+    /// A System Exclusive message at position `pos`, occupying `len` bytes of
+    /// input.  The message data `msg` excludes the SysEx start/end bytes and
+    /// any interleaved real-time bytes, but `len` includes them.
+    ///
+    /// If requested, `raw` holds the `len` bytes of the message exactly as
+    /// they occur in the input, including the start/end bytes and interleaved
+    /// real-time bytes.  See `ReadOptions::raw`.
+    Message { pos: usize, len: usize, msg: Cow<'a, [u8]>, raw: Option<Cow<'a, [u8]>> },
+
+    /// An error condition affecting `len` bytes at position `pos`.
+    Error { pos: usize, len: usize, err: SysExReadError },
+
+    /// A system real-time `byte` at position `pos`, interleaved within a
+    /// System Exclusive message.
+    RealTime { pos: usize, byte: u8 },
+
+    /// A System Exclusive message at position `pos`, truncated by the end of
+    /// input after `len` bytes.  The fields are as for `Message`.  Reported
+    /// only if requested, in place of an `UnexpectedEof` error.  See
+    /// `ReadOptions::keep_incomplete`.
+    Incomplete { pos: usize, len: usize, msg: Cow<'a, [u8]>, raw: Option<Cow<'a, [u8]>> },
+}
+
+impl<'a> SysExEvent<'a> {
+    /// Converts the event to one that owns its message data, copying the data
+    /// if it is borrowed.
+    pub fn into_owned(self) -> SysExEvent<'static> {
+        match self {
+            SysExEvent::Message  { pos, len, msg, raw } => SysExEvent::Message {
+                pos, len,
+                msg: Cow::Owned(msg.into_owned()),
+                raw: raw.map(|r| Cow::Owned(r.into_owned())),
             },
-            |pos, len, err| {
-                events.borrow_mut().push(Error { pos, len, err });
-                true
+            SysExEvent::Error    { pos, len, err } => SysExEvent::Error    { pos, len, err },
+            SysExEvent::RealTime { pos, byte     } => SysExEvent::RealTime { pos, byte     },
+            SysExEvent::Incomplete { pos, len, msg, raw } => SysExEvent::Incomplete {
+                pos, len,
+                msg: Cow::Owned(msg.into_owned()),
+                raw: raw.map(|r| Cow::Owned(r.into_owned())),
             },
-        );
+        }
+    }
 
-        assert!(result.unwrap());
-        events.into_inner()
+    /// Gets the error condition reported by the event, with its location, or
+    /// `None` if the event does not report an error.  An `Incomplete` event
+    /// reports an `UnexpectedEof` error.
+    pub fn error(&self) -> Option<SysExError> {
+        match *self {
+            SysExEvent::Error      { pos, len, err } => Some(SysExError { pos, len, err }),
+            SysExEvent::Incomplete { pos, len, ..  } => Some(SysExError { pos, len, err: UnexpectedEof }),
+            _                                        => None,
+        }
     }
 
-    #[test]
-    fn test_read_sysex_empty() {
-        let events = run_read(b"", 10);
-        assert_eq!(events.len(), 0);
+    /// Gets the position of the event.
+    #[inline]
+    pub fn pos(&self) -> usize {
+        self.range().start
     }
 
-    #[test]
-    fn test_read_sysex_junk() {
-        let events = run_read(b"any", 10);
-        assert_eq!(events.len(), 1);
-        assert_eq!(events[0], Error { pos: 0, len: 3, err: NotSysEx });
+    /// Gets the range of input positions occupied by the event.
+    pub fn range(&self) -> Range<usize> {
+        match *self {
+            SysExEvent::Message  { pos, len, .. } => pos..pos + len,
+            SysExEvent::Error    { pos, len, .. } => pos..pos + len,
+            SysExEvent::RealTime { pos,      .. } => pos..pos + 1,
+            SysExEvent::Incomplete { pos, len, .. } => pos..pos + len,
+        }
     }
+}
 
-    #[test]
-    fn test_read_sysex_sysex() {
-        let events = run_read(b"\xF0msg\xF7", 10);
-        assert_eq!(events.len(), 1);
-        assert_eq!(events[0], Message { pos: 0, msg: b"msg".to_vec() });
+/// Scans the given in-memory `bytes` for MIDI System Exclusive messages.
+///
+/// Returns an iterator over the same events that `read_sysex_events` reports
+/// for the same input, except that messages are never reported as `Overflow`
+/// errors, and messages always include their `raw` bytes.  Message data is
+/// borrowed from `bytes` without copying, unless system real-time bytes are
+/// interleaved within the message.
+pub fn scan_sysex(bytes: &[u8]) -> ScanSysEx {
+    ScanSysEx { bytes, start: 0, pos: 0, in_message: false, has_rt: false }
+}
+
+/// An iterator over the events in a slice of bytes.  See `scan_sysex`.
+#[derive(Clone, Debug)]
+pub struct ScanSysEx<'a> {
+    /// Bytes being scanned.
+    bytes: &'a [u8],
+
+    /// Start position of message or skipped chunk.
+    start: usize,
+
+    /// Position of next unscanned byte.
+    pos: usize,
+
+    /// Whether the scanner is within a System Exclusive message.
+    in_message: bool,
+
+    /// Whether the message contains interleaved real-time bytes.
+    has_rt: bool,
+}
+
+impl<'a> Iterator for ScanSysEx<'a> {
+    type Item = SysExEvent<'a>;
+
+    fn next(&mut self) -> Option<SysExEvent<'a>> {
+        use self::SysExEvent::*;
+
+        let bytes = self.bytes;
+        let start = self.start;
+
+        loop {
+            let rest = &bytes[self.pos..];
+
+            if !self.in_message {
+                // State A: Not In SysEx Message
+                let end = match rest.find_bits(SYSEX_START, ALL_BITS) {
+                    Some((i, _)) => self.pos + i,
+                    None         => bytes.len(),
+                };
+
+                self.start = end;
+                self.pos   = cmp::min(end + 1, bytes.len());
+                self.in_message = end != bytes.len();
+                self.has_rt     = false;
+
+                if end != start {
+                    return Some(Error { pos: start, len: end - start, err: NotSysEx })
+                } else if !self.in_message {
+                    return None
+                }
+            } else {
+                // State B: In SysEx Message
+                let (end, b) = match rest.find_bits(STATUS_BIT, STATUS_BIT) {
+                    Some((i, b)) => (self.pos + i, b),
+                    None         => {
+                        self.start      = bytes.len();
+                        self.pos        = bytes.len();
+                        self.in_message = false;
+                        return Some(Error { pos: start, len: bytes.len() - start, err: UnexpectedEof })
+                    },
+                };
+
+                self.pos = end + 1;
+
+                return Some(match b {
+                    SYSRT_MIN...SYSRT_MAX => {
+                        self.has_rt = true;
+                        RealTime { pos: end, byte: b }
+                    },
+                    SYSEX_START => {
+                        self.start  = end;
+                        self.has_rt = false;
+                        Error { pos: start, len: end - start, err: UnexpectedByte { byte: b } }
+                    },
+                    SYSEX_END => {
+                        self.start      = self.pos;
+                        self.in_message = false;
+
+                        let data = &bytes[start + 1..end];
+                        let msg  = if self.has_rt {
+                            Cow::Owned(data.iter().cloned().filter(|&b| b < SYSRT_MIN).collect())
+                        } else {
+                            Cow::Borrowed(data)
+                        };
+
+                        let raw = Some(Cow::Borrowed(&bytes[start..self.pos]));
+
+                        Message { pos: start, len: self.pos - start, msg, raw }
+                    },
+                    _ => {
+                        self.start      = end;
+                        self.in_message = false;
+                        Error { pos: start, len: end - start, err: UnexpectedByte { byte: b } }
+                    },
+                })
+            }
+        }
     }
+}
 
-    #[test]
-    fn test_read_sysex_with_junk() {
-        let events = run_read(b"abc\xF0def\xF7ghi\xF0jkl\xF7mno", 10);
-        assert_eq!(events.len(), 5);
-        assert_eq!(events[0], Error   { pos:  0, len: 3, err: NotSysEx });
-        assert_eq!(events[1], Message { pos:  3, msg: b"def".to_vec()  });
-        assert_eq!(events[2], Error   { pos:  8, len: 3, err: NotSysEx });
-        assert_eq!(events[3], Message { pos: 11, msg: b"jkl".to_vec()  });
-        assert_eq!(events[4], Error   { pos: 16, len: 3, err: NotSysEx });
+/// An incremental detector of MIDI System Exclusive messages.
+///
+/// A `SysExParser` consumes input in chunks of arbitrary size via `feed` and
+/// reports messages and error conditions exactly as `read_sysex` does.  Its
+/// state can be saved and restored, so that parsing of a large input can
+/// resume across multiple `feed` calls or even across process restarts.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SysExParser {
+    /// Whether the parser is within a System Exclusive message.
+    in_message: bool,
+
+    /// Start position of message or skipped chunk.
+    start: usize,
+
+    /// Position of next unfed byte.
+    offset: usize,
+
+    /// Length of message data (no start/end bytes).
+    len: usize,
+
+    /// Maximum length of message data, or `None` for no limit.
+    cap: Option<usize>,
+
+    /// Message data, without SysEx start/end bytes, up to `cap` bytes.
+    buf: Vec<u8>,
+
+    /// Required initial bytes of message data, or empty to accept all.
+    filter: Box<[u8]>,
+
+    /// Whether the message in progress has been rejected by the filter.
+    rejected: bool,
+
+    /// On-wire bytes of the message in progress, if kept.
+    raw: Option<Vec<u8>>,
+
+    /// Whether to report a message truncated by the end of input as
+    /// `Incomplete` rather than as an error.
+    keep_incomplete: bool,
+}
+
+impl SysExParser {
+    /// Creates a `SysExParser` that detects messages of length `cap` or less,
+    /// or of any length if `cap` is `None`.
+    ///
+    /// The message buffer starts empty and grows on demand, up to `cap` bytes.
+    pub fn new<C: Into<Option<usize>>>(cap: C) -> Self {
+        Self::with_offset(cap, 0)
     }
 
-    #[test]
-    fn test_read_sysex_with_sysrt() {
-        let events = run_read(b"\xF0abc\xF8def\xF7", 10);
-        assert_eq!(events.len(), 1);
-        assert_eq!(events[0], Message { pos: 0, msg: b"abcdef".to_vec() });
+    /// Creates a `SysExParser` that detects messages of length `cap` or less,
+    /// or of any length if `cap` is `None`, reporting positions relative to
+    /// the given initial `offset`.
+    pub fn with_offset<C: Into<Option<usize>>>(cap: C, offset: usize) -> Self {
+        Self {
+            in_message: false,
+            start:      offset,
+            offset:     offset,
+            len:        0,
+            cap:        cap.into(),
+            buf:        vec![],
+            filter:     Box::new([]),
+            rejected:   false,
+            raw:        None,
+            keep_incomplete: false,
+        }
     }
 
-    #[test]
-    fn test_read_sysex_interrupted_by_sysex() {
-        let events = run_read(b"\xF0abc\xF0def\xF7", 10);
-        assert_eq!(events.len(), 2);
-        assert_eq!(events[0], Error   { pos: 0, len: 4, err: UnexpectedByte });
-        assert_eq!(events[1], Message { pos: 4, msg: b"def".to_vec() });
+    /// Sets whether the parser reports a message truncated by the end of input
+    /// as an `Incomplete` event carrying the partial message data, rather than
+    /// as an `UnexpectedEof` error.  A truncated message that has overflowed
+    /// the parser's capacity or been rejected by the filter is reported as an
+    /// error regardless.
+    pub fn set_keep_incomplete(&mut self, keep: bool) {
+        self.keep_incomplete = keep;
     }
 
-    #[test]
-    fn test_read_sysex_interrupted_by_status() {
-        let events = run_read(b"\xF0abc\xA5def\xF7", 10);
-        assert_eq!(events.len(), 2);
-        assert_eq!(events[0], Error { pos: 0, len: 4, err: UnexpectedByte });
-        assert_eq!(events[1], Error { pos: 4, len: 5, err: NotSysEx       });
+    /// Sets whether the parser reports the on-wire bytes of each message in
+    /// the `raw` field of the `Message` event.
+    pub fn set_keep_raw(&mut self, keep: bool) {
+        self.raw = if keep { Some(vec![]) } else { None };
     }
 
-    #[test]
-    fn test_read_sysex_interrupted_by_eof() {
-        let events = run_read(b"\xF0abc", 10);
-        assert_eq!(events.len(), 1);
-        assert_eq!(events[0], Error { pos: 0, len: 4, err: UnexpectedEof });
+    /// Sets the initial bytes that message data must have, such as a
+    /// manufacturer ID, for the parser to report the message.  Other messages
+    /// are discarded without being copied or reported, even if they exceed
+    /// the parser's capacity.  An empty `prefix` accepts all messages.
+    pub fn set_filter(&mut self, prefix: &[u8]) {
+        self.filter = prefix.into();
     }
 
-    #[test]
-    fn test_read_sysex_overflow() {
-        let events = run_read(b"\xF0abc\xF7", 2);
-        assert_eq!(events.len(), 1);
-        assert_eq!(events[0], Error { pos: 0, len: 5, err: Overflow });
+    /// Gets the maximum length of messages the parser detects, or `None` if
+    /// there is no limit.
+    #[inline]
+    pub fn cap(&self) -> Option<usize> {
+        self.cap
     }
 
-    #[test]
-    fn test_read_sysex_overflow_2() {
-        let events = run_read(b"\xF0abc\xF8def\xF7", 2);
-        assert_eq!(events.len(), 1);
-        assert_eq!(events[0], Error { pos: 0, len: 9, err: Overflow });
+    /// Gets the position of the next byte to be fed to the parser.  This is
+    /// also the count of bytes consumed so far.
+    #[inline]
+    pub fn offset(&self) -> usize {
+        self.offset
     }
 
-    #[test]
-    fn test_encode_7bit() {
-        let data8 = [
-            0xF1, 0xE2, 0xD3, 0xC4, 0xB5, 0xA6, 0x97, 0x88, 0x79, 0x6A,
-        ];
-        let mut data7 = vec![];
-
-        encode_7bit(&data8, &mut data7);
-
-        assert_eq!(data7.len(), 12);
-        //                       always 0
-        //                       | new bits
-        //                       | |    leftover bits
-        //                       | |    |
-        //                    0b_x_xxxx_xxx
-        assert_eq!(data7[ 0], 0b_0_1110001_);
-        assert_eq!(data7[ 1], 0b_0_100010_1);
-        assert_eq!(data7[ 2], 0b_0_10011_11);
-        assert_eq!(data7[ 3], 0b_0_0100_110);
-        assert_eq!(data7[ 4], 0b_0_101_1100);
-        assert_eq!(data7[ 5], 0b_0_10_10110);
-        assert_eq!(data7[ 6], 0b_0_1_101001);
-        assert_eq!(data7[ 7], 0b_0__1001011);
-        assert_eq!(data7[ 8], 0b_0_0001000_);
-        assert_eq!(data7[ 9], 0b_0_111001_1);
-        assert_eq!(data7[10], 0b_0_01010_01);
-        assert_eq!(data7[11], 0b_0_0000_011);
-        //                         |    |
-        //                         |    final leftover bits
-        //                         0-padding
-    }
-
-    #[test]
-    fn test_decode_7bit() {
-        let data7 = [
-        //     don't care
-        //     | leftover bits
-        //     | |    new bits
-        //     | |    |
-        //  0b_x_xxxx_xxx
-            0b_1_1110001_,
-            0b_0_100010_1,
-            0b_1_10011_11,
-            0b_0_0100_110,
-            0b_1_101_1100,
-            0b_0_10_10110,
-            0b_1_1_101001,
-            0b_0__1001011,
-            0b_1_0001000_,
-            0b_0_111001_1,
-            0b_1_01010_01,
-            0b_0_1111_011,
+    /// Gets the start position of the message or skipped chunk in progress.
+    #[inline]
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// Gets the count of bytes the parser holds in buffers.
+    #[inline]
+    pub fn memory_used(&self) -> usize {
+        self.buf.capacity() + self.raw.as_ref().map_or(0, |r| r.capacity())
+    }
+
+    /// Returns `true` if the parser is within a System Exclusive message,
+    /// `false` otherwise.
+    #[inline]
+    pub fn in_message(&self) -> bool {
+        self.in_message
+    }
+
+    /// Consumes the given `bytes`, invoking the handler `on_msg` for each
+    /// detected message and the handler `on_err` for each error condition.
+    ///
+    /// Returns `true` if all bytes were consumed.  Returns `false` if a handler
+    /// returned `false` (stop); in that case, `offset()` indicates how many
+    /// bytes were consumed.
+    pub fn feed<M, E>(&mut self, bytes: &[u8], on_msg: M, on_err: E) -> bool
+    where
+        M: Fn(usize, &[u8])                 -> bool,
+        E: Fn(usize, usize, SysExReadError) -> bool,
+    {
+        self.feed_events(bytes, adapt(on_msg, on_err))
+    }
+
+    /// Consumes the given `bytes`, invoking the handler `on_event` for each
+    /// detected message, error condition, and system real-time byte
+    /// interleaved within a message.
+    ///
+    /// Returns `true` if all bytes were consumed.  Returns `false` if the
+    /// handler returned `false` (stop); in that case, `offset()` indicates how
+    /// many bytes were consumed.
+    pub fn feed_events<F>(&mut self, mut bytes: &[u8], mut on_event: F) -> bool
+    where
+        F: FnMut(SysExEvent) -> bool,
+    {
+        use self::SysExEvent::*;
+
+        // Helper for invoking the on_event handler
+        macro_rules! fire {
+            ($event:expr) => {
+                if !on_event($event) { return false }
+            }
+        }
+
+        while bytes.len() != 0 {
+            if !self.in_message {
+                // State A: Not In SysEx Message
+                let (i, _) = match bytes.find_bits(SYSEX_START, ALL_BITS) {
+                    Some(found) => found,
+                    None        => { self.offset += bytes.len(); return true },
+                };
+
+                let start   = self.start;
+                let end     = self.offset + i;
+                bytes       = &bytes[i + 1..];
+                self.offset = end + 1;
+                self.start  = end;
+                self.len    = 0;
+                self.buf.clear();
+                self.restart_raw();
+                self.in_message = true;
+                self.rejected   = false;
+
+                if end != start {
+                    fire!(Error { pos: start, len: end - start, err: NotSysEx });
+                }
+            } else {
+                // State B: In SysEx Message
+                let (i, b) = match bytes.find_bits(STATUS_BIT, STATUS_BIT) {
+                    Some(found) => found,
+                    None        => { self.append(bytes); return true },
+                };
+
+                self.append(&bytes[..i]);
+                let start   = self.start;
+                let end     = self.offset;
+                bytes       = &bytes[i + 1..];
+                self.offset = end + 1;
+
+                match b {
+                    SYSRT_MIN...SYSRT_MAX => {
+                        self.append_raw(&[b]);
+                        fire!(RealTime { pos: end, byte: b });
+                        // remain in state B
+                    },
+                    SYSEX_START => {
+                        self.start    = end;
+                        self.len      = 0;
+                        self.rejected = false;
+                        self.buf.clear();
+                        self.restart_raw();
+                        fire!(Error { pos: start, len: end - start, err: UnexpectedByte { byte: b } });
+                        // restart state B
+                    },
+                    SYSEX_END => {
+                        self.start      = self.offset;
+                        self.in_message = false;
+                        self.append_raw(&[b]);
+                        if self.rejected || self.len < self.filter.len() {
+                            // discard
+                        } else if self.len > self.buf.len() {
+                            fire!(Error { pos: start, len: self.offset - start, err: Overflow })
+                        } else {
+                            fire!(Message {
+                                pos: start,
+                                len: self.offset - start,
+                                msg: Cow::Borrowed(&self.buf[..]),
+                                raw: self.raw.as_ref().map(|r| Cow::Borrowed(&r[..])),
+                            })
+                        }
+                        // to state A
+                    },
+                    _ => {
+                        self.start      = end;
+                        self.in_message = false;
+                        fire!(Error { pos: start, len: end - start, err: UnexpectedByte { byte: b } });
+                        // to state A
+                    },
+                }
+            }
+        }
+
+        true
+    }
+
+    /// Signals the end of input, invoking the handler `on_err` for any
+    /// incomplete message or trailing non-SysEx bytes.
+    ///
+    /// Returns `false` if the handler returned `false` (stop), `true`
+    /// otherwise.
+    pub fn finish<E>(&mut self, on_err: E) -> bool
+    where
+        E: Fn(usize, usize, SysExReadError) -> bool,
+    {
+        self.finish_events(adapt(|_, _| true, on_err))
+    }
+
+    /// Signals the end of input, invoking the handler `on_event` for any
+    /// incomplete message or trailing non-SysEx bytes.
+    ///
+    /// Returns `false` if the handler returned `false` (stop), `true`
+    /// otherwise.
+    pub fn finish_events<F>(&mut self, mut on_event: F) -> bool
+    where
+        F: FnMut(SysExEvent) -> bool,
+    {
+        let start = self.start;
+        let len   = self.offset - start;
+        let err   = if self.in_message { UnexpectedEof } else { NotSysEx };
+
+        let salvage = self.in_message
+            && self.keep_incomplete
+            && !self.rejected
+            && self.buf.len() == self.len
+            && self.len >= self.filter.len();
+
+        self.start      = self.offset;
+        self.in_message = false;
+
+        if salvage {
+            return on_event(SysExEvent::Incomplete {
+                pos: start,
+                len,
+                msg: Cow::Borrowed(&self.buf[..]),
+                raw: self.raw.as_ref().map(|r| Cow::Borrowed(&r[..])),
+            })
+        }
+
+        len == 0 || on_event(SysExEvent::Error { pos: start, len, err })
+    }
+
+    /// Abandons the message in progress, if any, reporting it to the handler
+    /// `on_event` as an error of the given kind `err`.  Subsequent bytes are
+    /// parsed as if outside of a message.
+    ///
+    /// Returns `false` if the handler returned `false` (stop), `true`
+    /// otherwise.
+    pub fn abandon_events<F>(&mut self, err: SysExReadError, mut on_event: F) -> bool
+    where
+        F: FnMut(SysExEvent) -> bool,
+    {
+        if !self.in_message {
+            return true
+        }
+
+        let start = self.start;
+        let len   = self.offset - start;
+
+        self.start      = self.offset;
+        self.in_message = false;
+
+        on_event(SysExEvent::Error { pos: start, len, err })
+    }
+
+    /// Writes the parser state to the given `output` stream.
+    pub fn save<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        let cap = self.cap.map_or(u64::MAX, |c| c as u64);
+
+        output.write_all(&[self.in_message as u8])?;
+        output.write_all(&cap.to_be_bytes())?;
+        output.write_all(&(self.start   as u64).to_be_bytes())?;
+        output.write_all(&(self.offset  as u64).to_be_bytes())?;
+        output.write_all(&(self.len     as u64).to_be_bytes())?;
+        output.write_all(&(self.buf.len() as u64).to_be_bytes())?;
+        output.write_all(&self.buf)?;
+        output.write_all(&[self.rejected as u8])?;
+        output.write_all(&(self.filter.len() as u64).to_be_bytes())?;
+        output.write_all(&self.filter)?;
+        output.write_all(&[self.raw.is_some() as u8])?;
+        if let Some(ref raw) = self.raw {
+            output.write_all(&(raw.len() as u64).to_be_bytes())?;
+            output.write_all(raw)?;
+        }
+        output.write_all(&[self.keep_incomplete as u8])?;
+        Ok(())
+    }
+
+    /// Reads a parser state previously written by `save` from the given
+    /// `input` stream.
+    ///
+    /// Returns an `InvalidData` error if the state is inconsistent, or an
+    /// `UnexpectedEof` error if it is truncated.
+    pub fn restore<R: Read>(input: &mut R) -> io::Result<Self> {
+        let in_message = input.read_u8()?  != 0;
+        let cap        = input.read_u64()?;
+        let cap        = if cap == u64::MAX { None } else { Some(cap as usize) };
+        let start      = input.read_u64()? as usize;
+        let offset     = input.read_u64()? as usize;
+        let len        = input.read_u64()? as usize;
+
+        if start > offset {
+            return Err(invalid_state("start position is beyond offset"));
+        }
+
+        let buf_len = input.read_u64()?;
+        if buf_len > len as u64 || cap.map_or(false, |c| buf_len > c as u64) {
+            return Err(invalid_state("buffer length exceeds message length or capacity"));
+        }
+
+        let mut parser = Self::new(cap);
+        let buf        = read_state_bytes(input, buf_len)?;
+
+        let rejected   = input.read_u8()?  != 0;
+        let filter_len = input.read_u64()?;
+        let filter     = read_state_bytes(input, filter_len)?;
+
+        if input.read_u8()? != 0 {
+            let raw_len = input.read_u64()?;
+            if raw_len > (offset - start) as u64 {
+                return Err(invalid_state("raw message length exceeds bytes fed"));
+            }
+            parser.raw = Some(read_state_bytes(input, raw_len)?);
+        }
+
+        parser.keep_incomplete = input.read_u8()? != 0;
+
+        parser.buf        = buf;
+        parser.filter     = filter.into_boxed_slice();
+        parser.rejected   = rejected;
+        parser.in_message = in_message;
+        parser.start      = start;
+        parser.offset     = offset;
+        parser.len        = len;
+        Ok(parser)
+    }
+
+    // Appends `bytes` to the message in progress, discarding bytes beyond the
+    // parser's capacity or of messages rejected by the filter.
+    fn append(&mut self, bytes: &[u8]) {
+        let len = self.len;
+
+        // Check filter
+        if !self.rejected && len < self.filter.len() {
+            let n = cmp::min(bytes.len(), self.filter.len() - len);
+            self.rejected = bytes[..n] != self.filter[len..len + n];
+        }
+
+        // Copy unless rejected
+        if !self.rejected {
+            let n = match self.cap {
+                Some(cap) => cmp::min(bytes.len(), cap.saturating_sub(self.buf.len())),
+                None      => bytes.len(),
+            };
+            self.buf.extend_from_slice(&bytes[..n]);
+        }
+
+        self.len    += bytes.len();
+        self.offset += bytes.len();
+        self.append_raw(bytes);
+    }
+
+    // Appends `bytes` to the on-wire bytes of the message in progress, if
+    // kept and if the message can still be reported.
+    fn append_raw(&mut self, bytes: &[u8]) {
+        let reportable = !self.rejected && self.buf.len() == self.len;
+        if let Some(ref mut raw) = self.raw {
+            if reportable {
+                raw.extend_from_slice(bytes);
+            }
+        }
+    }
+
+    // Begins the on-wire bytes of a new message, if kept.
+    fn restart_raw(&mut self) {
+        if let Some(ref mut raw) = self.raw {
+            raw.clear();
+            raw.push(SYSEX_START);
+        }
+    }
+}
+
+// Creates the error returned when a saved parser state is inconsistent.
+fn invalid_state(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid SysEx parser state: {}.", what)
+    )
+}
+
+// Reads `len` bytes of a saved parser state.  Reads incrementally rather than
+// allocating `len` bytes up front, so that a corrupt length in a truncated
+// state fails with `UnexpectedEof` instead of exhausting memory.
+fn read_state_bytes<R: Read>(input: &mut R, len: u64) -> io::Result<Vec<u8>> {
+    let mut bytes = vec![];
+    input.take(len).read_to_end(&mut bytes)?;
+
+    if (bytes.len() as u64) < len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Invalid SysEx parser state: truncated."
+        ));
+    }
+
+    Ok(bytes)
+}
+
+/// Possible error conditions encountered by `read_sysex`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SysExReadError {
+    /// The bytes did not contain a System Exclusive message.
+    NotSysEx,
+
+    /// A System Exclusive message exceeded the maximum allowed length.
+    Overflow,
+
+    /// A System Exclusive message was interrupted by an unexpected `byte`,
+    /// which immediately follows the message.
+    UnexpectedByte { byte: u8 },
+
+    /// A System Exclusive message was interrupted by end-of-file.
+    UnexpectedEof,
+
+    /// A System Exclusive message or the input as a whole exceeded its allowed
+    /// time.  See `ReadOptions::timeout` and `ReadOptions::message_timeout`.
+    Timeout,
+
+    /// A System Exclusive message repeated the data of an earlier message, and
+    /// was skipped.  See `ReadOptions::skip_duplicates`.
+    Duplicate,
+}
+
+impl SysExReadError {
+    /// Gets the stable diagnostic code of the error, such as `A6-SYX-001`.
+    /// Codes appear in `Display` output and are never reused or renumbered.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            NotSysEx             => "A6-SYX-001",
+            Overflow             => "A6-SYX-002",
+            UnexpectedByte { .. } => "A6-SYX-003",
+            UnexpectedEof        => "A6-SYX-004",
+            Timeout              => "A6-SYX-005",
+            Duplicate            => "A6-SYX-006",
+        }
+    }
+
+    // Writes the message of the error, without its code.
+    fn fmt_message(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NotSysEx => f.write_str(
+                "The bytes are not part of a System Exclusive message."
+            ),
+            Overflow => f.write_str(
+                "The System Exclusive message exceeds the maximum length."
+            ),
+            UnexpectedByte { byte } => write!(
+                f, "The System Exclusive message was interrupted by byte {:02X}.",
+                byte
+            ),
+            UnexpectedEof => f.write_str(
+                "The System Exclusive message was interrupted by end of input."
+            ),
+            Timeout => f.write_str(
+                "The System Exclusive message or input exceeded its allowed time."
+            ),
+            Duplicate => f.write_str(
+                "The System Exclusive message repeats an earlier message."
+            ),
+        }
+    }
+}
+
+impl fmt::Display for SysExReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: ", self.code())?;
+        self.fmt_message(f)
+    }
+}
+
+impl error::Error for SysExReadError { }
+
+impl From<SysExReadError> for io::Error {
+    fn from(e: SysExReadError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+/// An error condition detected while reading System Exclusive messages,
+/// together with its location in the input.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SysExError {
+    /// Position of the affected bytes in the input.
+    pub pos: usize,
+
+    /// Count of affected bytes.
+    pub len: usize,
+
+    /// The error condition.
+    pub err: SysExReadError,
+}
+
+impl SysExError {
+    /// Gets the stable diagnostic code of the error condition.
+    #[inline]
+    pub fn code(&self) -> &'static str {
+        self.err.code()
+    }
+}
+
+impl fmt::Display for SysExError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: At offset {} ({} byte(s)): ", self.code(), self.pos, self.len)?;
+        self.err.fmt_message(f)
+    }
+}
+
+impl error::Error for SysExError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.err)
+    }
+}
+
+impl From<SysExError> for io::Error {
+    fn from(e: SysExError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+/// Cross-checks of the streaming and in-memory SysEx parsers, for fuzzing and
+/// property tests.  Each check panics if the parsers disagree.
+#[cfg(any(test, feature = "differential"))]
+pub mod differential {
+    use super::*;
+
+    /// Checks that reading `bytes` as a stream, in chunks of several sizes,
+    /// reports the same events as `scan_sysex`.
+    pub fn check_parser(bytes: &[u8]) {
+        let expected = scan_sysex(bytes)
+            .map(|e| e.into_owned())
+            .collect::<Vec<_>>();
+
+        let mut options = ReadOptions::new(None);
+        options.raw = true;
+
+        for &chunk in &[1, 7, 64, bytes.len().max(1)] {
+            let mut input  = io::BufReader::with_capacity(chunk, bytes);
+            let mut actual = vec![];
+
+            read_sysex_with(&mut input, &options, &mut |e: SysExEvent| {
+                actual.push(e.into_owned());
+                true
+            }).unwrap();
+
+            assert_eq!(actual, expected, "reading in {}-byte chunks diverges", chunk);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use self::ReadEvent::*;
+
+    #[derive(Clone, PartialEq, Eq, Debug)]
+    enum ReadEvent {
+        Message { pos: usize, msg: Vec<u8> },
+        Error   { pos: usize, len: usize, err: SysExReadError },
+    }
+
+    fn run_read<N: Into<Option<usize>>>(mut bytes: &[u8], cap: N) -> Vec<ReadEvent> {
+        use std::cell::RefCell;
+        let events = RefCell::new(vec![]);
+
+        let result = read_sysex(
+            &mut bytes, cap,
+            |pos, msg| {
+                events.borrow_mut().push(Message { pos, msg: msg.to_vec() });
+                true
+            },
+            |pos, len, err| {
+                events.borrow_mut().push(Error { pos, len, err });
+                true
+            },
+        );
+
+        assert!(result.unwrap().complete);
+        events.into_inner()
+    }
+
+    #[test]
+    fn test_read_sysex_unlimited() {
+        let mut bytes = vec![0xF0];
+        bytes.extend(vec![0x55; 100_000]);
+        bytes.push(0xF7);
+
+        let events = run_read(&bytes, None);
+
+        assert_eq!(events, vec![Message { pos: 0, msg: vec![0x55; 100_000] }]);
+    }
+
+    #[test]
+    fn test_parser_unlimited_chunked() {
+        let events = run_feed(&[b"\xF0abc", b"def", b"ghi\xF7"], None);
+
+        assert_eq!(events, vec![Message { pos: 0, msg: b"abcdefghi".to_vec() }]);
+    }
+
+    #[test]
+    fn test_parser_growable_overflow_chunked() {
+        let events = run_feed(&[b"\xF0abc", b"def", b"ghi\xF7\xF0jk\xF7"], 4);
+
+        assert_eq!(events, vec![
+            Error   { pos:  0, len: 11, err: Overflow },
+            Message { pos: 11, msg: b"jk".to_vec()    },
+        ]);
+    }
+
+    #[test]
+    fn test_read_sysex_summary() {
+        let mut bytes = &b"abc\xF0def\xF7\xF0ghi\x90\xF0jkl\xF7mno"[..];
+
+        let summary = read_sysex(&mut bytes, 10, |_, _| true, |_, _, _| true).unwrap();
+
+        assert_eq!(summary, ScanSummary {
+            messages:      2,
+            errors:        4,
+            bytes:         21,
+            skipped_bytes: 11,
+            complete:      true,
+        });
+    }
+
+    #[test]
+    fn test_read_sysex_summary_stopped() {
+        let mut bytes = &b"\xF0abc\xF7\xF0def\xF7"[..];
+
+        let summary = read_sysex(&mut bytes, 10, |_, _| false, |_, _, _| true).unwrap();
+
+        assert_eq!(summary, ScanSummary {
+            messages:      1,
+            errors:        0,
+            bytes:         5,
+            skipped_bytes: 0,
+            complete:      false,
+        });
+    }
+
+    #[test]
+    fn test_read_sysex_empty() {
+        let events = run_read(b"", 10);
+        assert_eq!(events.len(), 0);
+    }
+
+    #[test]
+    fn test_read_sysex_junk() {
+        let events = run_read(b"any", 10);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0], Error { pos: 0, len: 3, err: NotSysEx });
+    }
+
+    #[test]
+    fn test_read_sysex_sysex() {
+        let events = run_read(b"\xF0msg\xF7", 10);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0], Message { pos: 0, msg: b"msg".to_vec() });
+    }
+
+    #[test]
+    fn test_read_sysex_with_junk() {
+        let events = run_read(b"abc\xF0def\xF7ghi\xF0jkl\xF7mno", 10);
+        assert_eq!(events.len(), 5);
+        assert_eq!(events[0], Error   { pos:  0, len: 3, err: NotSysEx });
+        assert_eq!(events[1], Message { pos:  3, msg: b"def".to_vec()  });
+        assert_eq!(events[2], Error   { pos:  8, len: 3, err: NotSysEx });
+        assert_eq!(events[3], Message { pos: 11, msg: b"jkl".to_vec()  });
+        assert_eq!(events[4], Error   { pos: 16, len: 3, err: NotSysEx });
+    }
+
+    #[test]
+    fn test_read_sysex_with_sysrt() {
+        let events = run_read(b"\xF0abc\xF8def\xF7", 10);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0], Message { pos: 0, msg: b"abcdef".to_vec() });
+    }
+
+    #[test]
+    fn test_read_sysex_interrupted_by_sysex() {
+        let events = run_read(b"\xF0abc\xF0def\xF7", 10);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0], Error   { pos: 0, len: 4, err: UnexpectedByte { byte: 0xF0 } });
+        assert_eq!(events[1], Message { pos: 4, msg: b"def".to_vec() });
+    }
+
+    #[test]
+    fn test_read_sysex_interrupted_by_status() {
+        let events = run_read(b"\xF0abc\xA5def\xF7", 10);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0], Error { pos: 0, len: 4, err: UnexpectedByte { byte: 0xA5 } });
+        assert_eq!(events[1], Error { pos: 4, len: 5, err: NotSysEx                      });
+    }
+
+    #[test]
+    fn test_read_sysex_interrupted_by_eof() {
+        let events = run_read(b"\xF0abc", 10);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0], Error { pos: 0, len: 4, err: UnexpectedEof });
+    }
+
+    #[test]
+    fn test_read_sysex_overflow() {
+        let events = run_read(b"\xF0abc\xF7", 2);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0], Error { pos: 0, len: 5, err: Overflow });
+    }
+
+    #[test]
+    fn test_read_sysex_overflow_2() {
+        let events = run_read(b"\xF0abc\xF8def\xF7", 2);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0], Error { pos: 0, len: 9, err: Overflow });
+    }
+
+    fn run_feed<N: Into<Option<usize>>>(chunks: &[&[u8]], cap: N) -> Vec<ReadEvent> {
+        use std::cell::RefCell;
+        let events     = RefCell::new(vec![]);
+        let mut parser = SysExParser::new(cap);
+
+        let on_msg = |pos, msg: &[u8]| {
+            events.borrow_mut().push(Message { pos, msg: msg.to_vec() });
+            true
+        };
+        let on_err = |pos, len, err| {
+            events.borrow_mut().push(Error { pos, len, err });
+            true
+        };
+
+        for chunk in chunks {
+            // Round-trip the parser state between chunks
+            let mut saved = vec![];
+            parser.save(&mut saved).unwrap();
+            parser = SysExParser::restore(&mut &saved[..]).unwrap();
+
+            assert!(parser.feed(chunk, &on_msg, &on_err));
+        }
+
+        assert!(parser.finish(&on_err));
+        events.into_inner()
+    }
+
+    #[test]
+    fn test_parser_chunked() {
+        let bytes = b"abc\xF0de\xF8f\xF7ghi\xF0jkl\xF0mn\xF7o\xA5p\xF0qr";
+
+        let expected = run_read(bytes, 10);
+
+        // Split input at every possible pair of positions
+        for i in 0..bytes.len() {
+            for j in i..bytes.len() {
+                let events = run_feed(&[&bytes[..i], &bytes[i..j], &bytes[j..]], 10);
+                assert_eq!(events, expected, "split at {}, {}", i, j);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parser_chunked_overflow() {
+        let events = run_feed(&[b"\xF0ab", b"cd", b"\xF7"], 3);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0], Error { pos: 0, len: 6, err: Overflow });
+    }
+
+    #[test]
+    fn test_parser_stop() {
+        let mut parser = SysExParser::new(10);
+
+        let more = parser.feed(b"\xF0abc\xF7\xF0def\xF7", |_, _| false, |_, _, _| true);
+
+        assert_eq!(more, false);
+        assert_eq!(parser.offset(), 5);
+        assert_eq!(parser.in_message(), false);
+    }
+
+    #[test]
+    fn test_parser_restore_rejected() {
+        let input  = b"\xF0\x43\x10\x11\x12\x13\xF7\xF0\x00\x00\x0E\x01\xF7";
+        let split  = 5;  // Within the rejected message
+
+        use std::cell::RefCell;
+        let events       = RefCell::new(vec![]);
+        let expected     = RefCell::new(vec![]);
+        let mut parser   = SysExParser::new(10);
+        parser.set_filter(&[0x00, 0x00, 0x0E]);
+
+        let mut whole = parser.clone();
+        let on_expected = |pos, msg: &[u8]| { expected.borrow_mut().push((pos, msg.to_vec())); true };
+        whole.feed(input, on_expected, |_, _, _| true);
+
+        parser.feed(&input[..split], |_, _| true, |_, _, _| true);
+        let mut saved = vec![];
+        parser.save(&mut saved).unwrap();
+        let mut restored = SysExParser::restore(&mut &saved[..]).unwrap();
+        assert_eq!(restored, parser);
+
+        let on_msg = |pos, msg: &[u8]| { events.borrow_mut().push((pos, msg.to_vec())); true };
+        restored.feed(&input[split..], on_msg, |_, _, _| true);
+
+        assert_eq!(events, expected);
+        assert_eq!(events.into_inner(), vec![(7, vec![0x00, 0x00, 0x0E, 0x01])]);
+    }
+
+    #[test]
+    fn test_parser_restore_invalid() {
+        let mut saved = vec![];
+        let mut parser = SysExParser::new(4);
+        parser.feed(b"junk", |_, _| true, |_, _, _| true);
+        parser.save(&mut saved).unwrap();
+
+        // Corrupt start position to exceed offset
+        saved[9 + 7] = 0xFF;
+
+        let result = SysExParser::restore(&mut &saved[..]);
+
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_parser_restore_corrupt_lengths() {
+        let mut saved = vec![];
+        let mut parser = SysExParser::new(None);
+        parser.set_filter(&[0x00]);
+        parser.set_keep_raw(true);
+        parser.feed(b"\xF0\x00ab", |_, _| true, |_, _, _| true);
+        parser.save(&mut saved).unwrap();
+
+        // Buffer length (at 33) beyond message length
+        let mut bad = saved.clone();
+        bad[33..41].copy_from_slice(&u64::MAX.to_be_bytes());
+        let result = SysExParser::restore(&mut &bad[..]);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        // Filter length (at 45, after buffer and rejected flag) beyond input
+        let mut bad = saved.clone();
+        bad[45..53].copy_from_slice(&(u64::MAX / 2).to_be_bytes());
+        let result = SysExParser::restore(&mut &bad[..]);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+
+        // Raw length (at 55) beyond bytes fed
+        let mut bad = saved.clone();
+        bad[55..63].copy_from_slice(&1000u64.to_be_bytes());
+        let result = SysExParser::restore(&mut &bad[..]);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        // Truncated anywhere
+        for n in 0..saved.len() {
+            assert!(SysExParser::restore(&mut &saved[..n]).is_err(), "truncated at {}", n);
+        }
+
+        assert_eq!(SysExParser::restore(&mut &saved[..]).unwrap(), parser);
+    }
+
+    #[test]
+    fn test_read_sysex_tracked() {
+        use std::cell::RefCell;
+        use io::CountingReader;
+        let events = RefCell::new(vec![]);
+
+        let mut src = CountingReader::new(&b"ab\xF0cd\xF7"[..]);
+        src.read_u8().unwrap();
+
+        let result = read_sysex_tracked(
+            &mut src, 10,
+            |pos, msg| {
+                events.borrow_mut().push(Message { pos, msg: msg.to_vec() });
+                true
+            },
+            |pos, len, err| {
+                events.borrow_mut().push(Error { pos, len, err });
+                true
+            },
+        );
+
+        assert!(result.unwrap());
+        assert_eq!(events.into_inner(), vec![
+            Error   { pos: 1, len: 1, err: NotSysEx     },
+            Message { pos: 2, msg: b"cd".to_vec()       },
+        ]);
+        assert_eq!(src.offset(), 6);
+    }
+
+    fn run_read_joined(mut bytes: &[u8], cap: usize) -> Vec<ReadEvent> {
+        use std::cell::RefCell;
+        let events = RefCell::new(vec![]);
+
+        let result = read_sysex_joined(
+            &mut bytes, cap,
+            |msg| msg.len() >= 6,
+            |pos, msg| {
+                events.borrow_mut().push(Message { pos, msg: msg.to_vec() });
+                true
+            },
+            |pos, len, err| {
+                events.borrow_mut().push(Error { pos, len, err });
+                true
+            },
+        );
+
+        assert!(result.unwrap());
+        events.into_inner()
+    }
+
+    #[test]
+    fn test_read_sysex_joined_whole() {
+        let events = run_read_joined(b"\xF0abcdef\xF7", 10);
+        assert_eq!(events, vec![
+            Message { pos: 0, msg: b"abcdef".to_vec() },
+        ]);
+    }
+
+    #[test]
+    fn test_read_sysex_joined_fragments() {
+        let events = run_read_joined(b"\xF0ab\xF7\xF0cd\xF7\xF0ef\xF7\xF0ghijkl\xF7", 10);
+        assert_eq!(events, vec![
+            Message { pos:  0, msg: b"abcdef".to_vec() },
+            Message { pos: 12, msg: b"ghijkl".to_vec() },
+        ]);
+    }
+
+    #[test]
+    fn test_read_sysex_joined_interrupted() {
+        let events = run_read_joined(b"\xF0ab\xF7xy\xF0cd\xF7", 10);
+        assert_eq!(events, vec![
+            Message { pos: 0, msg: b"ab".to_vec() },
+            Error   { pos: 4, len: 2, err: NotSysEx },
+            Message { pos: 6, msg: b"cd".to_vec() },
+        ]);
+    }
+
+    #[test]
+    fn test_read_sysex_events() {
+        let mut bytes  = &b"\xF8\xF0ab\xF8c\xFE\xF7"[..];
+        let mut events = vec![];
+
+        let result = read_sysex_events(&mut bytes, 10, |event| {
+            events.push(match event {
+                SysExEvent::Message  { pos, len, msg, .. } => format!("{} msg {} {:?}", pos, len, msg),
+                SysExEvent::Error    { pos, len, err } => format!("{} err {} {:?}", pos, len, err),
+                SysExEvent::RealTime { pos, byte     } => format!("{} rt {:X}", pos, byte),
+                SysExEvent::Incomplete { .. }          => unreachable!(),
+            });
+            true
+        });
+
+        assert!(result.unwrap());
+        assert_eq!(events, vec![
+            "0 err 1 NotSysEx",
+            "4 rt F8",
+            "6 rt FE",
+            "1 msg 7 [97, 98, 99]",
+        ]);
+    }
+
+    // A sink that records the position of each event
+    #[derive(Default)]
+    struct PosSink(Vec<usize>);
+
+    impl SysExSink for PosSink {
+        fn on_event(&mut self, event: SysExEvent) -> bool {
+            self.0.push(event.pos());
+            true
+        }
+    }
+
+    fn run_read_with(bytes: &[u8], policy: ReadPolicy) -> (bool, Vec<usize>) {
+        let mut sink    = PosSink::default();
+        let mut options = ReadOptions::new(10);
+        options.policy  = policy;
+
+        let result = read_sysex_with(&mut &bytes[..], &options, &mut sink);
+
+        (result.unwrap(), sink.0)
+    }
+
+    const POLICY_INPUT: &[u8] = b"\xF0a\xF7b\xF0c\xF7d\xF0e\xF7f";
+
+    #[test]
+    fn test_read_sysex_with_lenient() {
+        let result = run_read_with(POLICY_INPUT, ReadPolicy::Lenient);
+        assert_eq!(result, (true, vec![0, 3, 4, 7, 8, 11]));
+    }
+
+    #[test]
+    fn test_read_sysex_with_strict() {
+        let result = run_read_with(POLICY_INPUT, ReadPolicy::Strict);
+        assert_eq!(result, (false, vec![0, 3]));
+    }
+
+    #[test]
+    fn test_read_sysex_with_abort_after() {
+        let result = run_read_with(POLICY_INPUT, ReadPolicy::AbortAfter(2));
+        assert_eq!(result, (false, vec![0, 3, 4, 7]));
+    }
+
+    #[test]
+    fn test_scan_sysex_matches_read() {
+        let inputs: &[&[u8]] = &[
+            b"",
+            b"any",
+            b"\xF0msg\xF7",
+            b"abc\xF0def\xF7ghi\xF0jkl\xF7mno",
+            b"\xF0abc\xF8def\xFE\xF7",
+            b"\xF0abc\xF0def\xF7",
+            b"\xF0abc\xA5def\xF7",
+            b"\xF0abc",
+            b"\xF0",
+            b"\xF7\xF0\xF0\xF7\xF8",
         ];
-        let mut data8 = vec![];
 
-        decode_7bit(&data7, &mut data8);
+        let mut options = ReadOptions::new(100);
+        options.raw = true;
 
-        assert_eq!(data8.len(), 10);
-        assert_eq!(data8[0], 0xF1);
-        assert_eq!(data8[1], 0xE2);
-        assert_eq!(data8[2], 0xD3);
-        assert_eq!(data8[3], 0xC4);
-        assert_eq!(data8[4], 0xB5);
-        assert_eq!(data8[5], 0xA6);
-        assert_eq!(data8[6], 0x97);
-        assert_eq!(data8[7], 0x88);
-        assert_eq!(data8[8], 0x79);
-        assert_eq!(data8[9], 0x6A);
-        // Final leftover 4 bits go unused.
+        for &input in inputs {
+            let mut expected = vec![];
+            read_sysex_with(&mut &input[..], &options, &mut |e: SysExEvent| {
+                expected.push(format!("{:?}", e));
+                true
+            }).unwrap();
+
+            let actual = scan_sysex(input)
+                .map(|e| format!("{:?}", e))
+                .collect::<Vec<_>>();
+
+            assert_eq!(actual, expected, "for input {:?}", input);
+        }
     }
-}
 
+    #[test]
+    fn test_event_error() {
+        let events = scan_sysex(b"\xF0a\xF7\xF0b\x90").collect::<Vec<_>>();
+
+        assert_eq!(events[0].error(), None);
+        assert_eq!(events[1].error(), Some(SysExError {
+            pos: 3, len: 2, err: UnexpectedByte { byte: 0x90 }
+        }));
+    }
+
+    #[test]
+    fn test_sysex_error_display() {
+        let err = SysExError { pos: 3, len: 2, err: UnexpectedByte { byte: 0x90 } };
+
+        assert_eq!(
+            err.to_string(),
+            "A6-SYX-003: At offset 3 (2 byte(s)): \
+             The System Exclusive message was interrupted by byte 90."
+        );
+    }
+
+    #[test]
+    fn test_sysex_error_into_io_error() {
+        fn check(on_err: &mut FnMut(SysExError) -> io::Result<()>) -> io::Result<()> {
+            on_err(SysExError { pos: 0, len: 1, err: NotSysEx })
+        }
+
+        let err = check(&mut |e| Err(e)?).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            err.get_ref().unwrap().downcast_ref::<SysExError>(),
+            Some(&SysExError { pos: 0, len: 1, err: NotSysEx })
+        );
+    }
+
+    #[test]
+    fn test_event_range() {
+        let input  = b"ab\xF0cd\xF8e\xF7";
+
+        let ranges = scan_sysex(input).map(|e| e.range()).collect::<Vec<_>>();
+
+        assert_eq!(ranges, vec![0..2, 5..6, 2..8]);
+    }
+
+    #[test]
+    fn test_scan_sysex_borrowed() {
+        let input = b"\xF0abc\xF7\xF0de\xF8f\xF7";
+
+        let events = scan_sysex(input).collect::<Vec<_>>();
+
+        match events[0] {
+            SysExEvent::Message { msg: Cow::Borrowed(msg), .. } => assert_eq!(msg, b"abc"),
+            ref e => panic!("Unexpected event: {:?}", e),
+        }
+        match events[2] {
+            SysExEvent::Message { msg: Cow::Owned(ref msg), .. } => assert_eq!(msg, b"def"),
+            ref e => panic!("Unexpected event: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_read_sysex_with_progress() {
+        #[derive(Default)]
+        struct ProgressSink(Vec<(usize, Option<usize>)>);
+
+        impl SysExSink for ProgressSink {
+            fn on_event(&mut self, _: SysExEvent) -> bool { true }
+
+            fn on_progress(&mut self, read: usize, total: Option<usize>) -> bool {
+                self.0.push((read, total));
+                true
+            }
+        }
+
+        use std::io::BufReader;
+        let bytes = [0xF0; 10];
+        let mut input = BufReader::with_capacity(3, &bytes[..]);
+
+        let mut sink    = ProgressSink::default();
+        let mut options = ReadOptions::new(10);
+        options.progress_interval = Some(4);
+        options.total_len         = Some(10);
+
+        assert!(read_sysex_with(&mut input, &options, &mut sink).unwrap());
+        assert_eq!(sink.0, vec![
+            (3, Some(10)),  // first chunk
+            (6, Some(10)),  // crossed 4
+            (9, Some(10)),  // crossed 8
+            (10, Some(10)), // EOF
+        ]);
+    }
+
+    #[test]
+    fn test_read_sysex_with_id_filter() {
+        let input = b"\xF0\x00\x00\x0E\x1Da\xF7\xF0\x43\x10b\xF7\xF0\x00\xF7\xF0\x00\x00\x0Ec\xF0";
+
+        let mut events  = vec![];
+        let mut options = ReadOptions::new(5);
+        options.id_filter = Some(vec![0x00, 0x00, 0x0E]);
+
+        let result = read_sysex_with(&mut &input[..], &options, &mut |e: SysExEvent| {
+            events.push(format!("{:?}", e));
+            true
+        });
+
+        assert!(result.unwrap());
+        assert_eq!(events, vec![
+            "Message { pos: 0, len: 7, msg: [0, 0, 14, 29, 97], raw: None }",
+            "Error { pos: 15, len: 5, err: UnexpectedByte { byte: 240 } }",
+            "Error { pos: 20, len: 1, err: UnexpectedEof }",
+        ]);
+    }
+
+    #[test]
+    fn test_read_sysex_with_channel() {
+        use std::sync::mpsc;
+        use std::thread;
+
+        let (tx, rx) = mpsc::sync_channel(1);
+
+        let reader = thread::spawn(move || {
+            let mut tx = tx;
+            read_sysex_with(&mut &POLICY_INPUT[..], &ReadOptions::new(10), &mut tx).unwrap()
+        });
+
+        let events = rx.iter().map(|e| e.range()).collect::<Vec<_>>();
+
+        assert!(reader.join().unwrap());
+        assert_eq!(events, vec![0..3, 3..4, 4..7, 7..8, 8..11, 11..12]);
+    }
+
+    #[test]
+    fn test_read_sysex_with_channel_hangup() {
+        use std::sync::mpsc;
+
+        let (mut tx, rx) = mpsc::channel();
+        drop(rx);
+
+        let more = read_sysex_with(&mut &POLICY_INPUT[..], &ReadOptions::new(10), &mut tx);
+
+        assert!(!more.unwrap());
+    }
+
+    #[test]
+    fn test_read_sysex_with_cancel() {
+        let token       = CancelToken::new();
+        let mut options = ReadOptions::new(10);
+        options.cancel  = Some(token.clone());
+
+        let mut input  = io::BufReader::with_capacity(4, &POLICY_INPUT[..]);
+        let mut events = 0;
+
+        let result = read_sysex_with(&mut input, &options, &mut |_: SysExEvent| {
+            events += 1;
+            token.cancel();
+            true
+        });
+
+        assert!(result.unwrap_err().is_cancelled());
+        assert_eq!(events, 1);
+    }
+
+    // A reader that yields the given chunks, then reports timeouts forever
+    struct StallingReader(Vec<&'static [u8]>);
+
+    impl Read for StallingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = {
+                let src = self.fill_buf()?;
+                let n   = src.len().min(buf.len());
+                buf[..n].copy_from_slice(&src[..n]);
+                n
+            };
+            self.consume(n);
+            Ok(n)
+        }
+    }
+
+    impl BufRead for StallingReader {
+        fn fill_buf(&mut self) -> io::Result<&[u8]> {
+            match self.0.first() {
+                Some(chunk) => Ok(chunk),
+                None        => Err(io::Error::new(io::ErrorKind::TimedOut, "stalled")),
+            }
+        }
+
+        fn consume(&mut self, amt: usize) {
+            self.0[0] = &self.0[0][amt..];
+            if self.0[0].is_empty() {
+                self.0.remove(0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_stalling_reader() {
+        let mut input = StallingReader(vec![b"abc", b"d"]);
+        let mut buf   = [0; 2];
+
+        assert_eq!(input.read(&mut buf).unwrap(), 2);
+        assert_eq!(&buf, b"ab");
+        assert_eq!(input.read(&mut buf).unwrap(), 1);
+        assert_eq!(input.read(&mut buf).unwrap(), 1);
+        assert_eq!(&buf[..1], b"d");
+        assert_eq!(input.read(&mut buf).unwrap_err().kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn test_read_sysex_with_timeouts() {
+        // The overall timeout is far longer than the message timeout, so that
+        // the message timeout fires first even on a heavily loaded machine.
+        let mut input   = StallingReader(vec![b"\xF0ab\xF7\xF0cd"]);
+        let mut options = ReadOptions::new(10);
+        options.message_timeout = Some(Duration::from_millis(10));
+        options.timeout         = Some(Duration::from_millis(500));
+
+        let mut events = vec![];
+        let more = read_sysex_with(&mut input, &options, &mut |e: SysExEvent| {
+            events.push(e.into_owned());
+            true
+        });
+
+        assert!(!more.unwrap());
+        assert_eq!(events, vec![
+            SysExEvent::Message { pos: 0, len: 4, msg: Cow::Borrowed(b"ab"), raw: None },
+            SysExEvent::Error   { pos: 4, len: 3, err: Timeout },
+            SysExEvent::Error   { pos: 7, len: 0, err: Timeout },
+        ]);
+    }
+
+    #[test]
+    fn test_read_sysex_with_overall_timeout_in_message() {
+        let mut input   = StallingReader(vec![b"\xF0ab"]);
+        let mut options = ReadOptions::new(10);
+        options.timeout = Some(Duration::from_millis(10));
+
+        let mut events = vec![];
+        let more = read_sysex_with(&mut input, &options, &mut |e: SysExEvent| {
+            events.push(e.range());
+            true
+        });
+
+        assert!(!more.unwrap());
+        assert_eq!(events, vec![0..3]);
+    }
+
+    #[test]
+    fn test_read_sysex_stalled_without_timeouts() {
+        let mut input = StallingReader(vec![b"\xF0ab"]);
+
+        let result = read_sysex_with(&mut input, &ReadOptions::new(10), &mut |_: SysExEvent| true);
+
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn test_read_sysex_with_raw() {
+        let mut options = ReadOptions::new(3);
+        options.raw = true;
+
+        let input      = b"\xF0a\xF8b\xF7\xF0c\xF0d\xFEe\xF7\xF0fghi\xF7";
+        let mut input  = io::BufReader::with_capacity(2, &input[..]);
+        let mut events = vec![];
+
+        let more = read_sysex_with(&mut input, &options, &mut |e: SysExEvent| {
+            if let SysExEvent::Message { .. } = e {
+                events.push(e.into_owned());
+            }
+            true
+        });
+
+        assert!(more.unwrap());
+        assert_eq!(events, vec![
+            SysExEvent::Message {
+                pos: 0, len: 5,
+                msg: Cow::Borrowed(b"ab"),
+                raw: Some(Cow::Borrowed(b"\xF0a\xF8b\xF7")),
+            },
+            SysExEvent::Message {
+                pos: 7, len: 5,
+                msg: Cow::Borrowed(b"de"),
+                raw: Some(Cow::Borrowed(b"\xF0d\xFEe\xF7")),
+            },
+        ]);
+    }
+
+    #[test]
+    fn test_parser_save_restore_raw() {
+        let mut parser = SysExParser::new(10);
+        parser.set_keep_raw(true);
+        parser.feed(b"\xF0a\xF8", |_, _| true, |_, _, _| true);
+
+        let mut state = vec![];
+        parser.save(&mut state).unwrap();
+        let mut parser = SysExParser::restore(&mut &state[..]).unwrap();
+
+        let mut raw = vec![];
+        parser.feed_events(b"b\xF7", |e| {
+            if let SysExEvent::Message { raw: Some(r), .. } = e {
+                raw.extend_from_slice(&r);
+            }
+            true
+        });
+
+        assert_eq!(raw, b"\xF0a\xF8b\xF7");
+    }
+
+    #[test]
+    fn test_read_sysex_with_keep_incomplete() {
+        let mut options = ReadOptions::new(10);
+        options.keep_incomplete = true;
+        options.raw             = true;
+
+        let mut events = vec![];
+
+        let more = read_sysex_with(&mut &b"\xF0a\xF7\xF0bc\xF8d"[..], &options, &mut |e: SysExEvent| {
+            events.push(e.into_owned());
+            true
+        });
+
+        assert!(more.unwrap());
+        assert_eq!(events[2], SysExEvent::Incomplete {
+            pos: 3, len: 5,
+            msg: Cow::Borrowed(b"bcd"),
+            raw: Some(Cow::Borrowed(b"\xF0bc\xF8d")),
+        });
+        assert_eq!(events.len(), 3);
+    }
+
+    #[test]
+    fn test_read_sysex_with_keep_incomplete_overflow() {
+        let mut options = ReadOptions::new(2);
+        options.keep_incomplete = true;
+
+        let mut events = vec![];
+
+        let more = read_sysex_with(&mut &b"\xF0abc"[..], &options, &mut |e: SysExEvent| {
+            events.push(e.into_owned());
+            true
+        });
+
+        assert!(more.unwrap());
+        assert_eq!(events, vec![
+            SysExEvent::Error { pos: 0, len: 4, err: UnexpectedEof },
+        ]);
+    }
+
+    #[test]
+    fn test_read_sysex_with_keep_incomplete_strict() {
+        let mut options = ReadOptions::new(10);
+        options.keep_incomplete = true;
+        options.policy          = ReadPolicy::Strict;
+
+        let more = read_sysex_with(&mut &b"\xF0ab"[..], &options, &mut |_: SysExEvent| true);
+
+        assert!(!more.unwrap());
+    }
+
+    #[test]
+    fn test_read_sysex_with_skip_duplicates() {
+        let mut options = ReadOptions::new(10);
+        options.skip_duplicates = true;
+        options.policy          = ReadPolicy::Strict;
+
+        let input      = b"\xF0a\xF7\xF0b\xF7\xF0a\xF8\xF7\xF0ab\xF7\xF0b\xF7";
+        let mut input  = io::BufReader::with_capacity(3, &input[..]);
+        let mut events = vec![];
+
+        let more = read_sysex_with(&mut input, &options, &mut |e: SysExEvent| {
+            events.push(e.into_owned());
+            true
+        });
+
+        assert!(more.unwrap());
+        assert_eq!(events, vec![
+            SysExEvent::Message  { pos:  0, len: 3, msg: Cow::Borrowed(b"a"),  raw: None },
+            SysExEvent::Message  { pos:  3, len: 3, msg: Cow::Borrowed(b"b"),  raw: None },
+            SysExEvent::RealTime { pos:  8, byte: 0xF8 },
+            SysExEvent::Error    { pos:  6, len: 4, err: Duplicate },
+            SysExEvent::Message  { pos: 10, len: 4, msg: Cow::Borrowed(b"ab"), raw: None },
+            SysExEvent::Error    { pos: 14, len: 3, err: Duplicate },
+        ]);
+    }
+
+    #[test]
+    fn test_read_sysex_with_memory_limit() {
+        let input = (0..40u8)
+            .flat_map(|i| vec![0xF0, i, i, i, i, i, i, i, i, 0xF7])
+            .collect::<Vec<_>>();
+
+        let mut options = ReadOptions::new(None);
+        options.skip_duplicates = true;
+
+        let mut count = 0;
+        options.memory_limit = Some(10_000);
+        let more = read_sysex_with(&mut &input[..], &options, &mut |_: SysExEvent| {
+            count += 1;
+            true
+        });
+        assert!(more.unwrap());
+        assert_eq!(count, 40);
+
+        let mut count = 0;
+        options.memory_limit = Some(200);
+        let result = read_sysex_with(&mut io::BufReader::with_capacity(10, &input[..]), &options, &mut |_: SysExEvent| {
+            count += 1;
+            true
+        });
+        let err = result.unwrap_err();
+        assert!(err.is_memory_limit());
+        assert!(count < 40);
+    }
+
+    #[test]
+    fn test_read_sysex_with_ignore_edge_junk() {
+        let mut options = ReadOptions::new(10);
+        options.ignore_edge_junk = true;
+
+        let mut input  = io::BufReader::with_capacity(3, &b"\x90\x40\xF0a\xF7b\xF0c\xF7\x80\x40"[..]);
+        let mut events = vec![];
+
+        let more = read_sysex_with(&mut input, &options, &mut |e: SysExEvent| {
+            events.push(e.into_owned());
+            true
+        });
+
+        assert!(more.unwrap());
+        assert_eq!(events, vec![
+            SysExEvent::Message { pos: 2, len: 3, msg: Cow::Borrowed(b"a"), raw: None },
+            SysExEvent::Error   { pos: 5, len: 1, err: NotSysEx },
+            SysExEvent::Message { pos: 6, len: 3, msg: Cow::Borrowed(b"c"), raw: None },
+        ]);
+    }
+
+    #[test]
+    fn test_read_sysex_with_ignore_edge_junk_truncated() {
+        let mut options = ReadOptions::new(10);
+        options.ignore_edge_junk = true;
+
+        let mut events = vec![];
+
+        let more = read_sysex_with(&mut &b"\xF0a\xF7\xF0b"[..], &options, &mut |e: SysExEvent| {
+            events.push(e.into_owned());
+            true
+        });
+
+        assert!(more.unwrap());
+        assert_eq!(events, vec![
+            SysExEvent::Message { pos: 0, len: 3, msg: Cow::Borrowed(b"a"), raw: None },
+            SysExEvent::Error   { pos: 3, len: 2, err: UnexpectedEof },
+        ]);
+    }
+
+    mod props {
+        use super::*;
+        use proptest::prelude::*;
+
+        // Arbitrary bytes, heavy in the bytes that matter to the parser
+        fn stream() -> impl Strategy<Value = Vec<u8>> {
+            let byte = prop_oneof![
+                any::<u8>(),
+                prop::sample::select(vec![SYSEX_START, SYSEX_END, 0xF8, 0x90, 0x00]),
+            ];
+            prop::collection::vec(byte, 0..512)
+        }
+
+        proptest! {
+            #[test]
+            fn prop_read_sysex_consumes_all(bytes in stream(), cap in 0usize..16) {
+                let last = ::std::cell::Cell::new(0);
+                let summary = read_sysex(
+                    &mut &bytes[..], cap,
+                    |pos, msg| {
+                        assert!(pos >= last.get() && msg.len() <= cap);
+                        last.set(pos);
+                        true
+                    },
+                    |pos, len, _| {
+                        assert!(pos >= last.get() && pos + len <= bytes.len());
+                        last.set(pos);
+                        true
+                    },
+                ).unwrap();
+
+                prop_assert!(summary.complete);
+                prop_assert_eq!(summary.bytes, bytes.len());
+                prop_assert!(summary.skipped_bytes <= summary.bytes);
+            }
+
+            #[test]
+            fn prop_scan_sysex_matches_read(bytes in stream()) {
+                differential::check_parser(&bytes);
+            }
+        }
+    }
+}
@@ -14,6 +14,9 @@
 // You should have received a copy of the GNU General Public License
 // along with a6-tools.  If not, see <http://www.gnu.org/licenses/>.
 
+#[cfg(not(feature = "std"))]
+use heap::*;
+
 // Distance to shift a BoolArray index to get the word index
 #[cfg(target_pointer_width = "32")]
 const WORD_INDEX_SHIFT: usize = 5;